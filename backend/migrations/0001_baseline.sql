@@ -0,0 +1,67 @@
+-- Tables the service was deployed against before migrations were tracked.
+-- Every statement is idempotent so existing databases adopt the history as is.
+
+CREATE TABLE IF NOT EXISTS rym_user (
+    id INT NOT NULL AUTO_INCREMENT,
+    username VARCHAR(64) NOT NULL,
+    email VARCHAR(128) NOT NULL,
+    password VARCHAR(128) NOT NULL,
+    session_id TEXT NULL,
+    genre_data TEXT NULL,
+    fresh_time INT NOT NULL DEFAULT 10,
+    PRIMARY KEY (id),
+    KEY idx_rym_user_username (username)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE IF NOT EXISTS album (
+    id INT NOT NULL AUTO_INCREMENT,
+    name VARCHAR(255) NOT NULL,
+    artist VARCHAR(255) NOT NULL,
+    cover VARCHAR(512) NOT NULL DEFAULT '',
+    media_url JSON NULL,
+    PRIMARY KEY (id),
+    KEY idx_album_artist (artist)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE IF NOT EXISTS album_detail (
+    id INT NOT NULL AUTO_INCREMENT,
+    album_id INT NOT NULL,
+    descriptors TEXT NULL,
+    released VARCHAR(64) NULL,
+    language VARCHAR(64) NULL,
+    rate VARCHAR(16) NULL,
+    PRIMARY KEY (id),
+    KEY idx_album_detail_album_id (album_id)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE IF NOT EXISTS album_genre (
+    id INT NOT NULL AUTO_INCREMENT,
+    album_id INT NOT NULL,
+    genre VARCHAR(128) NOT NULL,
+    genre_type VARCHAR(8) NOT NULL DEFAULT 'pri',
+    PRIMARY KEY (id),
+    KEY idx_album_genre_album_id (album_id),
+    KEY idx_album_genre_genre (genre)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE IF NOT EXISTS genres (
+    id INT NOT NULL AUTO_INCREMENT,
+    name VARCHAR(128) NOT NULL,
+    key_name VARCHAR(128) NOT NULL,
+    parents VARCHAR(255) NOT NULL DEFAULT '',
+    path VARCHAR(512) NOT NULL DEFAULT '',
+    PRIMARY KEY (id),
+    KEY idx_genres_key_name (key_name)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE IF NOT EXISTS user_album_log (
+    id INT NOT NULL AUTO_INCREMENT,
+    user_id VARCHAR(32) NOT NULL,
+    album_id VARCHAR(32) NOT NULL,
+    album_genre TEXT NULL,
+    click_count INT NOT NULL DEFAULT 0,
+    listen_count INT NOT NULL DEFAULT 0,
+    create_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    KEY idx_user_album_log_user (user_id, album_id)
+) DEFAULT CHARSET = utf8mb4;
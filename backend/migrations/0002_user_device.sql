@@ -0,0 +1,31 @@
+-- One row per browser a user has signed in from, replacing the comma-joined
+-- client ids in rym_user.session_id.
+
+CREATE TABLE IF NOT EXISTS user_device (
+    id INT NOT NULL AUTO_INCREMENT,
+    client_id VARCHAR(64) NOT NULL,
+    user_id INT NOT NULL,
+    label VARCHAR(64) NOT NULL DEFAULT '',
+    user_agent VARCHAR(255) NOT NULL DEFAULT '',
+    first_seen DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_user_device_client_id (client_id),
+    KEY idx_user_device_user_id (user_id)
+) DEFAULT CHARSET = utf8mb4;
+
+INSERT IGNORE INTO user_device (client_id, user_id)
+WITH RECURSIVE split (user_id, client_id, rest) AS (
+    SELECT id,
+        SUBSTRING_INDEX(session_id, ',', 1),
+        IF(LOCATE(',', session_id) > 0, SUBSTRING(session_id, LOCATE(',', session_id) + 1), '')
+    FROM rym_user
+    WHERE session_id IS NOT NULL AND session_id <> ''
+    UNION ALL
+    SELECT user_id,
+        SUBSTRING_INDEX(rest, ',', 1),
+        IF(LOCATE(',', rest) > 0, SUBSTRING(rest, LOCATE(',', rest) + 1), '')
+    FROM split
+    WHERE rest <> ''
+)
+SELECT client_id, user_id FROM split WHERE client_id <> '';
@@ -0,0 +1,16 @@
+-- Users with role 'admin' may edit the catalog through /api/v1/admin.
+-- Promote an account with: UPDATE rym_user SET role = 'admin' WHERE username = '...';
+
+ALTER TABLE rym_user ADD COLUMN role VARCHAR(16) NOT NULL DEFAULT 'user';
+
+CREATE TABLE IF NOT EXISTS admin_audit_log (
+    id INT NOT NULL AUTO_INCREMENT,
+    user_id INT NOT NULL,
+    action VARCHAR(32) NOT NULL,
+    target_type VARCHAR(32) NOT NULL,
+    target_id INT NOT NULL,
+    detail JSON NULL,
+    create_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    KEY idx_admin_audit_log_target (target_type, target_id)
+) DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,4 @@
+-- Stable identifier supplied by catalog imports, used to upsert albums.
+
+ALTER TABLE album ADD COLUMN external_key VARCHAR(128) NULL;
+ALTER TABLE album ADD UNIQUE KEY uk_album_external_key (external_key);
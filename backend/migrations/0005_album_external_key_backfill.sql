@@ -0,0 +1,3 @@
+-- Give every album a key so exports can be imported back into any database.
+
+UPDATE album SET external_key = CONCAT('rym-', id) WHERE external_key IS NULL;
@@ -0,0 +1,5 @@
+-- Streaming providers a user listens on, best first, as comma separated
+-- provider keys (see PROVIDERS in src/media.rs).
+ALTER TABLE rym_user ADD COLUMN preferred_providers VARCHAR(255) NOT NULL DEFAULT '';
+-- Home grid opens the preferred provider instead of the album page.
+ALTER TABLE rym_user ADD COLUMN open_direct TINYINT(1) NOT NULL DEFAULT 0;
@@ -0,0 +1,18 @@
+-- Last result of the dead link checker for each cover and media link.
+CREATE TABLE IF NOT EXISTS link_status (
+    id INT NOT NULL AUTO_INCREMENT,
+    album_id INT NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    provider VARCHAR(32) NOT NULL DEFAULT '',
+    url VARCHAR(1024) NOT NULL,
+    state VARCHAR(16) NOT NULL,
+    http_status SMALLINT NULL,
+    error VARCHAR(255) NULL,
+    checked_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    KEY idx_link_status_album (album_id),
+    KEY idx_link_status_state (state)
+) DEFAULT CHARSET = utf8mb4;
+
+-- Albums whose cover is gone are left out of /today.
+ALTER TABLE album ADD COLUMN cover_dead TINYINT(1) NOT NULL DEFAULT 0;
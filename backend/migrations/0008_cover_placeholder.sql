@@ -0,0 +1,3 @@
+-- Painted while the cover loads; filled in by the cover proxy.
+ALTER TABLE album ADD COLUMN cover_color CHAR(7) NULL;
+ALTER TABLE album ADD COLUMN cover_blurhash VARCHAR(64) NULL;
@@ -0,0 +1,17 @@
+-- Email verification and password reset. Only the SHA3-256 of a token is
+-- stored; a token is spent by setting used_at.
+
+ALTER TABLE rym_user ADD COLUMN email_verified TINYINT(1) NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS account_token (
+    id INT NOT NULL AUTO_INCREMENT,
+    user_id INT NOT NULL,
+    purpose VARCHAR(16) NOT NULL,
+    token_hash CHAR(64) NOT NULL,
+    expires_at DATETIME NOT NULL,
+    used_at DATETIME NULL,
+    create_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_account_token_hash (token_hash),
+    KEY idx_account_token_user (user_id, purpose)
+) DEFAULT CHARSET = utf8mb4;
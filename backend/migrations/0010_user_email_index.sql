@@ -0,0 +1,4 @@
+-- Registration checks names and addresses separately, and password reset
+-- looks accounts up by address.
+
+ALTER TABLE rym_user ADD KEY idx_rym_user_email (email);
@@ -0,0 +1,41 @@
+-- Background jobs: their run history, and the daily listening aggregates the
+-- rollup job writes.
+
+CREATE TABLE IF NOT EXISTS job_run (
+    id INT NOT NULL AUTO_INCREMENT,
+    job VARCHAR(32) NOT NULL,
+    -- 'schedule' or 'manual'
+    triggered_by VARCHAR(16) NOT NULL,
+    -- 'running', 'ok' or 'failed'
+    status VARCHAR(16) NOT NULL DEFAULT 'running',
+    detail TEXT NULL,
+    started_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at DATETIME NULL,
+    PRIMARY KEY (id),
+    KEY idx_job_run_job (job, started_at)
+) DEFAULT CHARSET = utf8mb4;
+
+-- user_album_log keeps running totals per user and album; each rollup adds
+-- what they grew by since the last one to the day it runs.
+CREATE TABLE IF NOT EXISTS user_album_daily (
+    day DATE NOT NULL,
+    user_id VARCHAR(32) NOT NULL,
+    album_id VARCHAR(32) NOT NULL,
+    click_count INT NOT NULL DEFAULT 0,
+    listen_count INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, user_id, album_id),
+    KEY idx_user_album_daily_user (user_id, day)
+) DEFAULT CHARSET = utf8mb4;
+
+ALTER TABLE user_album_log
+    ADD COLUMN rolled_click_count INT NOT NULL DEFAULT 0,
+    ADD COLUMN rolled_listen_count INT NOT NULL DEFAULT 0;
+
+-- history so far counts on the day of the first visit, the best we know
+INSERT INTO user_album_daily (day, user_id, album_id, click_count, listen_count)
+SELECT DATE(create_time), user_id, album_id, SUM(click_count), SUM(listen_count)
+FROM user_album_log
+GROUP BY DATE(create_time), user_id, album_id;
+
+UPDATE user_album_log
+SET rolled_click_count = click_count, rolled_listen_count = listen_count;
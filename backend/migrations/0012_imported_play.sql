@@ -0,0 +1,12 @@
+-- Plays already counted by a listening history import, so uploading an
+-- export again, or one that overlaps an earlier one, adds nothing twice.
+-- play_hash is the SHA3-256 of the play's artist, album, track and time, or
+-- of the file and the play's position in it when the export has no time.
+
+CREATE TABLE IF NOT EXISTS imported_play (
+    user_id INT NOT NULL,
+    play_hash CHAR(64) NOT NULL,
+    album_id INT NOT NULL,
+    create_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, play_hash)
+) DEFAULT CHARSET = utf8mb4;
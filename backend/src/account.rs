@@ -0,0 +1,723 @@
+use axum::{http::StatusCode, response::IntoResponse, response::Response, Extension, Json};
+use axum_sessions::{
+    async_session::Session,
+    extractors::{ReadableSession, WritableSession},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use serde::Deserialize;
+use sha3::{Digest, Sha3_256};
+use sqlx::mysql::MySqlPool;
+use sqlx::Row;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    device,
+    mailer::{self, Mail, Mailer},
+    rate_limit::ClientIp,
+    settings::MailSettings,
+    validation, MyShared,
+};
+
+const VERIFY: &str = "verify";
+const RESET: &str = "reset";
+
+/// The configured mailer and what the account mails need to know.
+pub struct AccountMail {
+    mailer: Box<dyn Mailer>,
+    settings: MailSettings,
+}
+
+impl AccountMail {
+    pub fn new(settings: &MailSettings) -> Result<Self, String> {
+        Ok(AccountMail {
+            mailer: mailer::from_settings(settings)?,
+            settings: settings.clone(),
+        })
+    }
+
+    fn link(&self, page: &str, token: &str) -> String {
+        format!(
+            "{}/{page}?token={token}",
+            self.settings.public_url.trim_end_matches('/')
+        )
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TokenPayload {
+    /// From the link in the mail
+    token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct ResetPassword {
+    /// From the link in the mail
+    token: String,
+    #[validate(custom = "common::validation::password")]
+    password: String,
+    #[validate(must_match(other = "password", message = "Passwords do not match"))]
+    password_confirm: String,
+}
+
+/// Only the hash is stored, so a leaked table can't redeem anything. Mail
+/// clients sometimes wrap links, hence the trim.
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha3_256::digest(token.trim().as_bytes()))
+}
+
+/// 32 random bytes, URL safe so it goes into a link as is.
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// A new `purpose` token for `user_id`, replacing any unused one.
+async fn issue(
+    db: &MySqlPool,
+    user_id: i32,
+    purpose: &str,
+    ttl_secs: u64,
+) -> Result<String, sqlx::Error> {
+    let token = new_token();
+    sqlx::query(
+        "UPDATE account_token SET used_at = NOW() WHERE user_id = ? AND purpose = ? AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(purpose)
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"INSERT INTO account_token (user_id, purpose, token_hash, expires_at)
+        VALUES (?, ?, ?, DATE_ADD(NOW(), INTERVAL ? SECOND))"#,
+    )
+    .bind(user_id)
+    .bind(purpose)
+    .bind(token_hash(&token))
+    .bind(ttl_secs)
+    .execute(db)
+    .await?;
+    Ok(token)
+}
+
+/// Spend a token. The user it was issued to, or `None` when it is unknown,
+/// expired or already used.
+async fn consume(db: &MySqlPool, token: &str, purpose: &str) -> Result<Option<i32>, sqlx::Error> {
+    let hash = token_hash(token);
+    // the conditional update is what makes a token single use under races
+    let res = sqlx::query(
+        r#"UPDATE account_token SET used_at = NOW() WHERE token_hash = ? AND purpose = ?
+        AND used_at IS NULL AND expires_at > NOW()"#,
+    )
+    .bind(&hash)
+    .bind(purpose)
+    .execute(db)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Ok(None);
+    }
+    let user_id: i32 = sqlx::query_scalar("SELECT user_id FROM account_token WHERE token_hash = ?")
+        .bind(&hash)
+        .fetch_one(db)
+        .await?;
+    Ok(Some(user_id))
+}
+
+/// Mail `email` a link that marks it verified.
+pub async fn send_verification(
+    state: &MyShared,
+    user_id: i32,
+    username: &str,
+    email: &str,
+) -> Result<(), String> {
+    let hours = state.mail.settings.verify_token_hours;
+    let token = issue(&state.db, user_id, VERIFY, hours * 3600)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mail = Mail {
+        to: email.to_string(),
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Hi {username},\n\nConfirm this address for your Random Your Music account:\n\n{}\n\n\
+            The link expires in {hours} hours.",
+            state.mail.link("verify_email", &token)
+        ),
+    };
+    state.mail.mailer.send(&mail).await
+}
+
+async fn send_reset(state: &MyShared, user_id: i32, username: &str, email: &str) -> Result<(), String> {
+    let minutes = state.mail.settings.reset_token_minutes;
+    let token = issue(&state.db, user_id, RESET, minutes * 60)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mail = Mail {
+        to: email.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {username},\n\nSomeone asked to reset the password of your Random Your Music \
+            account. Choose a new one here:\n\n{}\n\nThe link expires in {minutes} minutes and \
+            works once. If it wasn't you, ignore this mail.",
+            state.mail.link("reset_password", &token)
+        ),
+    };
+    state.mail.mailer.send(&mail).await
+}
+
+fn reply(status: StatusCode, msg: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let resp = if status == StatusCode::OK {
+        serde_json::json!({
+            "code": 200,
+            "msg": msg,
+            "data": {}
+        })
+    } else {
+        serde_json::json!({
+            "code": status.as_u16(),
+            "msg": msg
+        })
+    };
+    (status, Json(resp))
+}
+
+#[utoipa::path(
+    post,
+    path = "/verify_email",
+    request_body = TokenPayload,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Unknown, expired or used token", body = EmptyEnvelope)
+    ),
+    security(("csrf" = []))
+)]
+pub async fn verify_email(
+    Extension(state): Extension<MyShared>,
+    Json(payload): Json<TokenPayload>,
+) -> impl IntoResponse {
+    match consume(&state.db, &payload.token, VERIFY).await {
+        Ok(Some(user_id)) => {
+            let res = sqlx::query("UPDATE rym_user SET email_verified = 1 WHERE id = ?")
+                .bind(user_id)
+                .execute(&state.db)
+                .await;
+            match res {
+                Ok(_) => reply(StatusCode::OK, "email verified"),
+                Err(e) => {
+                    tracing::error!("verify email error: {e}");
+                    reply(StatusCode::BAD_REQUEST, "failed")
+                }
+            }
+        }
+        Ok(None) => reply(StatusCode::BAD_REQUEST, "this link is invalid or has expired"),
+        Err(e) => {
+            tracing::error!("verify email error: {e}");
+            reply(StatusCode::BAD_REQUEST, "failed")
+        }
+    }
+}
+
+/// Send the signed in user another verification mail.
+#[utoipa::path(
+    post,
+    path = "/verify_email/resend",
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Not logged in, already verified or the mail failed", body = EmptyEnvelope),
+        (status = 429, description = "Rate limited, see Retry-After", body = EmptyEnvelope)
+    ),
+    security(("session" = [], "csrf" = []))
+)]
+pub async fn resend_verification(
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    ClientIp(ip): ClientIp,
+) -> Response {
+    let user_id = device::session_user_id(&session, &state.db).await;
+    if user_id == 0 {
+        return reply(StatusCode::BAD_REQUEST, "you are not logged in").into_response();
+    }
+    let limit = state.limiter.mail(ip).await;
+    if !limit.allowed() {
+        return limit.reject();
+    }
+    let user = sqlx::query("SELECT username, email, email_verified FROM rym_user WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await;
+    let resp = match user {
+        Ok(row) if row.get::<bool, _>("email_verified") => {
+            reply(StatusCode::BAD_REQUEST, "your email is already verified")
+        }
+        Ok(row) => {
+            let username: String = row.get("username");
+            let email: String = row.get("email");
+            match send_verification(&state, user_id, &username, &email).await {
+                Ok(()) => reply(StatusCode::OK, "verification mail sent"),
+                Err(e) => {
+                    tracing::error!("verification mail error: {e}");
+                    reply(StatusCode::BAD_REQUEST, "could not send the mail")
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!("resend verification error: {e}");
+            reply(StatusCode::BAD_REQUEST, "failed")
+        }
+    };
+    limit.respond(resp)
+}
+
+/// Mail a reset link to every account that verified `email`; an address
+/// nobody confirmed could belong to anyone. Answers the same whether or not
+/// there is one, so it can't be used to find accounts.
+#[utoipa::path(
+    post,
+    path = "/password/forgot",
+    request_body = ForgotPassword,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 429, description = "Rate limited, see Retry-After", body = EmptyEnvelope)
+    ),
+    security(("csrf" = []))
+)]
+pub async fn forgot_password(
+    Extension(state): Extension<MyShared>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<ForgotPassword>,
+) -> Response {
+    let limit = state.limiter.mail(ip).await;
+    if !limit.allowed() {
+        return limit.reject();
+    }
+    let email = payload.email.trim().to_string();
+    // sent in the background so the response time doesn't tell either
+    tokio::spawn(async move {
+        let users = sqlx::query(
+            "SELECT id, username, email FROM rym_user WHERE email = ? AND email_verified = 1",
+        )
+            .bind(&email)
+            .fetch_all(&state.db)
+            .await
+            .unwrap_or_default();
+        for row in users {
+            let (user_id, username, email): (i32, String, String) =
+                (row.get("id"), row.get("username"), row.get("email"));
+            if let Err(e) = send_reset(&state, user_id, &username, &email).await {
+                tracing::error!("reset mail error: {e}");
+            }
+        }
+    });
+    limit.respond(reply(
+        StatusCode::OK,
+        "if that address belongs to an account, a reset link is on its way",
+    ))
+}
+
+/// Set a new password with a reset token. Every device of the account is
+/// signed out.
+#[utoipa::path(
+    post,
+    path = "/password/reset",
+    request_body = ResetPassword,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Invalid password, or unknown, expired or used token", body = Invalid)
+    ),
+    security(("csrf" = []))
+)]
+pub async fn reset_password(
+    Extension(state): Extension<MyShared>,
+    Json(payload): Json<ResetPassword>,
+) -> impl IntoResponse {
+    if let Err(e) = payload.validate() {
+        return validation::invalid(validation::field_errors(&e));
+    }
+    let user_id = match consume(&state.db, &payload.token, RESET).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return reply(StatusCode::BAD_REQUEST, "this link is invalid or has expired"),
+        Err(e) => {
+            tracing::error!("reset password error: {e}");
+            return reply(StatusCode::BAD_REQUEST, "failed");
+        }
+    };
+    let password = crate::generate_password(&payload.password).await;
+    let res = sqlx::query("UPDATE rym_user SET password = ? WHERE id = ?")
+        .bind(password)
+        .bind(user_id)
+        .execute(&state.db)
+        .await;
+    if let Err(e) = res {
+        tracing::error!("reset password error: {e}");
+        return reply(StatusCode::BAD_REQUEST, "failed");
+    }
+    sign_out_devices(&state.db, &state.redis, user_id, "").await;
+    if let Ok(username) = sqlx::query_scalar::<_, String>("SELECT username FROM rym_user WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await
+    {
+        state.limiter.login_succeeded(&username).await;
+    }
+    reply(StatusCode::OK, "password changed, sign in with the new one")
+}
+
+/// Forget every device of `user_id` but `keep`, which ends their sessions.
+pub async fn sign_out_devices(db: &MySqlPool, redis: &redis::Client, user_id: i32, keep: &str) {
+    let client_ids: Vec<String> =
+        sqlx::query_scalar("SELECT client_id FROM user_device WHERE user_id = ? AND client_id <> ?")
+            .bind(user_id)
+            .bind(keep)
+            .fetch_all(db)
+            .await
+            .unwrap_or_default();
+    if let Err(e) = sqlx::query("DELETE FROM user_device WHERE user_id = ? AND client_id <> ?")
+        .bind(user_id)
+        .bind(keep)
+        .execute(db)
+        .await
+    {
+        tracing::error!("sign out devices error: {e}");
+    }
+    for client_id in client_ids {
+        device::clear_feed_cache(redis, &client_id).await;
+    }
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct ChangePassword {
+    current_password: String,
+    #[validate(custom = "common::validation::password")]
+    password: String,
+    #[validate(must_match(other = "password", message = "Passwords do not match"))]
+    password_confirm: String,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct ChangeEmail {
+    current_password: String,
+    #[validate(custom = "common::validation::email")]
+    email: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeleteAccount {
+    current_password: String,
+}
+
+/// The signed in user's id and name once `password` is confirmed as theirs.
+/// Wrong guesses count against the login lockout, so these endpoints can't
+/// be used to brute force a password from an open session.
+async fn confirm_password(
+    state: &MyShared,
+    session: &Session,
+    ip: std::net::IpAddr,
+    password: &str,
+) -> Result<(i32, String), Response> {
+    let user_id = device::session_user_id(session, &state.db).await;
+    if user_id == 0 {
+        return Err(reply(StatusCode::BAD_REQUEST, "you are not logged in").into_response());
+    }
+    let row = sqlx::query("SELECT username, password FROM rym_user WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("confirm password error: {e}");
+            reply(StatusCode::BAD_REQUEST, "failed").into_response()
+        })?;
+    let username: String = row.get("username");
+    let limit = state.limiter.login(ip, &username).await;
+    if !limit.allowed() {
+        return Err(limit.reject());
+    }
+    if crate::generate_password(password).await != row.get::<String, _>("password") {
+        state.limiter.login_failed(&username).await;
+        let mut errors = validation::FieldErrors::new();
+        errors.insert(
+            "current_password".to_string(),
+            vec!["Current password is wrong".to_string()],
+        );
+        return Err(limit.respond(validation::invalid(errors)));
+    }
+    state.limiter.login_succeeded(&username).await;
+    Ok((user_id, username))
+}
+
+/// Change the password. Other devices are signed out, this one stays.
+#[utoipa::path(
+    post,
+    path = "/account/password",
+    request_body = ChangePassword,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Not logged in, wrong current password or invalid new one", body = Invalid),
+        (status = 429, description = "Too many wrong passwords, see Retry-After", body = EmptyEnvelope)
+    ),
+    security(("session" = [], "csrf" = []))
+)]
+pub async fn change_password(
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<ChangePassword>,
+) -> Response {
+    if let Err(e) = payload.validate() {
+        return validation::invalid(validation::field_errors(&e)).into_response();
+    }
+    let (user_id, _) =
+        match confirm_password(&state, &session, ip, &payload.current_password).await {
+            Ok(user) => user,
+            Err(resp) => return resp,
+        };
+    let password = crate::generate_password(&payload.password).await;
+    let res = sqlx::query("UPDATE rym_user SET password = ? WHERE id = ?")
+        .bind(password)
+        .bind(user_id)
+        .execute(&state.db)
+        .await;
+    if let Err(e) = res {
+        tracing::error!("change password error: {e}");
+        return reply(StatusCode::BAD_REQUEST, "failed").into_response();
+    }
+    let current: String = session.get("client_id").unwrap_or_default();
+    sign_out_devices(&state.db, &state.redis, user_id, &current).await;
+    reply(StatusCode::OK, "password changed, other devices were signed out").into_response()
+}
+
+/// Change the email address. The new one is unverified until its link is
+/// followed.
+#[utoipa::path(
+    post,
+    path = "/account/email",
+    request_body = ChangeEmail,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Not logged in, wrong password, or invalid or taken address", body = Invalid),
+        (status = 429, description = "Too many wrong passwords, see Retry-After", body = EmptyEnvelope)
+    ),
+    security(("session" = [], "csrf" = []))
+)]
+pub async fn change_email(
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<ChangeEmail>,
+) -> Response {
+    if let Err(e) = payload.validate() {
+        return validation::invalid(validation::field_errors(&e)).into_response();
+    }
+    let (user_id, username) =
+        match confirm_password(&state, &session, ip, &payload.current_password).await {
+            Ok(user) => user,
+            Err(resp) => return resp,
+        };
+    let taken = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM rym_user WHERE email = ? AND id <> ?",
+    )
+    .bind(&payload.email)
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await;
+    match taken {
+        Ok(0) => {}
+        Ok(_) => {
+            let mut errors = validation::FieldErrors::new();
+            errors.insert(
+                "email".to_string(),
+                vec!["Email is already registered".to_string()],
+            );
+            return validation::invalid(errors).into_response();
+        }
+        Err(e) => {
+            tracing::error!("change email error: {e}");
+            return reply(StatusCode::BAD_REQUEST, "failed").into_response();
+        }
+    }
+    let res = sqlx::query("UPDATE rym_user SET email = ?, email_verified = 0 WHERE id = ?")
+        .bind(&payload.email)
+        .bind(user_id)
+        .execute(&state.db)
+        .await;
+    if let Err(e) = res {
+        tracing::error!("change email error: {e}");
+        return reply(StatusCode::BAD_REQUEST, "failed").into_response();
+    }
+    // links mailed to the old address must not verify the new one
+    if let Err(e) = sqlx::query(
+        "UPDATE account_token SET used_at = NOW() WHERE user_id = ? AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(&state.db)
+    .await
+    {
+        tracing::error!("change email error: {e}");
+    }
+    match send_verification(&state, user_id, &username, &payload.email).await {
+        Ok(()) => reply(StatusCode::OK, "email changed, check your inbox to verify it"),
+        Err(e) => {
+            tracing::error!("verification mail error: {e}");
+            reply(StatusCode::OK, "email changed, but the verification mail could not be sent")
+        }
+    }
+    .into_response()
+}
+
+/// Delete the account with its history, devices and tokens, and end its
+/// sessions. Download `/user_album_log/export` first to keep the history.
+#[utoipa::path(
+    post,
+    path = "/account/delete",
+    request_body = DeleteAccount,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Not logged in or wrong password", body = Invalid),
+        (status = 429, description = "Too many wrong passwords, see Retry-After", body = EmptyEnvelope)
+    ),
+    security(("session" = [], "csrf" = []))
+)]
+pub async fn delete_account(
+    Extension(state): Extension<MyShared>,
+    mut session: WritableSession,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<DeleteAccount>,
+) -> Response {
+    let (user_id, username) =
+        match confirm_password(&state, &session, ip, &payload.current_password).await {
+            Ok(user) => user,
+            Err(resp) => return resp,
+        };
+    match delete_user(&state.db, user_id).await {
+        Ok(client_ids) => {
+            for client_id in client_ids {
+                device::clear_feed_cache(&state.redis, &client_id).await;
+            }
+            state.limiter.login_succeeded(&username).await;
+            session.destroy();
+            reply(StatusCode::OK, "account deleted").into_response()
+        }
+        Err(e) => {
+            tracing::error!("delete account error: {e}");
+            reply(StatusCode::BAD_REQUEST, "failed").into_response()
+        }
+    }
+}
+
+/// Remove `user_id` and everything that hangs off it in one transaction.
+/// Returns the client ids it was signed in on, from `user_device` and the
+/// older comma joined `rym_user.session_id`.
+async fn delete_user(db: &MySqlPool, user_id: i32) -> Result<Vec<String>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let mut client_ids: Vec<String> =
+        sqlx::query_scalar("SELECT client_id FROM user_device WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_all(&mut tx)
+            .await?;
+    let legacy: Option<String> =
+        sqlx::query_scalar("SELECT session_id FROM rym_user WHERE id = ? FOR UPDATE")
+            .bind(user_id)
+            .fetch_one(&mut tx)
+            .await?;
+    for client_id in legacy.unwrap_or_default().split(',') {
+        if !client_id.is_empty() && !client_ids.iter().any(|c| c == client_id) {
+            client_ids.push(client_id.to_string());
+        }
+    }
+    for sql in [
+        "DELETE FROM user_album_log WHERE user_id = ?",
+        "DELETE FROM user_album_daily WHERE user_id = ?",
+        "DELETE FROM imported_play WHERE user_id = ?",
+        "DELETE FROM user_device WHERE user_id = ?",
+        "DELETE FROM account_token WHERE user_id = ?",
+        "DELETE FROM rym_user WHERE id = ?",
+    ] {
+        sqlx::query(sql).bind(user_id).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(client_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::MailSettings;
+    use sqlx::mysql::MySqlPoolOptions;
+
+    #[test]
+    fn tokens_are_random_and_url_safe() {
+        let token = new_token();
+        assert_eq!(token.len(), 43);
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')));
+        assert_ne!(token, new_token());
+    }
+
+    #[test]
+    fn token_hash_ignores_wrapping_whitespace() {
+        let hash = token_hash("abc");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, token_hash(" abc\n"));
+        assert_ne!(hash, token_hash("abd"));
+        assert_ne!(hash, "abc");
+    }
+
+    #[test]
+    fn links_point_at_the_frontend_page() {
+        let mail = AccountMail::new(&MailSettings {
+            public_url: "https://music.example.com/".to_string(),
+            ..MailSettings::default()
+        })
+        .unwrap();
+        assert_eq!(
+            mail.link("reset_password", "t0k"),
+            "https://music.example.com/reset_password?token=t0k"
+        );
+    }
+
+    /// A migrated database at `TEST_DATABASE_URL` with a fresh user in it.
+    async fn user() -> (MySqlPool, i32) {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL");
+        let db = MySqlPoolOptions::new().connect(&url).await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let username = format!("token-test-{}", new_token()[..8].to_lowercase());
+        let id = sqlx::query("INSERT INTO rym_user (username, email, password) VALUES (?, ?, '')")
+            .bind(&username)
+            .bind(format!("{username}@example.invalid"))
+            .execute(&db)
+            .await
+            .unwrap()
+            .last_insert_id() as i32;
+        (db, id)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL, see TEST_DATABASE_URL"]
+    async fn a_token_redeems_once_and_for_its_purpose() {
+        let (db, user_id) = user().await;
+        let token = issue(&db, user_id, RESET, 600).await.unwrap();
+        assert_eq!(consume(&db, &token, VERIFY).await.unwrap(), None);
+        assert_eq!(consume(&db, &token, RESET).await.unwrap(), Some(user_id));
+        assert_eq!(consume(&db, &token, RESET).await.unwrap(), None);
+        delete_user(&db, user_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL, see TEST_DATABASE_URL"]
+    async fn expired_and_replaced_tokens_are_refused() {
+        let (db, user_id) = user().await;
+        let expired = issue(&db, user_id, VERIFY, 0).await.unwrap();
+        assert_eq!(consume(&db, &expired, VERIFY).await.unwrap(), None);
+        let first = issue(&db, user_id, VERIFY, 600).await.unwrap();
+        let second = issue(&db, user_id, VERIFY, 600).await.unwrap();
+        assert_eq!(consume(&db, &first, VERIFY).await.unwrap(), None);
+        assert_eq!(consume(&db, &second, VERIFY).await.unwrap(), Some(user_id));
+        delete_user(&db, user_id).await.unwrap();
+    }
+}
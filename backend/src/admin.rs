@@ -0,0 +1,907 @@
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_sessions::extractors::ReadableSession;
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::{MySql, MySqlPool};
+use sqlx::{MySqlConnection, Row};
+use std::collections::HashMap;
+
+use crate::{device, jobs, media, AlbumGenre, MyShared, Pagination};
+
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_USER: &str = "user";
+
+type ApiResponse = (StatusCode, Json<serde_json::Value>);
+
+fn success(data: serde_json::Value) -> ApiResponse {
+    let resp = serde_json::json!({
+        "code": 200,
+        "msg": "success",
+        "data": data
+    });
+    (StatusCode::OK, Json(resp))
+}
+
+fn failed(msg: &str) -> ApiResponse {
+    let resp = serde_json::json!({
+        "code": 400,
+        "msg": msg
+    });
+    (StatusCode::BAD_REQUEST, Json(resp))
+}
+
+/// The admin's user id, or the response to send back to anyone else.
+async fn require_admin(session: &ReadableSession, db: &MySqlPool) -> Result<i32, ApiResponse> {
+    let user_id = device::session_user_id(session, db).await;
+    let role: Option<String> = sqlx::query_scalar("SELECT role FROM rym_user WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .unwrap_or_default();
+    match role {
+        Some(role) if role == ROLE_ADMIN => Ok(user_id),
+        _ => {
+            let resp = serde_json::json!({
+                "code": 403,
+                "msg": "admin only"
+            });
+            Err((StatusCode::FORBIDDEN, Json(resp)))
+        }
+    }
+}
+
+async fn audit(
+    con: &mut MySqlConnection,
+    user_id: i32,
+    action: &str,
+    target_type: &str,
+    target_id: i32,
+    detail: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"INSERT INTO admin_audit_log (user_id, action, target_type, target_id, detail)
+        VALUES (?, ?, ?, ?, ?)"#,
+    )
+    .bind(user_id)
+    .bind(action)
+    .bind(target_type)
+    .bind(target_id)
+    .bind(sqlx::types::Json(detail))
+    .execute(con)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AlbumPayload {
+    name: String,
+    artist: String,
+    #[serde(default)]
+    cover: String,
+    #[serde(default)]
+    media_url: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AlbumDetailPayload {
+    descriptors: String,
+    released: String,
+    language: String,
+    rate: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AlbumGenresPayload {
+    genres: Vec<AlbumGenre>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MergePayload {
+    into: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GenrePayload {
+    name: String,
+    key_name: String,
+    parent_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct GenreNode {
+    id: i32,
+    name: String,
+    key_name: String,
+    parents: String,
+    path: String,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    id: i32,
+    user_id: i32,
+    action: String,
+    target_type: String,
+    target_id: i32,
+    detail: Option<sqlx::types::Json<serde_json::Value>>,
+    create_time: String,
+}
+
+impl AlbumPayload {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() || self.artist.trim().is_empty() {
+            return Err("name and artist are required".to_string());
+        }
+        media::validate(&self.media_url)
+    }
+}
+
+async fn album_exists(con: &mut MySqlConnection, album_id: i32) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("SELECT id FROM album WHERE id = ?")
+        .bind(album_id)
+        .fetch_optional(con)
+        .await?;
+    Ok(row.is_some())
+}
+
+pub async fn create_album(
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    Json(payload): Json<AlbumPayload>,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    if let Err(msg) = payload.validate() {
+        return failed(&msg);
+    }
+    let res: Result<i32, sqlx::Error> = async {
+        let mut tx = state.db.begin().await?;
+        let id = sqlx::query("INSERT INTO album (name, artist, cover, media_url) VALUES (?, ?, ?, ?)")
+            .bind(payload.name.trim())
+            .bind(payload.artist.trim())
+            .bind(&payload.cover)
+            .bind(sqlx::types::Json(&payload.media_url))
+            .execute(&mut tx)
+            .await?
+            .last_insert_id() as i32;
+        sqlx::query("UPDATE album SET external_key = CONCAT('rym-', id) WHERE id = ?")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        audit(&mut tx, admin_id, "create", "album", id, serde_json::json!(payload)).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+    .await;
+    match res {
+        Ok(id) => success(serde_json::json!({ "id": id })),
+        Err(e) => {
+            tracing::error!("create album error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+pub async fn update_album(
+    Path(album_id): Path<i32>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    Json(payload): Json<AlbumPayload>,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    if let Err(msg) = payload.validate() {
+        return failed(&msg);
+    }
+    let res: Result<bool, sqlx::Error> = async {
+        let mut tx = state.db.begin().await?;
+        if !album_exists(&mut tx, album_id).await? {
+            return Ok(false);
+        }
+        sqlx::query("UPDATE album SET name = ?, artist = ?, cover = ?, media_url = ? WHERE id = ?")
+            .bind(payload.name.trim())
+            .bind(payload.artist.trim())
+            .bind(&payload.cover)
+            .bind(sqlx::types::Json(&payload.media_url))
+            .bind(album_id)
+            .execute(&mut tx)
+            .await?;
+        audit(&mut tx, admin_id, "update", "album", album_id, serde_json::json!(payload)).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+    .await;
+    match res {
+        Ok(true) => success(serde_json::json!({})),
+        Ok(false) => failed("album not found"),
+        Err(e) => {
+            tracing::error!("update album error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+pub async fn update_album_detail(
+    Path(album_id): Path<i32>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    Json(payload): Json<AlbumDetailPayload>,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let res: Result<bool, sqlx::Error> = async {
+        let mut tx = state.db.begin().await?;
+        if !album_exists(&mut tx, album_id).await? {
+            return Ok(false);
+        }
+        let updated = sqlx::query(
+            r#"UPDATE album_detail SET descriptors = ?, released = ?, language = ?, rate = ?
+            WHERE album_id = ?"#,
+        )
+        .bind(&payload.descriptors)
+        .bind(&payload.released)
+        .bind(&payload.language)
+        .bind(&payload.rate)
+        .bind(album_id)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+        let exists = updated > 0
+            || sqlx::query("SELECT id FROM album_detail WHERE album_id = ?")
+                .bind(album_id)
+                .fetch_optional(&mut tx)
+                .await?
+                .is_some();
+        if !exists {
+            sqlx::query(
+                r#"INSERT INTO album_detail (album_id, descriptors, released, language, rate)
+                VALUES (?, ?, ?, ?, ?)"#,
+            )
+            .bind(album_id)
+            .bind(&payload.descriptors)
+            .bind(&payload.released)
+            .bind(&payload.language)
+            .bind(&payload.rate)
+            .execute(&mut tx)
+            .await?;
+        }
+        audit(&mut tx, admin_id, "update", "album_detail", album_id, serde_json::json!(payload)).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+    .await;
+    match res {
+        Ok(true) => success(serde_json::json!({})),
+        Ok(false) => failed("album not found"),
+        Err(e) => {
+            tracing::error!("update album detail error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+pub async fn update_album_genres(
+    Path(album_id): Path<i32>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    Json(payload): Json<AlbumGenresPayload>,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    if payload
+        .genres
+        .iter()
+        .any(|g| g.genre_type != "pri" && g.genre_type != "sec")
+    {
+        return failed("genre_type must be pri or sec");
+    }
+    let res: Result<Result<(), String>, sqlx::Error> = async {
+        let mut tx = state.db.begin().await?;
+        if !album_exists(&mut tx, album_id).await? {
+            return Ok(Err("album not found".to_string()));
+        }
+        for g in &payload.genres {
+            let known = sqlx::query("SELECT id FROM genres WHERE name = ?")
+                .bind(&g.genre)
+                .fetch_optional(&mut tx)
+                .await?;
+            if known.is_none() {
+                return Ok(Err(format!("unknown genre {}", g.genre)));
+            }
+        }
+        sqlx::query("DELETE FROM album_genre WHERE album_id = ?")
+            .bind(album_id)
+            .execute(&mut tx)
+            .await?;
+        for g in &payload.genres {
+            sqlx::query("INSERT INTO album_genre (album_id, genre, genre_type) VALUES (?, ?, ?)")
+                .bind(album_id)
+                .bind(&g.genre)
+                .bind(&g.genre_type)
+                .execute(&mut tx)
+                .await?;
+        }
+        audit(&mut tx, admin_id, "update", "album_genre", album_id, serde_json::json!(payload)).await?;
+        tx.commit().await?;
+        Ok(Ok(()))
+    }
+    .await;
+    match res {
+        Ok(Ok(())) => success(serde_json::json!({})),
+        Ok(Err(msg)) => failed(&msg),
+        Err(e) => {
+            tracing::error!("update album genres error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+/// Fold `album_id` into `payload.into`: genres, media links, detail and
+/// listening history move over, then the duplicate is removed.
+pub async fn merge_album(
+    Path(album_id): Path<i32>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    Json(payload): Json<MergePayload>,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let into = payload.into;
+    if into == album_id {
+        return failed("can't merge an album into itself");
+    }
+    let res: Result<bool, sqlx::Error> = async {
+        let mut tx = state.db.begin().await?;
+        if !album_exists(&mut tx, album_id).await? || !album_exists(&mut tx, into).await? {
+            return Ok(false);
+        }
+        sqlx::query(
+            r#"INSERT INTO album_genre (album_id, genre, genre_type)
+            SELECT ?, s.genre, s.genre_type FROM album_genre s WHERE s.album_id = ?
+            AND s.genre NOT IN (SELECT genre FROM (SELECT genre FROM album_genre WHERE album_id = ?) t)"#,
+        )
+        .bind(into)
+        .bind(album_id)
+        .bind(into)
+        .execute(&mut tx)
+        .await?;
+        // links already on the surviving album win
+        sqlx::query(
+            r#"UPDATE album t JOIN album s ON s.id = ?
+            SET t.media_url = JSON_MERGE_PATCH(IFNULL(s.media_url, JSON_OBJECT()), IFNULL(t.media_url, JSON_OBJECT()))
+            WHERE t.id = ?"#,
+        )
+        .bind(album_id)
+        .bind(into)
+        .execute(&mut tx)
+        .await?;
+        let has_detail = sqlx::query("SELECT id FROM album_detail WHERE album_id = ?")
+            .bind(into)
+            .fetch_optional(&mut tx)
+            .await?
+            .is_some();
+        if !has_detail {
+            sqlx::query("UPDATE album_detail SET album_id = ? WHERE album_id = ?")
+                .bind(into)
+                .bind(album_id)
+                .execute(&mut tx)
+                .await?;
+        }
+        // user_album_log stores ids as strings
+        let (from_key, into_key) = (album_id.to_string(), into.to_string());
+        sqlx::query(
+            r#"UPDATE user_album_log t JOIN user_album_log s
+            ON t.user_id = s.user_id AND t.album_id = ? AND s.album_id = ?
+            SET t.click_count = t.click_count + s.click_count,
+                t.listen_count = t.listen_count + s.listen_count,
+                t.rolled_click_count = t.rolled_click_count + s.rolled_click_count,
+                t.rolled_listen_count = t.rolled_listen_count + s.rolled_listen_count"#,
+        )
+        .bind(&into_key)
+        .bind(&from_key)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            r#"DELETE s FROM user_album_log s JOIN user_album_log t
+            ON t.user_id = s.user_id AND t.album_id = ? AND s.album_id = ?"#,
+        )
+        .bind(&into_key)
+        .bind(&from_key)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("UPDATE user_album_log SET album_id = ? WHERE album_id = ?")
+            .bind(&into_key)
+            .bind(&from_key)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            r#"INSERT INTO user_album_daily (day, user_id, album_id, click_count, listen_count)
+            SELECT day, user_id, ?, click_count, listen_count FROM user_album_daily WHERE album_id = ?
+            ON DUPLICATE KEY UPDATE click_count = user_album_daily.click_count + VALUES(click_count),
+                listen_count = user_album_daily.listen_count + VALUES(listen_count)"#,
+        )
+        .bind(&into_key)
+        .bind(&from_key)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("DELETE FROM user_album_daily WHERE album_id = ?")
+            .bind(&from_key)
+            .execute(&mut tx)
+            .await?;
+        // imported plays stay counted, under the surviving album
+        sqlx::query("UPDATE imported_play SET album_id = ? WHERE album_id = ?")
+            .bind(into)
+            .bind(album_id)
+            .execute(&mut tx)
+            .await?;
+        for sql in [
+            // the next link check covers the merged links on `into`
+            "DELETE FROM link_status WHERE album_id = ?",
+            "DELETE FROM album_genre WHERE album_id = ?",
+            "DELETE FROM album_detail WHERE album_id = ?",
+            "DELETE FROM album WHERE id = ?",
+        ] {
+            sqlx::query(sql).bind(album_id).execute(&mut tx).await?;
+        }
+        audit(&mut tx, admin_id, "merge", "album", album_id, serde_json::json!(payload)).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+    .await;
+    match res {
+        Ok(true) => success(serde_json::json!({ "id": into })),
+        Ok(false) => failed("album not found"),
+        Err(e) => {
+            tracing::error!("merge album error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+pub async fn genre_tree(
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+) -> impl IntoResponse {
+    if let Err(resp) = require_admin(&session, &state.db).await {
+        return resp;
+    }
+    match sqlx::query_as::<MySql, GenreNode>(
+        "SELECT id, name, key_name, parents, path FROM genres ORDER BY path",
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(genres) => success(serde_json::json!({ "genres": genres })),
+        Err(e) => {
+            tracing::error!("genre tree error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+/// Path of a genre placed under `parent_id`, and the `parents` value that goes
+/// with it (the parent's own path, empty for top level genres).
+async fn genre_path(
+    con: &mut MySqlConnection,
+    parent_id: Option<i32>,
+    key_name: &str,
+) -> Result<Option<(String, String)>, sqlx::Error> {
+    match parent_id {
+        None => Ok(Some((String::new(), key_name.to_string()))),
+        Some(parent_id) => {
+            let row = sqlx::query("SELECT path FROM genres WHERE id = ?")
+                .bind(parent_id)
+                .fetch_optional(con)
+                .await?;
+            Ok(row.map(|row| {
+                let parents: String = row.get(0);
+                let path = format!("{parents}/{key_name}");
+                (parents, path)
+            }))
+        }
+    }
+}
+
+impl GenrePayload {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.name.trim().is_empty() {
+            return Err("name is required");
+        }
+        if self.key_name.is_empty()
+            || !self
+                .key_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err("key_name may only contain letters, digits, '-' and '_'");
+        }
+        Ok(())
+    }
+}
+
+pub async fn create_genre(
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    Json(payload): Json<GenrePayload>,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    if let Err(msg) = payload.validate() {
+        return failed(msg);
+    }
+    let res: Result<Result<i32, &str>, sqlx::Error> = async {
+        let mut tx = state.db.begin().await?;
+        let (parents, path) = match genre_path(&mut tx, payload.parent_id, &payload.key_name).await? {
+            Some(p) => p,
+            None => return Ok(Err("parent genre not found")),
+        };
+        let taken = sqlx::query("SELECT id FROM genres WHERE name = ? OR path = ?")
+            .bind(payload.name.trim())
+            .bind(&path)
+            .fetch_optional(&mut tx)
+            .await?;
+        if taken.is_some() {
+            return Ok(Err("genre already exists"));
+        }
+        let id = sqlx::query("INSERT INTO genres (name, key_name, parents, path) VALUES (?, ?, ?, ?)")
+            .bind(payload.name.trim())
+            .bind(&payload.key_name)
+            .bind(&parents)
+            .bind(&path)
+            .execute(&mut tx)
+            .await?
+            .last_insert_id() as i32;
+        audit(&mut tx, admin_id, "create", "genre", id, serde_json::json!(payload)).await?;
+        tx.commit().await?;
+        Ok(Ok(id))
+    }
+    .await;
+    match res {
+        Ok(Ok(id)) => success(serde_json::json!({ "id": id })),
+        Ok(Err(msg)) => failed(msg),
+        Err(e) => {
+            tracing::error!("create genre error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+/// Rename or move a genre. Descendants and users' genre picks are re-pathed
+/// and albums tagged with the old name follow the rename.
+pub async fn update_genre(
+    Path(genre_id): Path<i32>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    Json(payload): Json<GenrePayload>,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    if let Err(msg) = payload.validate() {
+        return failed(msg);
+    }
+    let res: Result<Result<(), &str>, sqlx::Error> = async {
+        let mut tx = state.db.begin().await?;
+        let old = match sqlx::query_as::<MySql, GenreNode>(
+            "SELECT id, name, key_name, parents, path FROM genres WHERE id = ?",
+        )
+        .bind(genre_id)
+        .fetch_optional(&mut tx)
+        .await?
+        {
+            Some(old) => old,
+            None => return Ok(Err("genre not found")),
+        };
+        let (parents, path) = match genre_path(&mut tx, payload.parent_id, &payload.key_name).await? {
+            Some(p) => p,
+            None => return Ok(Err("parent genre not found")),
+        };
+        if parents == old.path || parents.starts_with(&format!("{}/", old.path)) {
+            return Ok(Err("can't move a genre below itself"));
+        }
+        let taken = sqlx::query("SELECT id FROM genres WHERE (name = ? OR path = ?) AND id <> ?")
+            .bind(payload.name.trim())
+            .bind(&path)
+            .bind(genre_id)
+            .fetch_optional(&mut tx)
+            .await?;
+        if taken.is_some() {
+            return Ok(Err("genre already exists"));
+        }
+        sqlx::query("UPDATE genres SET name = ?, key_name = ?, parents = ?, path = ? WHERE id = ?")
+            .bind(payload.name.trim())
+            .bind(&payload.key_name)
+            .bind(&parents)
+            .bind(&path)
+            .bind(genre_id)
+            .execute(&mut tx)
+            .await?;
+        if path != old.path {
+            // descendants start with the old path and a slash; compared
+            // with LEFT so a `_` or `%` in a path isn't a wildcard
+            let tail = old.path.chars().count() as i32 + 1;
+            sqlx::query(
+                r#"UPDATE genres SET parents = CONCAT(?, SUBSTRING(parents, ?))
+                WHERE parents = ? OR LEFT(parents, ?) = CONCAT(?, '/')"#,
+            )
+            .bind(&path)
+            .bind(tail)
+            .bind(&old.path)
+            .bind(tail)
+            .bind(&old.path)
+            .execute(&mut tx)
+            .await?;
+            sqlx::query(
+                "UPDATE genres SET path = CONCAT(?, SUBSTRING(path, ?)) WHERE LEFT(path, ?) = CONCAT(?, '/')",
+            )
+            .bind(&path)
+            .bind(tail)
+            .bind(tail)
+            .bind(&old.path)
+            .execute(&mut tx)
+            .await?;
+            // users' picks are paths too; LOCATE, as LIKE would take `_`
+            // as a wildcard
+            let users = sqlx::query_as::<MySql, (i32, String)>(
+                "SELECT id, genre_data FROM rym_user WHERE LOCATE(?, genre_data) > 0",
+            )
+            .bind(&old.path)
+            .fetch_all(&mut tx)
+            .await?;
+            for (user_id, genre_data) in users {
+                let moved = move_genre_paths(&genre_data, &old.path, &path);
+                if moved != genre_data {
+                    sqlx::query("UPDATE rym_user SET genre_data = ? WHERE id = ?")
+                        .bind(&moved)
+                        .bind(user_id)
+                        .execute(&mut tx)
+                        .await?;
+                }
+            }
+        }
+        if payload.name.trim() != old.name {
+            sqlx::query("UPDATE album_genre SET genre = ? WHERE genre = ?")
+                .bind(payload.name.trim())
+                .bind(&old.name)
+                .execute(&mut tx)
+                .await?;
+        }
+        let detail = serde_json::json!({ "before": old, "after": payload });
+        audit(&mut tx, admin_id, "update", "genre", genre_id, detail).await?;
+        tx.commit().await?;
+        Ok(Ok(()))
+    }
+    .await;
+    match res {
+        Ok(Ok(())) => success(serde_json::json!({})),
+        Ok(Err(msg)) => failed(msg),
+        Err(e) => {
+            tracing::error!("update genre error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+/// A user's comma separated genre paths with `old` and the paths below it
+/// moved to `new`.
+fn move_genre_paths(genre_data: &str, old: &str, new: &str) -> String {
+    genre_data
+        .split(',')
+        .map(|p| match p.strip_prefix(old) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{new}{rest}"),
+            _ => p.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Remove a genre that has no sub genres and no albums tagged with it.
+pub async fn delete_genre(
+    Path(genre_id): Path<i32>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let res: Result<Result<(), &str>, sqlx::Error> = async {
+        let mut tx = state.db.begin().await?;
+        let old = match sqlx::query_as::<MySql, GenreNode>(
+            "SELECT id, name, key_name, parents, path FROM genres WHERE id = ?",
+        )
+        .bind(genre_id)
+        .fetch_optional(&mut tx)
+        .await?
+        {
+            Some(old) => old,
+            None => return Ok(Err("genre not found")),
+        };
+        let children = sqlx::query("SELECT id FROM genres WHERE parents = ? LIMIT 1")
+            .bind(&old.path)
+            .fetch_optional(&mut tx)
+            .await?;
+        if children.is_some() {
+            return Ok(Err("genre has sub genres"));
+        }
+        let used = sqlx::query("SELECT id FROM album_genre WHERE genre = ? LIMIT 1")
+            .bind(&old.name)
+            .fetch_optional(&mut tx)
+            .await?;
+        if used.is_some() {
+            return Ok(Err("genre is still assigned to albums"));
+        }
+        sqlx::query("DELETE FROM genres WHERE id = ?")
+            .bind(genre_id)
+            .execute(&mut tx)
+            .await?;
+        audit(&mut tx, admin_id, "delete", "genre", genre_id, serde_json::json!(old)).await?;
+        tx.commit().await?;
+        Ok(Ok(()))
+    }
+    .await;
+    match res {
+        Ok(Ok(())) => success(serde_json::json!({})),
+        Ok(Err(msg)) => failed(msg),
+        Err(e) => {
+            tracing::error!("delete genre error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+pub async fn audit_log(
+    pagination: Option<Query<Pagination>>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+) -> impl IntoResponse {
+    if let Err(resp) = require_admin(&session, &state.db).await {
+        return resp;
+    }
+    let Query(pagination) = pagination.unwrap_or_default();
+    if let Err(e) = pagination.validate() {
+        return failed(&e);
+    }
+    match sqlx::query_as::<MySql, AuditEntry>(
+        r#"SELECT id, user_id, action, target_type, target_id, detail,
+        CAST(create_time AS CHAR) AS create_time FROM admin_audit_log
+        ORDER BY id DESC LIMIT ?, ?"#,
+    )
+    .bind(pagination.offset() as u64)
+    .bind(pagination.page_size as u64)
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(res) => success(serde_json::json!({
+            "res": res,
+            "page": pagination.page,
+            "page_size": pagination.page_size,
+        })),
+        Err(e) => {
+            tracing::error!("audit log error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+/// Background jobs with their schedules, whether they're running and
+/// their last run.
+pub async fn jobs(
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+) -> impl IntoResponse {
+    if let Err(resp) = require_admin(&session, &state.db).await {
+        return resp;
+    }
+    match state.jobs.status().await {
+        Ok(res) => success(serde_json::json!(res)),
+        Err(e) => {
+            tracing::error!("job status error: {e}");
+            failed("failed")
+        }
+    }
+}
+
+/// Start a job now, whatever its schedule. Its outcome shows up in `jobs`.
+pub async fn run_job(
+    Path(name): Path<String>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+) -> impl IntoResponse {
+    let admin_id = match require_admin(&session, &state.db).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let Some(job) = jobs::Job::from_name(&name) else {
+        return failed("no such job");
+    };
+    if !state.jobs.trigger(job).await {
+        return failed("job is already running");
+    }
+    let audited = match state.db.acquire().await {
+        Ok(mut con) => audit(&mut con, admin_id, "run", "job", 0, serde_json::json!({ "job": name })).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = audited {
+        tracing::error!("audit log error: {e}");
+    }
+    success(serde_json::json!({ "job": name }))
+}
+
+/// Tables behind the `/today`, genre and artist queries.
+const INDEXED_TABLES: &[&str] = &["album", "album_detail", "album_genre", "genres", "user_album_log", "user_device"];
+
+#[derive(clap::Args)]
+pub struct RebuildIndexesArgs {
+    /// Rebuild the tables and their indexes with OPTIMIZE TABLE rather than
+    /// only refreshing index statistics. Slower, and copies each table
+    #[arg(long)]
+    pub optimize: bool,
+}
+
+/// `rebuild-indexes`: recompute genre paths, which the genre filters match
+/// on, from each genre's parents, then refresh MySQL's indexes. Returns the
+/// number of genre paths that were out of date.
+pub async fn rebuild_indexes(db: &MySqlPool, args: &RebuildIndexesArgs) -> Result<u64, String> {
+    let repaired = sqlx::query(
+        r#"UPDATE genres SET path = IF(parents = '', key_name, CONCAT(parents, '/', key_name))
+        WHERE path <> IF(parents = '', key_name, CONCAT(parents, '/', key_name))"#,
+    )
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?
+    .rows_affected();
+
+    let op = if args.optimize { "OPTIMIZE" } else { "ANALYZE" };
+    for table in INDEXED_TABLES {
+        // not a prepared statement, which some servers refuse these as
+        let rows = sqlx::Executor::fetch_all(db, format!("{op} TABLE {table}").as_str())
+            .await
+            .map_err(|e| format!("{table}: {e}"))?;
+        for row in rows {
+            let kind: String = row.try_get("Msg_type").unwrap_or_default();
+            let text: String = row.try_get("Msg_text").unwrap_or_default();
+            if kind.eq_ignore_ascii_case("error") {
+                return Err(format!("{table}: {text}"));
+            }
+        }
+    }
+    Ok(repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_a_genre_repaths_it_and_what_is_below() {
+        assert_eq!(
+            move_genre_paths("rock/shoegaze,rock/shoegaze/blackgaze,pop", "rock/shoegaze", "rock/noise-pop"),
+            "rock/noise-pop,rock/noise-pop/blackgaze,pop"
+        );
+        // only whole segments match
+        assert_eq!(
+            move_genre_paths("rock/shoegazer,rock", "rock/shoegaze", "dream"),
+            "rock/shoegazer,rock"
+        );
+        assert_eq!(move_genre_paths("", "rock", "metal"), "");
+    }
+}
@@ -0,0 +1,566 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use clap::Args;
+use futures::StreamExt;
+use image::{
+    codecs::jpeg::JpegEncoder,
+    error::{EncodingError, ImageFormatHint},
+    DynamicImage, ImageError, ImageFormat, Rgb, RgbImage,
+};
+use serde::Deserialize;
+use sha3::{Digest, Sha3_256};
+use sqlx::mysql::{MySql, MySqlPool};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use utoipa::IntoParams;
+
+use crate::http_cache::{self, Validators};
+use crate::settings::CoverSettings;
+use crate::MyShared;
+
+/// Thumbnail edges we render; requests are rounded up to one of these.
+pub const SIZES: [u32; 4] = [64, 150, 300, 600];
+const DEFAULT_SIZE: u32 = 300;
+const JPEG_QUALITY: u8 = 85;
+/// Lossy, about the size of the JPEG at [`JPEG_QUALITY`] or smaller.
+const WEBP_QUALITY: f32 = 80.0;
+/// Originals larger than this aren't worth a thumbnail.
+const MAX_ORIGINAL_BYTES: u64 = 20 * 1024 * 1024;
+/// Short, as the url stays the same when an album's cover changes; the ETag
+/// makes revalidating cheap.
+const CACHE_CONTROL: &str = "public, max-age=3600";
+/// Short, so a recovered source shows up again soon.
+const PLACEHOLDER_CACHE_CONTROL: &str = "public, max-age=300";
+/// Site background (tailwind blue-800)
+const PLACEHOLDER_COLOR: Rgb<u8> = Rgb([30, 64, 175]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Jpeg,
+    Webp,
+}
+
+impl Format {
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Jpeg => "image/jpeg",
+            Format::Webp => "image/webp",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpg",
+            Format::Webp => "webp",
+        }
+    }
+}
+
+/// What the frontend paints while a cover loads.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    /// `#rrggbb`
+    pub color: String,
+    pub blurhash: String,
+}
+
+#[derive(Debug, Args)]
+pub struct BackfillArgs {
+    /// Stop after this many albums
+    #[arg(long)]
+    pub limit: Option<i64>,
+    /// Covers fetched at once
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct CoverQuery {
+    /// Rounded up to 64, 150, 300 or 600
+    size: Option<u32>,
+    /// `webp` or `jpeg`; picked from the Accept header when missing
+    format: Option<String>,
+}
+
+/// Fetches covers from their original hosts, so browsers only ever talk to
+/// us, and keeps the thumbnails on disk.
+pub struct CoverProxy {
+    client: reqwest::Client,
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Thumbnail bytes written since the last eviction
+    written: AtomicU64,
+    evicting: Arc<AtomicBool>,
+}
+
+impl CoverProxy {
+    pub fn new(settings: &CoverSettings) -> Result<Self, String> {
+        let dir = PathBuf::from(&settings.cache_dir);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("can't create cover cache dir {}: {e}", dir.display()))?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(settings.timeout_secs))
+            .build()
+            .map_err(|e| format!("can't build http client: {e}"))?;
+        Ok(Self {
+            client,
+            dir,
+            max_bytes: settings.cache_max_mb * 1024 * 1024,
+            written: AtomicU64::new(0),
+            evicting: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// One file per album, cover url, size and format, so a changed cover
+    /// never hits an old thumbnail.
+    fn cache_path(&self, album_id: i32, cover: &str, size: u32, format: Format) -> PathBuf {
+        self.dir
+            .join(format!("{album_id}-{}", thumbnail_name(cover, size, format)))
+    }
+
+    /// Walking the cache dir is slow, so only evict once another tenth of
+    /// the budget has been written, and never twice at once.
+    fn written(&self, len: usize) {
+        let written = self.written.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+        if written < self.max_bytes / 10 || self.evicting.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.written.store(0, Ordering::Relaxed);
+        let dir = self.dir.clone();
+        let max_bytes = self.max_bytes;
+        let evicting = self.evicting.clone();
+        tokio::task::spawn_blocking(move || {
+            evict(&dir, max_bytes);
+            evicting.store(false, Ordering::Release);
+        });
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Bytes, String> {
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?;
+        if res.content_length().unwrap_or(0) > MAX_ORIGINAL_BYTES {
+            return Err("original is too large".to_string());
+        }
+        let body = res.bytes().await.map_err(|e| e.to_string())?;
+        if body.len() as u64 > MAX_ORIGINAL_BYTES {
+            return Err("original is too large".to_string());
+        }
+        Ok(body)
+    }
+
+    async fn thumbnail(
+        &self,
+        album_id: i32,
+        cover: &str,
+        size: u32,
+        format: Format,
+    ) -> Result<(Vec<u8>, Option<Palette>), String> {
+        let path = self.cache_path(album_id, cover, size, format);
+        if let Ok(data) = tokio::fs::read(&path).await {
+            // eviction goes by mtime, so a hit counts as a use
+            tokio::task::spawn_blocking(move || {
+                std::fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|f| f.set_modified(SystemTime::now()))
+            });
+            return Ok((data, None));
+        }
+        let original = self.fetch(cover).await?;
+        let (data, palette) = tokio::task::spawn_blocking(move || render(&original, size, format))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+        match tokio::fs::write(&path, &data).await {
+            Ok(()) => self.written(data.len()),
+            Err(e) => tracing::error!("cover cache write error: {e}"),
+        }
+        Ok((data, Some(palette)))
+    }
+
+    /// Placeholders for a cover, from the cached thumbnail when there is one.
+    async fn palette(&self, album_id: i32, cover: &str) -> Result<Palette, String> {
+        match self
+            .thumbnail(album_id, cover, DEFAULT_SIZE, Format::Jpeg)
+            .await?
+        {
+            (_, Some(palette)) => Ok(palette),
+            (data, None) => tokio::task::spawn_blocking(move || {
+                image::load_from_memory(&data).map(|img| palette(&img))
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Names a thumbnail after a hash of its cover url, which doubles as its
+/// ETag.
+fn thumbnail_name(cover: &str, size: u32, format: Format) -> String {
+    let hash = format!("{:x}", Sha3_256::digest(cover.as_bytes()));
+    format!("{}-{size}.{}", &hash[..16], format.extension())
+}
+
+fn standard_size(requested: Option<u32>) -> u32 {
+    let requested = requested.unwrap_or(DEFAULT_SIZE);
+    SIZES
+        .into_iter()
+        .find(|s| *s >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+fn negotiate(format: Option<&str>, headers: &HeaderMap) -> Format {
+    match format {
+        Some("webp") => Format::Webp,
+        Some(_) => Format::Jpeg,
+        None => {
+            let accept = headers
+                .get(header::ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            if accept.contains("image/webp") {
+                Format::Webp
+            } else {
+                Format::Jpeg
+            }
+        }
+    }
+}
+
+fn encode(img: DynamicImage, format: Format) -> Result<Vec<u8>, ImageError> {
+    let mut out = vec![];
+    match format {
+        Format::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))?,
+        Format::Webp => {
+            let rgba = img.to_rgba8();
+            let webp = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+                .encode_simple(false, WEBP_QUALITY)
+                .map_err(|e| {
+                    ImageError::Encoding(EncodingError::new(
+                        ImageFormatHint::Exact(ImageFormat::WebP),
+                        format!("{e:?}"),
+                    ))
+                })?;
+            out.extend_from_slice(&webp);
+        }
+    }
+    Ok(out)
+}
+
+/// The most common colour, on a 4 bit per channel grid, and a 4x3
+/// component blurhash.
+fn palette(img: &DynamicImage) -> Palette {
+    let small = img.thumbnail(32, 32).to_rgba8();
+    let mut buckets: HashMap<(u8, u8, u8), (u32, [u32; 3])> = HashMap::new();
+    for p in small.pixels().filter(|p| p[3] > 0) {
+        let bucket = buckets
+            .entry((p[0] >> 4, p[1] >> 4, p[2] >> 4))
+            .or_default();
+        bucket.0 += 1;
+        for c in 0..3 {
+            bucket.1[c] += p[c] as u32;
+        }
+    }
+    let color = buckets
+        .into_values()
+        .max_by_key(|(n, _)| *n)
+        .map(|(n, sum)| format!("#{:02x}{:02x}{:02x}", sum[0] / n, sum[1] / n, sum[2] / n))
+        .unwrap_or_else(|| "#000000".to_string());
+    let blurhash =
+        blurhash::encode(4, 3, small.width(), small.height(), small.as_raw()).unwrap_or_default();
+    Palette { color, blurhash }
+}
+
+/// Scale the original to fit in a `size` square.
+fn render(original: &[u8], size: u32, format: Format) -> Result<(Vec<u8>, Palette), ImageError> {
+    let img = image::load_from_memory(original)?;
+    let palette = palette(&img);
+    let img = if img.width() > size || img.height() > size {
+        img.resize(size, size, image::imageops::FilterType::Lanczos3)
+    } else {
+        img
+    };
+    Ok((encode(img, format)?, palette))
+}
+
+async fn store_palette(
+    db: &MySqlPool,
+    album_id: i32,
+    palette: &Palette,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE album SET cover_color = ?, cover_blurhash = ? WHERE id = ?")
+        .bind(&palette.color)
+        .bind(&palette.blurhash)
+        .bind(album_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Compute placeholders for covers nobody has requested yet. Returns how
+/// many albums got them and how many covers failed.
+pub async fn backfill(
+    pool: &MySqlPool,
+    redis: &redis::Client,
+    proxy: &CoverProxy,
+    args: &BackfillArgs,
+) -> Result<(usize, usize), sqlx::Error> {
+    let albums = sqlx::query_as::<MySql, (i32, String)>(
+        r#"SELECT id, cover FROM album WHERE cover <> '' AND cover_dead = 0
+        AND cover_blurhash IS NULL ORDER BY id LIMIT ?"#,
+    )
+    .bind(args.limit.unwrap_or(i64::MAX))
+    .fetch_all(pool)
+    .await?;
+    let mut results = futures::stream::iter(albums)
+        .map(|(album_id, cover)| async move { (album_id, proxy.palette(album_id, &cover).await) })
+        .buffer_unordered(args.concurrency.max(1));
+    let (mut done, mut failed) = (0, 0);
+    while let Some((album_id, palette)) = results.next().await {
+        match palette {
+            Ok(palette) => {
+                store_palette(pool, album_id, &palette).await?;
+                done += 1;
+            }
+            Err(e) => {
+                tracing::warn!("album {album_id}: {e}");
+                failed += 1;
+            }
+        }
+    }
+    // listings carry the placeholders, touch once for the whole run
+    if done > 0 {
+        http_cache::touch_catalog(redis).await;
+    }
+    Ok((done, failed))
+}
+
+fn placeholder(size: u32, format: Format) -> Vec<u8> {
+    let img = RgbImage::from_pixel(size, size, PLACEHOLDER_COLOR);
+    encode(DynamicImage::ImageRgb8(img), format).unwrap_or_default()
+}
+
+/// Drop least recently used thumbnails until the cache is back under 90%
+/// of its budget.
+fn evict(dir: &std::path::Path, max_bytes: u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut files = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            meta.is_file().then(|| {
+                (
+                    e.path(),
+                    meta.len(),
+                    meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut total: u64 = files.iter().map(|f| f.1).sum();
+    if total <= max_bytes {
+        return;
+    }
+    files.sort_by_key(|f| f.2);
+    for (path, len, _) in files {
+        if total <= max_bytes / 10 * 9 {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/cover/{album_id}",
+    params(("album_id" = i32, Path, description = "Album id"), CoverQuery),
+    responses((
+        status = 200,
+        description = "The thumbnail, or a plain placeholder when the cover can't be fetched",
+        content(("image/webp" = [u8]), ("image/jpeg" = [u8]))
+    ))
+)]
+pub async fn cover(
+    Path(album_id): Path<i32>,
+    Query(query): Query<CoverQuery>,
+    headers: HeaderMap,
+    Extension(state): Extension<MyShared>,
+) -> Response {
+    let size = standard_size(query.size);
+    let format = negotiate(query.format.as_deref(), &headers);
+
+    let cover: Option<String> =
+        sqlx::query_scalar("SELECT IFNULL(cover, '') FROM album WHERE id = ?")
+            .bind(album_id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or_default();
+    let cover = cover.filter(|c| !c.is_empty());
+    let validators = cover
+        .as_deref()
+        .map(|cover| Validators::tag(&thumbnail_name(cover, size, format)));
+    if let Some(validators) = validators.as_ref().filter(|v| v.fresh(&headers)) {
+        let mut resp = validators.not_modified(CACHE_CONTROL);
+        resp.headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("Accept"));
+        return resp;
+    }
+    let thumbnail = match cover {
+        Some(cover) => state.cover.thumbnail(album_id, &cover, size, format).await,
+        None => Err("album has no cover".to_string()),
+    };
+    let (data, validators) = match thumbnail {
+        Ok((data, palette)) => {
+            if let Some(palette) = palette {
+                match store_palette(&state.db, album_id, &palette).await {
+                    Ok(()) => http_cache::touch_catalog(&state.redis).await,
+                    Err(e) => tracing::error!("cover {album_id} palette error: {e}"),
+                }
+            }
+            (data, validators)
+        }
+        Err(e) => {
+            tracing::error!("cover {album_id} error: {e}");
+            (placeholder(size, format), None)
+        }
+    };
+    let resp = (
+        [
+            (header::CONTENT_TYPE, format.content_type()),
+            (header::VARY, "Accept"),
+        ],
+        data,
+    );
+    match validators {
+        Some(validators) => validators.respond(resp, CACHE_CONTROL),
+        None => ([(header::CACHE_CONTROL, PLACEHOLDER_CACHE_CONTROL)], resp).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cover-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn png(width: u32, height: u32, color: Rgb<u8>) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, color))
+            .write_to(&mut out, ImageFormat::Png)
+            .unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn sizes_round_up() {
+        assert_eq!(standard_size(None), DEFAULT_SIZE);
+        assert_eq!(standard_size(Some(1)), 64);
+        assert_eq!(standard_size(Some(64)), 64);
+        assert_eq!(standard_size(Some(151)), 300);
+        assert_eq!(standard_size(Some(5000)), 600);
+    }
+
+    #[test]
+    fn format_from_query_then_accept() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(negotiate(None, &headers), Format::Jpeg);
+        headers.insert(header::ACCEPT, HeaderValue::from_static("image/avif,image/webp,*/*"));
+        assert_eq!(negotiate(None, &headers), Format::Webp);
+        assert_eq!(negotiate(Some("jpeg"), &headers), Format::Jpeg);
+        assert_eq!(negotiate(Some("webp"), &HeaderMap::new()), Format::Webp);
+    }
+
+    #[test]
+    fn thumbnail_name_follows_the_cover() {
+        let name = thumbnail_name("https://a.example/1.jpg", 300, Format::Webp);
+        assert!(name.ends_with("-300.webp"));
+        assert_eq!(name, thumbnail_name("https://a.example/1.jpg", 300, Format::Webp));
+        assert_ne!(name, thumbnail_name("https://a.example/2.jpg", 300, Format::Webp));
+        assert_ne!(name, thumbnail_name("https://a.example/1.jpg", 300, Format::Jpeg));
+    }
+
+    #[test]
+    fn render_fits_the_size_and_encodes() {
+        let original = png(800, 400, Rgb([200, 10, 10]));
+        let (jpeg, palette) = render(&original, 300, Format::Jpeg).unwrap();
+        assert_eq!(&jpeg[..2], &[0xff, 0xd8]);
+        let img = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!((img.width(), img.height()), (300, 150));
+        assert_eq!(palette.color, "#c80a0a");
+        assert!(!palette.blurhash.is_empty());
+
+        let (webp, _) = render(&original, 64, Format::Webp).unwrap();
+        assert_eq!(&webp[8..12], b"WEBP");
+        // small originals aren't blown up
+        let (small, _) = render(&png(40, 40, Rgb([0, 0, 0])), 600, Format::Jpeg).unwrap();
+        assert_eq!(image::load_from_memory(&small).unwrap().width(), 40);
+    }
+
+    #[test]
+    fn render_rejects_garbage() {
+        assert!(render(b"not an image", 300, Format::Jpeg).is_err());
+    }
+
+    #[test]
+    fn evict_drops_the_least_recently_used() {
+        let dir = temp_dir("evict");
+        let now = SystemTime::now();
+        for (i, name) in ["old", "mid", "new"].iter().enumerate() {
+            let path = dir.join(name);
+            std::fs::write(&path, [0u8; 100]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|f| f.set_modified(now - Duration::from_secs(100 - i as u64)))
+                .unwrap();
+        }
+        evict(&dir, 300);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        evict(&dir, 250);
+        let mut left = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["mid", "new"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_reports_an_unusable_cache_dir() {
+        let dir = temp_dir("new");
+        let file = dir.join("file");
+        std::fs::write(&file, b"").unwrap();
+        let settings = CoverSettings {
+            cache_dir: file.join("covers").display().to_string(),
+            ..CoverSettings::default()
+        };
+        assert!(CoverProxy::new(&settings).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
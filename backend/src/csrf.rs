@@ -0,0 +1,177 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use headers::{Cookie, HeaderMapExt};
+use rand::RngCore;
+
+use crate::MyShared;
+
+pub const COOKIE: &str = "csrf_token";
+pub const HEADER: &str = "x-csrf-token";
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Compare without bailing out at the first differing byte.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// An `Origin` that isn't this host or one of `allowed_origins`. Requests
+/// without the header (same origin GETs, curl) pass.
+fn foreign_origin(headers: &HeaderMap, allowed: &[String]) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let own = origin
+        .split_once("://")
+        .map(|(_, rest)| rest == host)
+        .unwrap_or(false);
+    !own && !allowed.iter().any(|a| a == origin)
+}
+
+fn forbidden(msg: &str) -> Response {
+    let resp = serde_json::json!({
+        "code": 403,
+        "msg": msg
+    });
+    (StatusCode::FORBIDDEN, Json(resp)).into_response()
+}
+
+/// Double submit guard: every response hands out a `csrf_token` cookie,
+/// and the same token in an `X-CSRF-Token` header, and requests that change
+/// state must echo it in `X-CSRF-Token`. The frontend reads the cookie; the
+/// `[cors] allowed_origins` can't, and read the exposed header instead.
+/// Other sites can read neither, so they can't forge the header. Mutating
+/// requests from unknown origins are refused outright.
+pub async fn guard(
+    Extension(state): Extension<MyShared>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let cookie = req
+        .headers()
+        .typed_get::<Cookie>()
+        .and_then(|c| c.get(COOKIE).map(str::to_string))
+        .filter(|token| !token.is_empty());
+    let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if !safe {
+        if foreign_origin(req.headers(), &state.allowed_origins) {
+            return forbidden("origin not allowed");
+        }
+        let sent = req.headers().get(HEADER).and_then(|v| v.to_str().ok());
+        let valid = match (&cookie, sent) {
+            (Some(cookie), Some(sent)) => same(cookie, sent),
+            _ => false,
+        };
+        if !valid {
+            return with_token(forbidden("missing or wrong csrf token"), cookie, &state.allowed_origins);
+        }
+    }
+    with_token(next.run(req).await, cookie, &state.allowed_origins)
+}
+
+/// Send the token in the header, and set the cookie when the client doesn't
+/// have one yet. With allowed origins the cookie has to go along on their
+/// cross site requests, which browsers only do for `SameSite=None; Secure`.
+fn with_token(mut resp: Response, cookie: Option<String>, allowed: &[String]) -> Response {
+    let token = match cookie {
+        Some(token) => token,
+        None => {
+            let token = new_token();
+            let same_site = if allowed.is_empty() {
+                "SameSite=Lax"
+            } else {
+                "SameSite=None; Secure"
+            };
+            let cookie = format!("{COOKIE}={token}; Path=/; {same_site}");
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                resp.headers_mut().append(header::SET_COOKIE, value);
+            }
+            token
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&token) {
+        resp.headers_mut().insert(HEADER, value);
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn same_compares_whole_tokens() {
+        assert!(same("abc", "abc"));
+        assert!(same("", ""));
+        assert!(!same("abc", "abd"));
+        assert!(!same("abc", "ab"));
+        assert!(!same("ab", "abc"));
+    }
+
+    #[test]
+    fn foreign_origin_allows_own_host_and_listed_origins() {
+        let allowed = vec!["https://app.example.com".to_string()];
+        let host = (header::HOST, "api.example.com");
+        assert!(!foreign_origin(&headers(&[(header::HOST, "api.example.com")]), &allowed));
+        assert!(!foreign_origin(
+            &headers(&[host.clone(), (header::ORIGIN, "https://api.example.com")]),
+            &allowed
+        ));
+        assert!(!foreign_origin(
+            &headers(&[host.clone(), (header::ORIGIN, "https://app.example.com")]),
+            &allowed
+        ));
+        assert!(foreign_origin(
+            &headers(&[host.clone(), (header::ORIGIN, "https://evil.example.com")]),
+            &allowed
+        ));
+        assert!(foreign_origin(
+            &headers(&[host.clone(), (header::ORIGIN, "https://app.example.com.evil.example")]),
+            &allowed
+        ));
+        assert!(foreign_origin(&headers(&[host, (header::ORIGIN, "null")]), &[]));
+    }
+
+    #[test]
+    fn with_token_hands_out_a_readable_token() {
+        let resp = with_token(StatusCode::OK.into_response(), Some("known".to_string()), &[]);
+        assert_eq!(resp.headers()[HEADER], "known");
+        assert!(resp.headers().get(header::SET_COOKIE).is_none());
+
+        let resp = with_token(StatusCode::OK.into_response(), None, &[]);
+        let token = resp.headers()[HEADER].to_str().unwrap();
+        let cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert_eq!(cookie, format!("{COOKIE}={token}; Path=/; SameSite=Lax"));
+
+        let allowed = vec!["https://app.example.com".to_string()];
+        let resp = with_token(StatusCode::OK.into_response(), None, &allowed);
+        let cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(cookie.ends_with("; SameSite=None; Secure"));
+    }
+}
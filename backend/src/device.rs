@@ -0,0 +1,252 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_sessions::{
+    async_session::Session,
+    extractors::{ReadableSession, WritableSession},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::{MySql, MySqlPool};
+
+use utoipa::ToSchema;
+
+use crate::{feed, MyShared};
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct Device {
+    client_id: String,
+    label: String,
+    user_agent: String,
+    first_seen: String,
+    last_seen: String,
+    #[sqlx(default)]
+    current: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceLabel {
+    label: String,
+}
+
+/// Record that `client_id` is signed in as `user_id`. A client id belongs to
+/// exactly one user, so signing in as someone else moves the device over.
+pub async fn register(
+    db: &MySqlPool,
+    client_id: &str,
+    user_id: i32,
+    user_agent: &str,
+) -> Result<(), sqlx::Error> {
+    let user_agent: String = user_agent.chars().take(255).collect();
+    sqlx::query(
+        r#"INSERT INTO user_device (client_id, user_id, user_agent, first_seen, last_seen)
+        VALUES (?, ?, ?, NOW(), NOW())
+        ON DUPLICATE KEY UPDATE
+            first_seen = IF(user_id = VALUES(user_id), first_seen, NOW()),
+            label = IF(user_id = VALUES(user_id), label, ''),
+            user_id = VALUES(user_id),
+            user_agent = VALUES(user_agent),
+            last_seen = NOW()"#,
+    )
+    .bind(client_id)
+    .bind(user_id)
+    .bind(user_agent)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Bump `last_seen` for a device that fetched its feed.
+pub async fn touch(db: &MySqlPool, client_id: &str) {
+    if let Err(e) = sqlx::query("UPDATE user_device SET last_seen = NOW() WHERE client_id = ?")
+        .bind(client_id)
+        .execute(db)
+        .await
+    {
+        tracing::error!("touch device error: {e}");
+    }
+}
+
+/// The user id of the session, or 0 when it is anonymous or its device has
+/// been signed out from another browser.
+pub async fn session_user_id(session: &Session, db: &MySqlPool) -> i32 {
+    let user_id: i32 = session.get("user_id").unwrap_or_default();
+    if user_id == 0 {
+        return 0;
+    }
+    // sessions created before devices were tracked carry no client id
+    let client_id: String = match session.get("client_id") {
+        Some(client_id) => client_id,
+        None => return user_id,
+    };
+    match sqlx::query("SELECT id FROM user_device WHERE client_id = ? AND user_id = ?")
+        .bind(&client_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+    {
+        Ok(Some(_)) => user_id,
+        _ => 0,
+    }
+}
+
+/// Drop the cached `/today` pages of a client so a signed out device stops
+/// receiving its former owner's feed.
+pub async fn clear_feed_cache(redis: &redis::Client, client_id: &str) {
+    let mut con = match redis.get_async_connection().await {
+        Ok(con) => con,
+        Err(e) => {
+            tracing::error!("redis error: {e}");
+            return;
+        }
+    };
+    if let Err(e) = feed::clear_pages(&mut con, client_id).await {
+        tracing::error!("redis error: {e}");
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices",
+    responses(
+        (status = 200, body = DevicesEnvelope),
+        (status = 400, description = "Not logged in", body = EmptyEnvelope)
+    ),
+    security(("session" = []))
+)]
+pub async fn list_devices(
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+) -> impl IntoResponse {
+    let user_id = session_user_id(&session, &state.db).await;
+    if user_id == 0 {
+        let resp = serde_json::json!({
+            "code": 400,
+            "msg": "you are not logged in"
+        });
+        return (StatusCode::BAD_REQUEST, Json(resp));
+    }
+    let current: String = session.get("client_id").unwrap_or_default();
+    match sqlx::query_as::<MySql, Device>(
+        r#"SELECT client_id, label, user_agent, CAST(first_seen AS CHAR) AS first_seen,
+        CAST(last_seen AS CHAR) AS last_seen FROM user_device WHERE user_id = ?
+        ORDER BY last_seen DESC"#,
+    )
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(mut devices) => {
+            for device in devices.iter_mut() {
+                device.current = device.client_id == current;
+            }
+            let resp = serde_json::json!({
+                "code": 200,
+                "msg": "success",
+                "data": {
+                    "devices": devices
+                }
+            });
+            (StatusCode::OK, Json(resp))
+        }
+        Err(e) => {
+            tracing::error!("list devices error: {e}");
+            let resp = serde_json::json!({
+                "code": 400,
+                "msg": "failed"
+            });
+            (StatusCode::BAD_REQUEST, Json(resp))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/devices/{client_id}",
+    params(("client_id" = String, Path, description = "Device client id")),
+    request_body = DeviceLabel,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Not one of the user's devices", body = EmptyEnvelope)
+    ),
+    security(("session" = [], "csrf" = []))
+)]
+pub async fn label_device(
+    Path(client_id): Path<String>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    Json(payload): Json<DeviceLabel>,
+) -> impl IntoResponse {
+    let user_id = session_user_id(&session, &state.db).await;
+    let label: String = payload.label.trim().chars().take(64).collect();
+    let res = sqlx::query("UPDATE user_device SET label = ? WHERE client_id = ? AND user_id = ?")
+        .bind(label)
+        .bind(&client_id)
+        .bind(user_id)
+        .execute(&state.db)
+        .await;
+    match res {
+        Ok(res) if res.rows_affected() > 0 => {
+            let resp = serde_json::json!({
+                "code": 200,
+                "msg": "success",
+                "data": {}
+            });
+            (StatusCode::OK, Json(resp))
+        }
+        _ => {
+            let resp = serde_json::json!({
+                "code": 400,
+                "msg": "device not found"
+            });
+            (StatusCode::BAD_REQUEST, Json(resp))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/devices/{client_id}/logout",
+    params(("client_id" = String, Path, description = "Device client id")),
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Not one of the user's devices", body = EmptyEnvelope)
+    ),
+    security(("session" = [], "csrf" = []))
+)]
+pub async fn logout_device(
+    Path(client_id): Path<String>,
+    Extension(state): Extension<MyShared>,
+    mut session: WritableSession,
+) -> impl IntoResponse {
+    let user_id = session_user_id(&session, &state.db).await;
+    let res = sqlx::query("DELETE FROM user_device WHERE client_id = ? AND user_id = ?")
+        .bind(&client_id)
+        .bind(user_id)
+        .execute(&state.db)
+        .await;
+    match res {
+        Ok(res) if res.rows_affected() > 0 => {
+            clear_feed_cache(&state.redis, &client_id).await;
+            let current: String = session.get("client_id").unwrap_or_default();
+            if current == client_id {
+                session.destroy();
+            }
+            let resp = serde_json::json!({
+                "code": 200,
+                "msg": "device signed out",
+                "data": {}
+            });
+            (StatusCode::OK, Json(resp))
+        }
+        _ => {
+            let resp = serde_json::json!({
+                "code": 400,
+                "msg": "device not found"
+            });
+            (StatusCode::BAD_REQUEST, Json(resp))
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use clap::Args;
+use futures::TryStreamExt;
+use sqlx::mysql::{MySql, MySqlPool};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::import::{AlbumRecord, GenreRecord, ListenRecord, Record, UserRecord};
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Write to this file instead of stdout
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+    /// Also dump users and their listening history, with personal data anonymized
+    #[arg(long)]
+    pub include_users: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct AlbumRow {
+    key: String,
+    name: String,
+    artist: String,
+    cover: String,
+    media_url: Option<sqlx::types::Json<HashMap<String, serde_json::Value>>>,
+    descriptors: String,
+    language: String,
+    released: String,
+    rate: String,
+    primary_genres: Option<String>,
+    secondary_genres: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: i32,
+    genre_data: Option<String>,
+    fresh_time: i32,
+    role: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct ListenRow {
+    user_id: String,
+    album: String,
+    album_genre: String,
+    click_count: i32,
+    listen_count: i32,
+    create_time: String,
+}
+
+/// Exported users are known by their id only. The `:` keeps the name from
+/// ever matching a real account, whose names can't contain one.
+fn anonymous_username(user_id: impl std::fmt::Display) -> String {
+    format!("anon:{user_id}")
+}
+
+fn split_lines(genres: Option<String>) -> Vec<String> {
+    genres
+        .unwrap_or_default()
+        .split('\n')
+        .filter(|g| !g.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn write_record(out: &mut impl Write, record: &Record) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    out.write_all(b"\n")
+}
+
+/// Stream the catalog as JSON Lines that `import` reads back: the genre tree
+/// first, then albums, then optionally users and their listens.
+pub async fn run(pool: &MySqlPool, args: ExportArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    let mut counts: Vec<(&str, usize)> = vec![];
+
+    let mut genres = sqlx::query_as::<MySql, GenreRecord>(
+        "SELECT name, key_name, parents, path FROM genres ORDER BY path",
+    )
+    .fetch(pool);
+    let mut n = 0;
+    while let Some(genre) = genres.try_next().await? {
+        write_record(&mut out, &Record::Genre(genre))?;
+        n += 1;
+    }
+    counts.push(("genres", n));
+
+    let mut albums = sqlx::query_as::<MySql, AlbumRow>(
+        r#"SELECT IFNULL(a.external_key, CONCAT('rym-', a.id)) AS `key`, a.name, a.artist,
+        IFNULL(a.cover, '') AS cover, a.media_url, IFNULL(d.descriptors, '') AS descriptors,
+        IFNULL(d.language, '') AS language, IFNULL(d.released, '') AS released,
+        IFNULL(d.rate, '') AS rate,
+        CAST((SELECT GROUP_CONCAT(g.genre ORDER BY g.id SEPARATOR '\n') FROM album_genre g
+            WHERE g.album_id = a.id AND g.genre_type = 'pri') AS CHAR) AS primary_genres,
+        CAST((SELECT GROUP_CONCAT(g.genre ORDER BY g.id SEPARATOR '\n') FROM album_genre g
+            WHERE g.album_id = a.id AND g.genre_type = 'sec') AS CHAR) AS secondary_genres
+        FROM album a LEFT JOIN album_detail d ON d.album_id = a.id ORDER BY a.id"#,
+    )
+    .fetch(pool);
+    let mut n = 0;
+    while let Some(row) = albums.try_next().await? {
+        let album = AlbumRecord {
+            key: row.key,
+            name: row.name,
+            artist: row.artist,
+            cover: row.cover,
+            media_url: row.media_url.map(|m| m.0).unwrap_or_default(),
+            primary_genres: split_lines(row.primary_genres),
+            secondary_genres: split_lines(row.secondary_genres),
+            descriptors: row.descriptors,
+            language: row.language,
+            released: row.released,
+            rate: row.rate,
+        };
+        write_record(&mut out, &Record::Album(album))?;
+        n += 1;
+    }
+    counts.push(("albums", n));
+
+    if args.include_users {
+        let mut users = sqlx::query_as::<MySql, UserRow>(
+            "SELECT id, genre_data, fresh_time, role FROM rym_user ORDER BY id",
+        )
+        .fetch(pool);
+        let mut n = 0;
+        while let Some(row) = users.try_next().await? {
+            let user = UserRecord {
+                username: anonymous_username(row.id),
+                email: format!("anon-{}@example.invalid", row.id),
+                genre_data: row.genre_data,
+                fresh_time: row.fresh_time,
+                role: row.role,
+            };
+            write_record(&mut out, &Record::User(user))?;
+            n += 1;
+        }
+        counts.push(("users", n));
+
+        let mut listens = sqlx::query_as::<MySql, ListenRow>(
+            r#"SELECT CAST(l.user_id AS CHAR) AS user_id,
+            IFNULL(a.external_key, CONCAT('rym-', a.id)) AS album,
+            IFNULL(l.album_genre, '') AS album_genre, l.click_count, l.listen_count,
+            CAST(l.create_time AS CHAR) AS create_time
+            FROM user_album_log l JOIN album a ON a.id = l.album_id ORDER BY l.id"#,
+        )
+        .fetch(pool);
+        let mut n = 0;
+        while let Some(row) = listens.try_next().await? {
+            let listen = ListenRecord {
+                user: anonymous_username(row.user_id),
+                album: row.album,
+                album_genre: row.album_genre,
+                click_count: row.click_count,
+                listen_count: row.listen_count,
+                create_time: row.create_time,
+            };
+            write_record(&mut out, &Record::Listen(listen))?;
+            n += 1;
+        }
+        counts.push(("listens", n));
+    }
+    out.flush()?;
+
+    // stdout may be the dump itself
+    let summary = counts
+        .iter()
+        .map(|(kind, n)| format!("{n} {kind}"))
+        .collect::<Vec<String>>()
+        .join(", ");
+    eprintln!("exported {summary}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::parse_line;
+
+    #[test]
+    fn import_reads_the_export_back() {
+        let media_url = serde_json::json!({
+            "spotify": {"7dxKtc08dYeRVHt3p9CZJn": {"default": true}},
+            "applemusic": {"1440857781": {"album": "loveless", "loc": "gb"}},
+        });
+        let records = vec![
+            Record::Genre(GenreRecord {
+                name: "Shoegaze".to_string(),
+                key_name: "shoegaze".to_string(),
+                parents: "rock".to_string(),
+                path: "rock/shoegaze".to_string(),
+            }),
+            Record::Album(AlbumRecord {
+                key: "rym-12".to_string(),
+                name: "Loveless".to_string(),
+                artist: "My Bloody Valentine".to_string(),
+                cover: "https://example.com/loveless.jpg".to_string(),
+                media_url: serde_json::from_value(media_url).unwrap(),
+                primary_genres: vec!["Shoegaze".to_string()],
+                secondary_genres: vec![],
+                descriptors: "noisy, dense".to_string(),
+                language: "English".to_string(),
+                released: "4 November 1991".to_string(),
+                rate: "3.9".to_string(),
+            }),
+            Record::User(UserRecord {
+                username: anonymous_username(7),
+                email: "anon-7@example.invalid".to_string(),
+                genre_data: Some("rock/shoegaze".to_string()),
+                fresh_time: 10,
+                role: "admin".to_string(),
+            }),
+            Record::Listen(ListenRecord {
+                user: anonymous_username("7"),
+                album: "rym-12".to_string(),
+                album_genre: "Shoegaze".to_string(),
+                click_count: 3,
+                listen_count: 2,
+                create_time: "2023-04-01 12:00:00".to_string(),
+            }),
+        ];
+        let mut dump = vec![];
+        for record in &records {
+            write_record(&mut dump, record).unwrap();
+        }
+        let read: Vec<Record> = String::from_utf8(dump)
+            .unwrap()
+            .lines()
+            .map(|line| parse_line(line).and_then(Record::validate).unwrap())
+            .collect();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn import_rejects_media_urls_the_album_page_cant_show() {
+        let line = r#"{"type":"album","key":"rym-12","name":"Loveless","artist":"My Bloody Valentine","media_url":{"applemusic":{"1440857781":{}}}}"#;
+        assert!(parse_line(line).and_then(Record::validate).is_err());
+        let line = line.replace("{}}", r#"{"album":"loveless"}}"#);
+        assert!(parse_line(&line).and_then(Record::validate).is_ok());
+    }
+
+    #[test]
+    fn anonymous_usernames_are_not_valid_usernames() {
+        assert!(common::validation::username(&anonymous_username(7)).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! The random album pages behind `/today`, cached in Redis per browser as
+//! `{client_id}_{page}`.
+use clap::Args;
+use redis::AsyncCommands;
+use sqlx::mysql::{MySql, MySqlPool};
+
+use crate::{media, Album, User};
+
+/// Who a page is built for.
+pub struct Reader {
+    /// 0 for a browser nobody signed in on
+    pub user_id: i32,
+    /// Minutes a page stays cached
+    pub fresh_time: usize,
+    /// Comma-separated genre paths, empty for any genre
+    pub genres: String,
+}
+
+impl Reader {
+    fn anonymous() -> Self {
+        Reader {
+            user_id: 0,
+            fresh_time: 10,
+            genres: String::new(),
+        }
+    }
+}
+
+pub fn page_key(client_id: &str, page: usize) -> String {
+    format!("{client_id}_{page}")
+}
+
+/// The set of a client's cached page keys, so they can be dropped without
+/// searching Redis for them.
+fn pages_key(client_id: &str) -> String {
+    format!("{client_id}_pages")
+}
+
+/// Cache a built page for `ttl` seconds and remember its key.
+pub async fn cache_page(
+    con: &mut redis::aio::Connection,
+    client_id: &str,
+    page: usize,
+    json: &str,
+    ttl: usize,
+) -> redis::RedisResult<()> {
+    let key = page_key(client_id, page);
+    let pages = pages_key(client_id);
+    redis::pipe()
+        .set_ex(&key, json, ttl)
+        .ignore()
+        .sadd(&pages, &key)
+        .ignore()
+        // outlives any page, whatever fresh_time they were cached with
+        .expire(&pages, common::validation::FRESH_TIME_MAX as usize * 60)
+        .ignore()
+        .query_async(con)
+        .await
+}
+
+/// Drop every cached page of a client.
+pub async fn clear_pages(con: &mut redis::aio::Connection, client_id: &str) -> redis::RedisResult<()> {
+    let pages = pages_key(client_id);
+    let mut keys: Vec<String> = con.smembers(&pages).await?;
+    keys.push(pages);
+    con.del(keys).await
+}
+
+/// The user signed in on `client_id`, from their device.
+pub async fn reader_for_client(db: &MySqlPool, client_id: &str) -> Reader {
+    let sql = r#"SELECT u.id, u.username, u.email, u.password, u.genre_data, u.fresh_time
+    from rym_user u join user_device d on u.id = d.user_id where d.client_id = ?"#;
+    match sqlx::query_as::<MySql, User>(sql)
+        .bind(client_id)
+        .fetch_one(db)
+        .await
+    {
+        Ok(user) => Reader {
+            user_id: user.id,
+            fresh_time: user.fresh_time as usize,
+            genres: user.genre_data.unwrap_or_default(),
+        },
+        Err(_) => Reader::anonymous(),
+    }
+}
+
+/// A page of random albums from the reader's genres, linked to their
+/// preferred provider.
+pub async fn generate(db: &MySqlPool, reader: &Reader, page_size: usize) -> sqlx::Result<Vec<Album>> {
+    let paths: Vec<&str> = reader.genres.split(',').filter(|p| !p.is_empty()).collect();
+    // no genres settings
+    let album_list = if paths.is_empty() {
+        let sql = format!(
+            r#"SELECT r1.id, name, cover, cover_color AS color, cover_blurhash AS blurhash,
+            media_url FROM album AS r1 where r1.cover <> ''
+            and r1.cover_dead = 0 ORDER BY rand() ASC LIMIT {}"#,
+            page_size
+        );
+        sqlx::query_as::<MySql, Album>(&sql).fetch_all(db).await
+    } else {
+        // use user genres settings: each genre and the ones below it
+        let search_query = vec!["path = ? OR LEFT(path, CHAR_LENGTH(?) + 1) = CONCAT(?, '/')"; paths.len()]
+            .join(" OR ");
+        let sql = format!(
+            r#"SELECT r1.id, name, cover, cover_color AS color, cover_blurhash AS blurhash,
+            media_url FROM album AS r1 left join album_genre r2
+        on r1.id = r2.album_id where r1.cover <> '' and r1.cover_dead = 0
+        and r2.genre in (select name from genres where {})
+        ORDER BY rand() ASC LIMIT {}"#,
+            search_query, page_size
+        );
+        let mut query = sqlx::query_as::<MySql, Album>(&sql);
+        for path in paths {
+            query = query.bind(path).bind(path).bind(path);
+        }
+        query.fetch_all(db).await
+    };
+    let mut album_list = album_list?;
+    let preferred = media::user_preferred(db, reader.user_id).await;
+    if !preferred.is_empty() {
+        for album in album_list.iter_mut() {
+            if let Some(media_url) = &album.media_url {
+                album.link = media::preferred_link(media_url, &preferred);
+            }
+        }
+    }
+    Ok(album_list)
+}
+
+#[derive(Args)]
+pub struct WarmCacheArgs {
+    /// Warm browsers seen in the last this many days
+    #[arg(long, default_value_t = 7)]
+    pub days: u32,
+    /// Pages to build for each browser
+    #[arg(long, default_value_t = 1)]
+    pub pages: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct WarmSummary {
+    pub clients: usize,
+    pub built: usize,
+    /// Pages still cached from a visit
+    pub fresh: usize,
+    pub failed: usize,
+}
+
+/// Build the first `pages` pages of every recently seen signed-in browser
+/// that has none cached, so its next visit is a cache hit.
+pub async fn warm(
+    db: &MySqlPool,
+    redis: &redis::Client,
+    days: u32,
+    pages: usize,
+    page_size: usize,
+) -> Result<WarmSummary, String> {
+    let client_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT client_id FROM user_device WHERE last_seen >= DATE_SUB(NOW(), INTERVAL ? DAY)",
+    )
+    .bind(days)
+    .fetch_all(db)
+    .await
+    .map_err(|e| e.to_string())?;
+    let mut con = redis
+        .get_async_connection()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut summary = WarmSummary {
+        clients: client_ids.len(),
+        ..Default::default()
+    };
+    for client_id in client_ids {
+        let reader = reader_for_client(db, &client_id).await;
+        for page in 1..=pages {
+            let key = page_key(&client_id, page);
+            let cached: bool = con.exists(&key).await.map_err(|e| e.to_string())?;
+            if cached {
+                summary.fresh += 1;
+                continue;
+            }
+            let stored = match generate(db, &reader, page_size).await {
+                Ok(album_list) => {
+                    let json = serde_json::to_string(&album_list).unwrap();
+                    cache_page(&mut con, &client_id, page, &json, reader.fresh_time * 60)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                Err(e) => Err(e.to_string()),
+            };
+            match stored {
+                Ok(()) => summary.built += 1,
+                Err(e) => {
+                    tracing::warn!("warm {key} failed: {e}");
+                    summary.failed += 1;
+                }
+            }
+        }
+    }
+    Ok(summary)
+}
@@ -0,0 +1,201 @@
+//! Liveness and readiness probes, and waiting for MySQL and Redis at
+//! startup.
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use redis::AsyncCommands;
+use serde::Serialize;
+use sqlx::mysql::{MySql, MySqlConnection, MySqlPool, MySqlPoolOptions};
+use sqlx::Connection;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::{
+    settings::{Settings, StartupSettings},
+    MyShared,
+};
+
+/// Call `attempt` until it succeeds, waiting twice as long after each
+/// failure, up to `connect_retries` retries.
+async fn retry<T, E, F, Fut>(what: &str, settings: &StartupSettings, mut attempt: F) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut backoff = Duration::from_millis(settings.connect_backoff_ms);
+    let max_backoff = Duration::from_millis(settings.connect_backoff_max_ms);
+    let mut retries = 0;
+    loop {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) if retries < settings.connect_retries => {
+                retries += 1;
+                tracing::warn!(
+                    "{what} unreachable ({e}), retry {retries}/{} in {backoff:?}",
+                    settings.connect_retries
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub async fn connect_db(
+    url: &str,
+    max_connections: u32,
+    settings: &StartupSettings,
+) -> Result<MySqlPool, sqlx::Error> {
+    // a single connection fails fast with the real error, where the pool
+    // would keep trying until its acquire timeout
+    retry("database", settings, || async {
+        MySqlConnection::connect(url).await?.close().await
+    })
+    .await?;
+    MySqlPoolOptions::new()
+        .max_connections(max_connections)
+        .connect(url)
+        .await
+}
+
+/// A Redis client whose server answered a PING.
+pub async fn connect_redis(
+    url: &str,
+    settings: &StartupSettings,
+) -> Result<redis::Client, redis::RedisError> {
+    let client = redis::Client::open(url)?;
+    retry("redis", settings, || ping_redis(&client)).await?;
+    Ok(client)
+}
+
+async fn ping_redis(client: &redis::Client) -> redis::RedisResult<()> {
+    let mut con = client.get_async_connection().await?;
+    let _: String = redis::cmd("PING").query_async(&mut con).await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    async fn run<E: std::fmt::Display>(probe: impl Future<Output = Result<(), E>>) -> Check {
+        let start = Instant::now();
+        let res = tokio::time::timeout(Duration::from_secs(2), probe).await;
+        let error = match res {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(_) => Some("timed out".to_string()),
+        };
+        Check {
+            ok: error.is_none(),
+            latency_ms: start.elapsed().as_millis() as u64,
+            error,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Migrations {
+    /// Latest migration applied to the database
+    pub applied: Option<i64>,
+    /// Latest migration this build ships
+    pub expected: Option<i64>,
+}
+
+impl Migrations {
+    pub async fn check<'c>(db: impl sqlx::Executor<'c, Database = MySql>) -> Migrations {
+        Migrations {
+            applied: sqlx::query_scalar(
+                "SELECT MAX(version) FROM _sqlx_migrations WHERE success = 1",
+            )
+            .fetch_one(db)
+            .await
+            .unwrap_or_default(),
+            expected: sqlx::migrate!().migrations.iter().map(|m| m.version).max(),
+        }
+    }
+
+    pub fn current(&self) -> bool {
+        self.applied >= self.expected
+    }
+}
+
+/// `GET /healthz`: the process is up and serving. Checks nothing else, so
+/// an outage of MySQL or Redis doesn't get the container restarted.
+pub async fn healthz() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+/// `GET /readyz`: 200 when MySQL and Redis answer and the schema is
+/// current, 503 otherwise, with the result of each check.
+pub async fn readyz(Extension(state): Extension<MyShared>) -> impl IntoResponse {
+    let db = Check::run(async {
+        sqlx::query("SELECT 1").execute(&state.db).await.map(|_| ())
+    })
+    .await;
+    let redis = Check::run(async {
+        let mut con = state.redis.get_async_connection().await?;
+        // any reply will do, the key needn't exist
+        let _: Option<String> = con.get("readyz").await?;
+        Ok::<_, redis::RedisError>(())
+    })
+    .await;
+    let migrations = Migrations::check(&state.db).await;
+    let ready = db.ok && redis.ok && migrations.current();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::json!({
+        "status": if ready { "ready" } else { "unavailable" },
+        "checks": {
+            "db": db,
+            "redis": redis,
+        },
+        "migrations": migrations,
+    });
+    (status, Json(body))
+}
+
+/// `check-config`: print the settings with secrets hidden and try MySQL
+/// and Redis once each. False when anything is off.
+pub async fn check_config(settings: &Settings) -> bool {
+    println!("{settings:#?}");
+    let mut ok = true;
+    if !std::path::Path::new(&settings.server.static_dir).is_dir() {
+        println!("static_dir: {} is not a directory", settings.server.static_dir);
+        ok = false;
+    }
+    match MySqlConnection::connect(&settings.db_url).await {
+        Ok(mut con) => {
+            let migrations = Migrations::check(&mut con).await;
+            println!(
+                "database: ok, migrations at {:?} of {:?}",
+                migrations.applied, migrations.expected
+            );
+            ok &= migrations.current();
+        }
+        Err(e) => {
+            println!("database: {e}");
+            ok = false;
+        }
+    }
+    let redis = match redis::Client::open(settings.redis_url.as_str()) {
+        Ok(client) => ping_redis(&client).await,
+        Err(e) => Err(e),
+    };
+    match redis {
+        Ok(()) => println!("redis: ok"),
+        Err(e) => {
+            println!("redis: {e}");
+            ok = false;
+        }
+    }
+    ok
+}
@@ -0,0 +1,289 @@
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::Query,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_sessions::extractors::ReadableSession;
+use futures::{channel::mpsc, SinkExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::{MySql, MySqlPool};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{device, MyShared};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    #[default]
+    Csv,
+    Json,
+    Markdown,
+}
+
+impl HistoryFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            HistoryFormat::Csv => "text/csv; charset=utf-8",
+            HistoryFormat::Json => "application/json",
+            HistoryFormat::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            HistoryFormat::Csv => "csv",
+            HistoryFormat::Json => "json",
+            HistoryFormat::Markdown => "md",
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: HistoryFormat,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct HistoryRow {
+    album_id: String,
+    album: String,
+    artist: String,
+    genres: String,
+    click_count: i32,
+    listen_count: i32,
+    create_time: String,
+}
+
+const COLUMNS: [&str; 7] = [
+    "album_id",
+    "album",
+    "artist",
+    "genres",
+    "click_count",
+    "listen_count",
+    "create_time",
+];
+
+impl HistoryRow {
+    fn fields(&self) -> [String; 7] {
+        [
+            self.album_id.clone(),
+            self.album.clone(),
+            self.artist.clone(),
+            self.genres.clone(),
+            self.click_count.to_string(),
+            self.listen_count.to_string(),
+            self.create_time.clone(),
+        ]
+    }
+}
+
+fn csv_line<I: IntoIterator<Item = T>, T: AsRef<[u8]>>(fields: I) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    // writing to a Vec can't fail
+    writer.write_record(fields).unwrap();
+    writer.into_inner().unwrap_or_default()
+}
+
+/// One table cell: pipes escaped and line breaks of any kind as spaces.
+fn markdown_cell(field: &str) -> String {
+    field
+        .replace('|', "\\|")
+        .replace("\r\n", " ")
+        .replace(['\r', '\n'], " ")
+}
+
+fn markdown_line<I: IntoIterator<Item = T>, T: AsRef<str>>(fields: I) -> Vec<u8> {
+    let cells = fields
+        .into_iter()
+        .map(|f| markdown_cell(f.as_ref()))
+        .collect::<Vec<String>>()
+        .join(" | ");
+    format!("| {cells} |\n").into_bytes()
+}
+
+fn opening(format: HistoryFormat) -> Vec<u8> {
+    match format {
+        HistoryFormat::Csv => csv_line(COLUMNS),
+        HistoryFormat::Json => b"[".to_vec(),
+        HistoryFormat::Markdown => {
+            let mut out = b"# Listening history\n\n".to_vec();
+            out.extend(markdown_line(COLUMNS));
+            out.extend(markdown_line(COLUMNS.map(|_| "---")));
+            out
+        }
+    }
+}
+
+fn format_row(format: HistoryFormat, row: &HistoryRow, first: bool) -> Vec<u8> {
+    match format {
+        HistoryFormat::Csv => csv_line(row.fields()),
+        HistoryFormat::Json => {
+            let mut out = if first { b"\n".to_vec() } else { b",\n".to_vec() };
+            out.extend(serde_json::to_vec(row).unwrap_or_default());
+            out
+        }
+        HistoryFormat::Markdown => markdown_line(row.fields()),
+    }
+}
+
+fn closing(format: HistoryFormat) -> Vec<u8> {
+    match format {
+        HistoryFormat::Json => b"\n]\n".to_vec(),
+        _ => vec![],
+    }
+}
+
+/// Feed the rows into `tx` as they come out of the database, so large
+/// histories never sit in memory.
+async fn write_history(
+    db: MySqlPool,
+    user_id: i32,
+    format: HistoryFormat,
+    mut tx: mpsc::Sender<Result<Bytes, std::io::Error>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    tx.send(Ok(opening(format).into())).await?;
+    let mut rows = sqlx::query_as::<MySql, HistoryRow>(
+        r#"SELECT CAST(l.album_id AS CHAR) AS album_id, IFNULL(a.name, '') AS album,
+        IFNULL(a.artist, '') AS artist,
+        IFNULL(CAST((SELECT GROUP_CONCAT(g.genre ORDER BY g.genre_type, g.id SEPARATOR ', ')
+            FROM album_genre g WHERE g.album_id = a.id) AS CHAR), '') AS genres,
+        l.click_count, l.listen_count, CAST(l.create_time AS CHAR) AS create_time
+        FROM user_album_log l LEFT JOIN album a ON a.id = l.album_id
+        WHERE l.user_id = CAST(? AS CHAR) ORDER BY l.create_time DESC, l.id DESC"#,
+    )
+    // user_album_log.user_id is a VARCHAR; compared as a string so its
+    // index is used
+    .bind(user_id)
+    .fetch(&db);
+    let mut first = true;
+    while let Some(r) = rows.try_next().await? {
+        tx.send(Ok(format_row(format, &r, first).into())).await?;
+        first = false;
+    }
+    tx.send(Ok(closing(format).into())).await?;
+    Ok(())
+}
+
+/// Download the user's whole history.
+#[utoipa::path(
+    get,
+    path = "/user_album_log/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Streamed as an attachment", content(
+            ("text/csv" = String),
+            ("application/json" = String),
+            ("text/markdown" = String)
+        )),
+        (status = 400, description = "Not logged in", body = EmptyEnvelope)
+    ),
+    security(("session" = []))
+)]
+pub async fn export_history(
+    Query(query): Query<ExportQuery>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+) -> Response {
+    let user_id = device::session_user_id(&session, &state.db).await;
+    if user_id == 0 {
+        let resp = serde_json::json!({
+            "code": 400,
+            "msg": "you are not logged in"
+        });
+        return (StatusCode::BAD_REQUEST, Json(resp)).into_response();
+    }
+    let format = query.format;
+
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut err_tx = tx.clone();
+        if let Err(e) = write_history(state.db, user_id, format, tx).await {
+            tracing::error!("export history error: {e}");
+            // cut the download short rather than hand out a truncated file as complete
+            let _ = err_tx.send(Err(std::io::Error::other(e.to_string()))).await;
+        }
+    });
+
+    let disposition = format!(
+        "attachment; filename=\"listening-history.{}\"",
+        format.extension()
+    );
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        StreamBody::new(rx),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row() -> HistoryRow {
+        HistoryRow {
+            album_id: "12".to_string(),
+            album: "Loveless | Remastered".to_string(),
+            artist: "My Bloody Valentine".to_string(),
+            genres: "Shoegaze, Noise Pop".to_string(),
+            click_count: 3,
+            listen_count: 2,
+            create_time: "2023-04-01 12:00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn markdown_cells_stay_on_one_line() {
+        assert_eq!(markdown_cell("a|b"), "a\\|b");
+        assert_eq!(markdown_cell("a\nb"), "a b");
+        assert_eq!(markdown_cell("a\r\nb"), "a b");
+        assert_eq!(markdown_cell("a\rb"), "a b");
+    }
+
+    #[test]
+    fn csv_lines_quote_what_needs_it() {
+        assert_eq!(csv_line(["a", "b c", "d,e", "f\"g"]), b"a,b c,\"d,e\",\"f\"\"g\"\n");
+        assert_eq!(csv_line(["line\nbreak"]), b"\"line\nbreak\"\n");
+    }
+
+    #[test]
+    fn csv_rows_follow_the_header() {
+        assert_eq!(
+            opening(HistoryFormat::Csv),
+            b"album_id,album,artist,genres,click_count,listen_count,create_time\n"
+        );
+        assert_eq!(
+            String::from_utf8(format_row(HistoryFormat::Csv, &row(), true)).unwrap(),
+            "12,Loveless | Remastered,My Bloody Valentine,\"Shoegaze, Noise Pop\",3,2,2023-04-01 12:00:00\n"
+        );
+    }
+
+    #[test]
+    fn json_rows_make_one_array() {
+        let mut out = opening(HistoryFormat::Json);
+        out.extend(format_row(HistoryFormat::Json, &row(), true));
+        out.extend(format_row(HistoryFormat::Json, &row(), false));
+        out.extend(closing(HistoryFormat::Json));
+        let parsed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed.as_array().unwrap().len(), 2);
+        assert_eq!(parsed[1]["album"], "Loveless | Remastered");
+        assert_eq!(parsed[1]["click_count"], 3);
+    }
+
+    #[test]
+    fn markdown_rows_escape_pipes() {
+        let table = String::from_utf8(opening(HistoryFormat::Markdown)).unwrap();
+        assert!(table.ends_with("| --- | --- | --- | --- | --- | --- | --- |\n"));
+        assert_eq!(
+            String::from_utf8(format_row(HistoryFormat::Markdown, &row(), true)).unwrap(),
+            "| 12 | Loveless \\| Remastered | My Bloody Valentine | Shoegaze, Noise Pop | 3 | 2 | 2023-04-01 12:00:00 |\n"
+        );
+        assert!(closing(HistoryFormat::Markdown).is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified};
+use redis::AsyncCommands;
+use sha3::{Digest, Sha3_256};
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::MyShared;
+
+/// Unix time of the last catalogue change: albums, their details and
+/// genres, and the genre tree. Bumped by every writer of those tables.
+const CATALOG_MODIFIED_KEY: &str = "catalog_modified";
+
+/// Genre and artist listings only change through the admin pages.
+const CATALOG_CACHE_CONTROL: &str = "public, max-age=300";
+/// Album pages carry the user's provider ranking, and every view is logged.
+pub const ALBUM_CACHE_CONTROL: &str = "private, no-cache";
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+const PAGE_CACHE_CONTROL: &str = "no-cache";
+
+/// Response validators and the conditional request headers to check them
+/// against.
+pub struct Validators {
+    etag: Option<ETag>,
+    last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// From the catalogue stamp, so a revalidation costs a Redis read and no
+    /// query. `None` when Redis is down.
+    pub async fn catalog(redis: &redis::Client) -> Option<Validators> {
+        let modified = catalog_modified(redis).await?;
+        Some(Validators {
+            etag: format!("W/\"c{modified}\"").parse().ok(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(modified)),
+        })
+    }
+
+    /// From a tag the caller derives from whatever the response is made of.
+    pub fn tag(tag: &str) -> Validators {
+        Validators {
+            etag: format!("\"{tag}\"").parse().ok(),
+            last_modified: None,
+        }
+    }
+
+    /// From the rendered body, for responses that differ per user.
+    pub fn body(body: &[u8]) -> Validators {
+        let hash = format!("{:x}", Sha3_256::digest(body));
+        Validators {
+            etag: format!("\"{}\"", &hash[..32]).parse().ok(),
+            last_modified: None,
+        }
+    }
+
+    /// Whether the client's copy is still good. If-Modified-Since only
+    /// counts when there is no If-None-Match.
+    pub fn fresh(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            return match &self.etag {
+                Some(etag) => !if_none_match.precondition_passes(etag),
+                None => false,
+            };
+        }
+        match (headers.typed_get::<IfModifiedSince>(), self.last_modified) {
+            (Some(since), Some(modified)) => !since.is_modified(modified),
+            _ => false,
+        }
+    }
+
+    fn apply(&self, headers: &mut HeaderMap, cache_control: &'static str) {
+        if let Some(etag) = &self.etag {
+            headers.typed_insert(etag.clone());
+        }
+        if let Some(modified) = self.last_modified {
+            headers.typed_insert(LastModified::from(modified));
+        }
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    }
+
+    pub fn not_modified(&self, cache_control: &'static str) -> Response {
+        let mut resp = StatusCode::NOT_MODIFIED.into_response();
+        self.apply(resp.headers_mut(), cache_control);
+        resp
+    }
+
+    /// Add the validators to a successful response; errors go out uncached.
+    pub fn respond(&self, resp: impl IntoResponse, cache_control: &'static str) -> Response {
+        let mut resp = resp.into_response();
+        if resp.status() == StatusCode::OK {
+            self.apply(resp.headers_mut(), cache_control);
+        }
+        resp
+    }
+}
+
+/// Answer a catalogue read with 304 when the client's copy is current, and
+/// only run `render` otherwise.
+pub async fn catalog<R: IntoResponse>(
+    redis: &redis::Client,
+    headers: &HeaderMap,
+    render: impl Future<Output = R>,
+) -> Response {
+    match Validators::catalog(redis).await {
+        Some(validators) if validators.fresh(headers) => {
+            validators.not_modified(CATALOG_CACHE_CONTROL)
+        }
+        Some(validators) => validators.respond(render.await, CATALOG_CACHE_CONTROL),
+        None => render.await.into_response(),
+    }
+}
+
+/// The catalogue stamp, started at now when Redis has none yet.
+async fn catalog_modified(redis: &redis::Client) -> Option<u64> {
+    let mut con = redis.get_async_connection().await.ok()?;
+    let now = unix_now();
+    let _: bool = con.set_nx(CATALOG_MODIFIED_KEY, now).await.ok()?;
+    con.get(CATALOG_MODIFIED_KEY).await.ok()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Move the stamp to now, and at least one second past its old value so two
+/// writes within a second still get different validators.
+const TOUCH_SCRIPT: &str = r#"
+local old = tonumber(redis.call('GET', KEYS[1]) or '0')
+local now = math.max(old + 1, tonumber(ARGV[1]))
+redis.call('SET', KEYS[1], now)
+return now
+"#;
+
+/// Mark the catalogue as changed, so cached listings revalidate.
+pub async fn touch_catalog(redis: &redis::Client) {
+    let touched: redis::RedisResult<u64> = match redis.get_async_connection().await {
+        Ok(mut con) => {
+            redis::Script::new(TOUCH_SCRIPT)
+                .key(CATALOG_MODIFIED_KEY)
+                .arg(unix_now())
+                .invoke_async(&mut con)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = touched {
+        tracing::error!("touch catalog error: {e}");
+    }
+}
+
+/// Route layer for the admin routes: a successful write changes the
+/// catalogue.
+pub async fn touch_catalog_on_write(
+    Extension(state): Extension<MyShared>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let write = req.method() != axum::http::Method::GET;
+    let resp = next.run(req).await;
+    if write && resp.status() == StatusCode::OK {
+        touch_catalog(&state.redis).await;
+    }
+    resp
+}
+
+/// Trunk names build output `name-<hash>.ext`; those never change.
+fn is_hashed_asset(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let Some((stem, ext)) = name.rsplit_once('.') else {
+        return false;
+    };
+    // wasm-bindgen output is `name-<hash>_bg.wasm`
+    let stem = stem.strip_suffix("_bg").unwrap_or(stem);
+    let hash = stem.rsplit_once('-').map(|(_, hash)| hash).unwrap_or_default();
+    matches!(ext, "js" | "wasm" | "css")
+        && hash.len() >= 16
+        && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Cache headers for the static files: forever for hashed assets, and
+/// always revalidate the rest so a deploy shows up on the next load.
+pub async fn static_cache_control(req: Request<Body>, next: Next<Body>) -> Response {
+    let cache_control = if is_hashed_asset(req.uri().path()) {
+        IMMUTABLE_CACHE_CONTROL
+    } else {
+        PAGE_CACHE_CONTROL
+    };
+    let mut resp = next.run(req).await;
+    if resp.status().is_success() || resp.status() == StatusCode::NOT_MODIFIED {
+        resp.headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    fn catalog_at(modified: u64) -> Validators {
+        Validators {
+            etag: format!("W/\"c{modified}\"").parse().ok(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(modified)),
+        }
+    }
+
+    #[test]
+    fn fresh_matches_the_etag() {
+        let validators = catalog_at(1_700_000_000);
+        assert!(validators.fresh(&headers(&[(header::IF_NONE_MATCH, "W/\"c1700000000\"")])));
+        assert!(validators.fresh(&headers(&[(header::IF_NONE_MATCH, "*")])));
+        assert!(!validators.fresh(&headers(&[(header::IF_NONE_MATCH, "W/\"c1699999999\"")])));
+        assert!(!validators.fresh(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn fresh_prefers_if_none_match_over_if_modified_since() {
+        let validators = catalog_at(1_700_000_000);
+        let since = "Tue, 14 Nov 2023 22:13:20 GMT";
+        assert!(validators.fresh(&headers(&[(header::IF_MODIFIED_SINCE, since)])));
+        assert!(!validators.fresh(&headers(&[
+            (header::IF_MODIFIED_SINCE, since),
+            (header::IF_NONE_MATCH, "W/\"c1\""),
+        ])));
+    }
+
+    #[test]
+    fn fresh_compares_if_modified_since() {
+        let validators = catalog_at(1_700_000_000);
+        let earlier = "Tue, 14 Nov 2023 22:13:19 GMT";
+        let later = "Tue, 14 Nov 2023 22:13:21 GMT";
+        assert!(!validators.fresh(&headers(&[(header::IF_MODIFIED_SINCE, earlier)])));
+        assert!(validators.fresh(&headers(&[(header::IF_MODIFIED_SINCE, later)])));
+        let body = Validators::body(b"{}");
+        assert!(!body.fresh(&headers(&[(header::IF_MODIFIED_SINCE, later)])));
+    }
+
+    #[test]
+    fn hashed_assets_are_trunk_output() {
+        assert!(is_hashed_asset("/index-4f8a9c2b1d3e5f60.js"));
+        assert!(is_hashed_asset("/static/index-4f8a9c2b1d3e5f60_bg.wasm"));
+        assert!(is_hashed_asset("/style-0123456789abcdef0123.css"));
+        assert!(!is_hashed_asset("/index.html"));
+        assert!(!is_hashed_asset("/index.js"));
+        assert!(!is_hashed_asset("/index-4f8a9c2b.js"));
+        assert!(!is_hashed_asset("/index-4f8a9c2b1d3e5f6g.js"));
+        assert!(!is_hashed_asset("/logo-4f8a9c2b1d3e5f60.png"));
+    }
+}
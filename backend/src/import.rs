@@ -0,0 +1,665 @@
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::{MySql, MySqlPool};
+use sqlx::{Connection, MySqlConnection, Row, Transaction};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use crate::{
+    admin::{ROLE_ADMIN, ROLE_USER},
+    media,
+};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ImportFormat {
+    Csv,
+    Jsonl,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// CSV file with one album per row, or JSON Lines as written by `export`
+    pub file: PathBuf,
+    /// Input format, guessed from the file extension when omitted
+    #[arg(long, value_enum)]
+    pub format: Option<ImportFormat>,
+    /// Validate and run every row in one transaction, then roll back
+    /// instead of committing
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Rows written per transaction
+    #[arg(long, default_value_t = 500)]
+    pub batch_size: usize,
+}
+
+/// One album as it appears in a JSON Lines import.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AlbumRecord {
+    pub key: String,
+    pub name: String,
+    pub artist: String,
+    pub cover: String,
+    pub media_url: HashMap<String, serde_json::Value>,
+    pub primary_genres: Vec<String>,
+    pub secondary_genres: Vec<String>,
+    pub descriptors: String,
+    pub language: String,
+    pub released: String,
+    pub rate: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, sqlx::FromRow)]
+#[serde(default)]
+pub struct GenreRecord {
+    pub name: String,
+    pub key_name: String,
+    pub parents: String,
+    pub path: String,
+}
+
+/// A user without credentials. Imported users can't sign in until their
+/// password is reset.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UserRecord {
+    pub username: String,
+    pub email: String,
+    pub genre_data: Option<String>,
+    pub fresh_time: i32,
+    pub role: String,
+}
+
+/// A `user_album_log` row, pointing at its user by username and at its album
+/// by external key.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ListenRecord {
+    pub user: String,
+    pub album: String,
+    pub album_genre: String,
+    pub click_count: i32,
+    pub listen_count: i32,
+    pub create_time: String,
+}
+
+/// A JSON Lines row. Rows without a `type` are albums.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Genre(GenreRecord),
+    Album(AlbumRecord),
+    User(UserRecord),
+    Listen(ListenRecord),
+}
+
+/// CSV rows carry the same columns flattened: `media_url` is a JSON object and
+/// the genre columns are `;` separated.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CsvRecord {
+    key: String,
+    name: String,
+    artist: String,
+    cover: String,
+    media_url: String,
+    primary_genres: String,
+    secondary_genres: String,
+    descriptors: String,
+    language: String,
+    released: String,
+    rate: String,
+}
+
+fn split_genres(genres: &str) -> Vec<String> {
+    genres
+        .split(';')
+        .map(|g| g.trim().to_string())
+        .filter(|g| !g.is_empty())
+        .collect()
+}
+
+impl TryFrom<CsvRecord> for AlbumRecord {
+    type Error = String;
+
+    fn try_from(row: CsvRecord) -> Result<Self, Self::Error> {
+        let media_url = if row.media_url.trim().is_empty() {
+            HashMap::new()
+        } else {
+            serde_json::from_str(&row.media_url).map_err(|e| format!("media_url: {e}"))?
+        };
+        Ok(AlbumRecord {
+            key: row.key,
+            name: row.name,
+            artist: row.artist,
+            cover: row.cover,
+            media_url,
+            primary_genres: split_genres(&row.primary_genres),
+            secondary_genres: split_genres(&row.secondary_genres),
+            descriptors: row.descriptors,
+            language: row.language,
+            released: row.released,
+            rate: row.rate,
+        })
+    }
+}
+
+impl AlbumRecord {
+    /// Trim and check a record before it reaches the database.
+    pub fn validate(mut self) -> Result<Self, String> {
+        self.key = self.key.trim().to_string();
+        self.name = self.name.trim().to_string();
+        self.artist = self.artist.trim().to_string();
+        self.cover = self.cover.trim().to_string();
+        if self.key.is_empty() {
+            return Err("key is required".to_string());
+        }
+        if self.key.chars().count() > 128 {
+            return Err("key is longer than 128 characters".to_string());
+        }
+        if self.name.is_empty() {
+            return Err("name is required".to_string());
+        }
+        if self.artist.is_empty() {
+            return Err("artist is required".to_string());
+        }
+        if self.cover.contains(char::is_whitespace) {
+            return Err(format!("cover {:?} is not a url", self.cover));
+        }
+        media::validate(&self.media_url)?;
+        self.rate = self.rate.trim().to_string();
+        if !self.rate.is_empty() {
+            match self.rate.parse::<f32>() {
+                Ok(r) if (0.0..=5.0).contains(&r) => {}
+                _ => return Err(format!("rate {:?} is not a number between 0 and 5", self.rate)),
+            }
+        }
+        Ok(self)
+    }
+
+    fn genres(&self) -> impl Iterator<Item = (&str, &'static str)> {
+        self.primary_genres
+            .iter()
+            .map(|g| (g.as_str(), "pri"))
+            .chain(self.secondary_genres.iter().map(|g| (g.as_str(), "sec")))
+    }
+}
+
+impl Record {
+    fn key(&self) -> &str {
+        match self {
+            Record::Genre(genre) => &genre.name,
+            Record::Album(album) => &album.key,
+            Record::User(user) => &user.username,
+            Record::Listen(listen) => &listen.user,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Record::Genre(_) => "genre",
+            Record::Album(_) => "album",
+            Record::User(_) => "user",
+            Record::Listen(_) => "listen",
+        }
+    }
+
+    pub fn validate(self) -> Result<Self, String> {
+        match self {
+            Record::Album(album) => album.validate().map(Record::Album),
+            Record::Genre(genre) => {
+                if genre.name.trim().is_empty() || genre.key_name.is_empty() || genre.path.is_empty() {
+                    return Err("genre name, key_name and path are required".to_string());
+                }
+                Ok(Record::Genre(genre))
+            }
+            Record::User(user) => {
+                if user.username.trim().is_empty() {
+                    return Err("username is required".to_string());
+                }
+                if !["", ROLE_ADMIN, ROLE_USER].contains(&user.role.as_str()) {
+                    return Err(format!("role must be {ROLE_ADMIN} or {ROLE_USER}"));
+                }
+                Ok(Record::User(user))
+            }
+            Record::Listen(listen) => {
+                if listen.user.is_empty() || listen.album.is_empty() {
+                    return Err("listen user and album are required".to_string());
+                }
+                Ok(Record::Listen(listen))
+            }
+        }
+    }
+}
+
+pub(crate) fn parse_line(line: &str) -> Result<Record, String> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    if value.get("type").is_some() {
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_value(value)
+            .map(Record::Album)
+            .map_err(|e| e.to_string())
+    }
+}
+
+type Records = Box<dyn Iterator<Item = (usize, Result<Record, String>)>>;
+
+fn read_records(file: File, format: ImportFormat) -> Records {
+    match format {
+        ImportFormat::Csv => Box::new(
+            csv::Reader::from_reader(file)
+                .into_deserialize::<CsvRecord>()
+                .enumerate()
+                // line 1 is the header
+                .map(|(i, row)| {
+                    let row = row
+                        .map_err(|e| e.to_string())
+                        .and_then(AlbumRecord::try_from)
+                        .map(Record::Album);
+                    (i + 2, row)
+                }),
+        ),
+        ImportFormat::Jsonl => Box::new(
+            BufReader::new(file)
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|(i, line)| {
+                    let record = line
+                        .map_err(|e| e.to_string())
+                        .and_then(|line| parse_line(&line));
+                    (i + 1, record)
+                }),
+        ),
+    }
+}
+
+/// Lower case ascii key used for genres created on the fly.
+pub fn slug(name: &str) -> String {
+    let mut key = String::new();
+    for c in name.to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            key.push(c);
+        } else if !key.ends_with('-') {
+            key.push('-');
+        }
+    }
+    key.trim_matches('-').to_string()
+}
+
+#[derive(Default)]
+struct Report {
+    inserted: usize,
+    updated: usize,
+    failed: usize,
+}
+
+impl Report {
+    fn error(&mut self, line: usize, key: &str, msg: &str) {
+        self.failed += 1;
+        println!("line {line} [{key}]: error: {msg}");
+    }
+}
+
+/// What happened to a row: the id it was written to, whether it was new, and
+/// notes for the report.
+struct Outcome {
+    id: i32,
+    inserted: bool,
+    notes: Vec<String>,
+}
+
+/// A row that can't be written, e.g. a listen whose album is missing.
+enum RowError {
+    Invalid(String),
+    Db(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RowError {
+    fn from(e: sqlx::Error) -> Self {
+        RowError::Db(e)
+    }
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowError::Invalid(msg) => write!(f, "{msg}"),
+            RowError::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+async fn upsert(con: &mut MySqlConnection, record: &Record) -> Result<Outcome, RowError> {
+    match record {
+        Record::Genre(genre) => Ok(upsert_genre(con, genre).await?),
+        Record::Album(album) => Ok(upsert_album(con, album).await?),
+        Record::User(user) => Ok(upsert_user(con, user).await?),
+        Record::Listen(listen) => upsert_listen(con, listen).await,
+    }
+}
+
+/// Genres are matched by name, the key `album_genre` refers to them by.
+async fn upsert_genre(con: &mut MySqlConnection, record: &GenreRecord) -> Result<Outcome, sqlx::Error> {
+    let name = record.name.trim();
+    let existing = sqlx::query("SELECT id FROM genres WHERE name = ?")
+        .bind(name)
+        .fetch_optional(&mut *con)
+        .await?;
+    let (id, inserted) = match existing {
+        Some(row) => {
+            let id: i32 = row.get(0);
+            sqlx::query("UPDATE genres SET key_name = ?, parents = ?, path = ? WHERE id = ?")
+                .bind(&record.key_name)
+                .bind(&record.parents)
+                .bind(&record.path)
+                .bind(id)
+                .execute(&mut *con)
+                .await?;
+            (id, false)
+        }
+        None => {
+            let id = sqlx::query("INSERT INTO genres (name, key_name, parents, path) VALUES (?, ?, ?, ?)")
+                .bind(name)
+                .bind(&record.key_name)
+                .bind(&record.parents)
+                .bind(&record.path)
+                .execute(&mut *con)
+                .await?
+                .last_insert_id() as i32;
+            (id, true)
+        }
+    };
+    Ok(Outcome { id, inserted, notes: vec![] })
+}
+
+/// Users are matched by username and never get a usable password. The role
+/// only applies to new users, so a dump can't promote an existing account.
+async fn upsert_user(con: &mut MySqlConnection, record: &UserRecord) -> Result<Outcome, sqlx::Error> {
+    let role = if record.role.is_empty() { ROLE_USER } else { &record.role };
+    let fresh_time = if record.fresh_time > 0 { record.fresh_time } else { 10 };
+    let existing = sqlx::query("SELECT id FROM rym_user WHERE username = ?")
+        .bind(record.username.trim())
+        .fetch_optional(&mut *con)
+        .await?;
+    let (id, inserted) = match existing {
+        Some(row) => {
+            let id: i32 = row.get(0);
+            sqlx::query("UPDATE rym_user SET genre_data = ?, fresh_time = ? WHERE id = ?")
+                .bind(&record.genre_data)
+                .bind(fresh_time)
+                .bind(id)
+                .execute(&mut *con)
+                .await?;
+            (id, false)
+        }
+        None => {
+            let id = sqlx::query(
+                r#"INSERT INTO rym_user (username, email, password, genre_data, fresh_time, role)
+                VALUES (?, ?, '', ?, ?, ?)"#,
+            )
+            .bind(record.username.trim())
+            .bind(&record.email)
+            .bind(&record.genre_data)
+            .bind(fresh_time)
+            .bind(role)
+            .execute(&mut *con)
+            .await?
+            .last_insert_id() as i32;
+            (id, true)
+        }
+    };
+    Ok(Outcome { id, inserted, notes: vec![] })
+}
+
+/// Listens are matched by user and album, like the album page does.
+async fn upsert_listen(con: &mut MySqlConnection, record: &ListenRecord) -> Result<Outcome, RowError> {
+    let user_id: i32 = match sqlx::query("SELECT id FROM rym_user WHERE username = ?")
+        .bind(&record.user)
+        .fetch_optional(&mut *con)
+        .await?
+    {
+        Some(row) => row.get(0),
+        None => return Err(RowError::Invalid(format!("unknown user {:?}", record.user))),
+    };
+    let album_id: i32 = match sqlx::query("SELECT id FROM album WHERE external_key = ?")
+        .bind(&record.album)
+        .fetch_optional(&mut *con)
+        .await?
+    {
+        Some(row) => row.get(0),
+        None => return Err(RowError::Invalid(format!("unknown album {:?}", record.album))),
+    };
+    // user_album_log stores ids as strings
+    let (user_key, album_key) = (user_id.to_string(), album_id.to_string());
+    let create_time = if record.create_time.is_empty() {
+        None
+    } else {
+        Some(&record.create_time)
+    };
+    let existing = sqlx::query("SELECT id FROM user_album_log WHERE user_id = ? AND album_id = ?")
+        .bind(&user_key)
+        .bind(&album_key)
+        .fetch_optional(&mut *con)
+        .await?;
+    let (id, inserted) = match existing {
+        Some(row) => {
+            let id: i32 = row.get(0);
+            sqlx::query(
+                r#"UPDATE user_album_log SET album_genre = ?, click_count = ?, listen_count = ?,
+                create_time = IFNULL(?, create_time) WHERE id = ?"#,
+            )
+            .bind(&record.album_genre)
+            .bind(record.click_count)
+            .bind(record.listen_count)
+            .bind(create_time)
+            .bind(id)
+            .execute(&mut *con)
+            .await?;
+            (id, false)
+        }
+        None => {
+            let id = sqlx::query(
+                r#"INSERT INTO user_album_log (user_id, album_id, album_genre, click_count, listen_count, create_time)
+                VALUES (?, ?, ?, ?, ?, IFNULL(?, NOW()))"#,
+            )
+            .bind(&user_key)
+            .bind(&album_key)
+            .bind(&record.album_genre)
+            .bind(record.click_count)
+            .bind(record.listen_count)
+            .bind(create_time)
+            .execute(&mut *con)
+            .await?
+            .last_insert_id() as i32;
+            (id, true)
+        }
+    };
+    Ok(Outcome { id, inserted, notes: vec![] })
+}
+
+/// Insert or update one album by its external key.
+async fn upsert_album(con: &mut MySqlConnection, record: &AlbumRecord) -> Result<Outcome, sqlx::Error> {
+    let mut notes = vec![];
+    let media_url = sqlx::types::Json(&record.media_url);
+    let existing = sqlx::query("SELECT id FROM album WHERE external_key = ?")
+        .bind(&record.key)
+        .fetch_optional(&mut *con)
+        .await?;
+    let (album_id, inserted) = match existing {
+        Some(row) => {
+            let id: i32 = row.get(0);
+            sqlx::query("UPDATE album SET name = ?, artist = ?, cover = ?, media_url = ? WHERE id = ?")
+                .bind(&record.name)
+                .bind(&record.artist)
+                .bind(&record.cover)
+                .bind(media_url)
+                .bind(id)
+                .execute(&mut *con)
+                .await?;
+            (id, false)
+        }
+        None => {
+            let id = sqlx::query(
+                "INSERT INTO album (external_key, name, artist, cover, media_url) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(&record.key)
+            .bind(&record.name)
+            .bind(&record.artist)
+            .bind(&record.cover)
+            .bind(media_url)
+            .execute(&mut *con)
+            .await?
+            .last_insert_id() as i32;
+            (id, true)
+        }
+    };
+
+    sqlx::query("DELETE FROM album_detail WHERE album_id = ?")
+        .bind(album_id)
+        .execute(&mut *con)
+        .await?;
+    sqlx::query(
+        r#"INSERT INTO album_detail (album_id, descriptors, released, language, rate)
+        VALUES (?, ?, ?, ?, ?)"#,
+    )
+    .bind(album_id)
+    .bind(&record.descriptors)
+    .bind(&record.released)
+    .bind(&record.language)
+    .bind(&record.rate)
+    .execute(&mut *con)
+    .await?;
+
+    sqlx::query("DELETE FROM album_genre WHERE album_id = ?")
+        .bind(album_id)
+        .execute(&mut *con)
+        .await?;
+    for (genre, genre_type) in record.genres() {
+        let known = sqlx::query("SELECT id FROM genres WHERE name = ?")
+            .bind(genre)
+            .fetch_optional(&mut *con)
+            .await?;
+        if known.is_none() {
+            let key = slug(genre);
+            sqlx::query("INSERT INTO genres (name, key_name, parents, path) VALUES (?, ?, '', ?)")
+                .bind(genre)
+                .bind(&key)
+                .bind(&key)
+                .execute(&mut *con)
+                .await?;
+            notes.push(format!("created top level genre {genre:?}"));
+        }
+        sqlx::query("INSERT INTO album_genre (album_id, genre, genre_type) VALUES (?, ?, ?)")
+            .bind(album_id)
+            .bind(genre)
+            .bind(genre_type)
+            .execute(&mut *con)
+            .await?;
+    }
+    Ok(Outcome {
+        id: album_id,
+        inserted,
+        notes,
+    })
+}
+
+async fn run_batch(
+    tx: &mut Transaction<'_, MySql>,
+    batch: &mut Vec<(usize, Record)>,
+    dry_run: bool,
+    report: &mut Report,
+) -> Result<(), sqlx::Error> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let verb = |inserted: bool| match (dry_run, inserted) {
+        (false, true) => "inserted",
+        (false, false) => "updated",
+        (true, true) => "would insert",
+        (true, false) => "would update",
+    };
+    for (line, record) in batch.drain(..) {
+        // a savepoint per row keeps one bad row from sinking the batch
+        let mut row_tx = tx.begin().await?;
+        match upsert(&mut row_tx, &record).await {
+            Ok(outcome) => {
+                row_tx.commit().await?;
+                if outcome.inserted {
+                    report.inserted += 1;
+                } else {
+                    report.updated += 1;
+                }
+                let mut msg = format!(
+                    "line {line} [{}]: {} {} {}",
+                    record.key(),
+                    verb(outcome.inserted),
+                    record.kind(),
+                    outcome.id
+                );
+                for note in outcome.notes {
+                    msg.push_str("; ");
+                    msg.push_str(&note);
+                }
+                println!("{msg}");
+            }
+            Err(e) => {
+                row_tx.rollback().await?;
+                report.error(line, record.key(), &e.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Import rows from `args.file` and print a report line per row. Returns
+/// false if any row failed.
+pub async fn run(pool: &MySqlPool, args: ImportArgs) -> Result<bool, Box<dyn std::error::Error>> {
+    let format = match args.format {
+        Some(format) => format,
+        None => match args.file.extension().and_then(|e| e.to_str()) {
+            Some("csv") => ImportFormat::Csv,
+            Some("jsonl") | Some("json") | Some("ndjson") => ImportFormat::Jsonl,
+            _ => return Err("can't guess the format, pass --format".into()),
+        },
+    };
+    let batch_size = args.batch_size.max(1);
+    let file = File::open(&args.file)?;
+
+    let mut report = Report::default();
+    let mut batch = Vec::with_capacity(batch_size);
+    // a transaction per batch, but one for the whole dry run so later rows
+    // see what earlier batches would have written
+    let mut tx = pool.begin().await?;
+    for (line, record) in read_records(file, format) {
+        let key = record.as_ref().map(|r| r.key().to_string()).unwrap_or_default();
+        match record.and_then(Record::validate) {
+            Ok(record) => batch.push((line, record)),
+            Err(e) => report.error(line, &key, &e),
+        }
+        if batch.len() >= batch_size {
+            run_batch(&mut tx, &mut batch, args.dry_run, &mut report).await?;
+            if !args.dry_run {
+                std::mem::replace(&mut tx, pool.begin().await?).commit().await?;
+            }
+        }
+    }
+    run_batch(&mut tx, &mut batch, args.dry_run, &mut report).await?;
+    if args.dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+
+    println!(
+        "{}{} inserted, {} updated, {} failed",
+        if args.dry_run { "dry run: " } else { "" },
+        report.inserted,
+        report.updated,
+        report.failed
+    );
+    Ok(report.failed == 0)
+}
@@ -0,0 +1,598 @@
+//! Background jobs on the cron schedules in `[jobs]`. A Redis lock per job
+//! keeps replicas from running the same job at once, a claim per scheduled
+//! slot keeps them from running it once each, and every run is recorded in
+//! `job_run`. A run renews its lock while it lasts, and no run starts once
+//! the server is shutting down.
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::Serialize;
+use sqlx::mysql::MySqlPool;
+use sqlx::Row;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::{
+    device, feed, link_check,
+    settings::{parse_schedule, JobSettings, LinkCheckSettings},
+    Pagination,
+};
+
+/// Deletes the lock only if it still holds our token, so a run that
+/// outlived its lock can't release another replica's.
+const UNLOCK: &str = r#"if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("del", KEYS[1])
+else
+    return 0
+end"#;
+
+/// Extends the lock by `ARGV[2]` seconds, again only while it holds our
+/// token.
+const EXTEND: &str = r#"if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("expire", KEYS[1], ARGV[2])
+else
+    return 0
+end"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Job {
+    WarmFeed,
+    RollupListens,
+    PruneDevices,
+    CheckLinks,
+}
+
+impl Job {
+    pub const ALL: [Job; 4] = [
+        Job::WarmFeed,
+        Job::RollupListens,
+        Job::PruneDevices,
+        Job::CheckLinks,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Job::WarmFeed => "warm_feed",
+            Job::RollupListens => "rollup_listens",
+            Job::PruneDevices => "prune_devices",
+            Job::CheckLinks => "check_links",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Job> {
+        Job::ALL.into_iter().find(|job| job.name() == name)
+    }
+
+    fn schedule(self, settings: &JobSettings) -> &str {
+        match self {
+            Job::WarmFeed => &settings.warm_feed,
+            Job::RollupListens => &settings.rollup_listens,
+            Job::PruneDevices => &settings.prune_devices,
+            Job::CheckLinks => &settings.check_links,
+        }
+    }
+
+    fn lock_key(self) -> String {
+        format!("job_lock:{}", self.name())
+    }
+
+    fn slot_key(self, slot: DateTime<Utc>) -> String {
+        format!("job_slot:{}:{}", self.name(), slot.timestamp())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Trigger {
+    Schedule,
+    Manual,
+}
+
+impl Trigger {
+    fn as_str(self) -> &'static str {
+        match self {
+            Trigger::Schedule => "schedule",
+            Trigger::Manual => "manual",
+        }
+    }
+}
+
+/// The last run of a job, from `job_run`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct JobRun {
+    pub id: i32,
+    pub triggered_by: String,
+    pub status: String,
+    pub detail: Option<String>,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobStatus {
+    pub name: &'static str,
+    /// Empty when the job only runs when triggered
+    pub schedule: String,
+    /// UTC, RFC 3339
+    pub next_run: Option<String>,
+    /// Some replica holds the job's lock
+    pub running: bool,
+    pub last_run: Option<JobRun>,
+}
+
+pub struct Scheduler {
+    db: MySqlPool,
+    redis: redis::Client,
+    settings: JobSettings,
+    link_check: LinkCheckSettings,
+    stopped: AtomicBool,
+    /// Wakes the schedule loops once `stopped` is set
+    wake: Notify,
+}
+
+impl Scheduler {
+    pub fn new(
+        db: MySqlPool,
+        redis: redis::Client,
+        settings: JobSettings,
+        link_check: LinkCheckSettings,
+    ) -> Self {
+        Scheduler {
+            db,
+            redis,
+            settings,
+            link_check,
+            stopped: AtomicBool::new(false),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Start a task for each job with a schedule. Once `stopping` fires no
+    /// further run starts; runs in flight go on until the process exits.
+    pub fn spawn(self: &Arc<Self>, stopping: Arc<Notify>) {
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            stopping.notified().await;
+            scheduler.stopped.store(true, Ordering::SeqCst);
+            scheduler.wake.notify_waiters();
+        });
+        if !self.settings.enabled {
+            return;
+        }
+        for job in Job::ALL {
+            // checked by Settings::new
+            let Ok(Some(schedule)) = parse_schedule(job.schedule(&self.settings)) else {
+                continue;
+            };
+            let scheduler = self.clone();
+            tokio::spawn(async move {
+                // from now each time, so a long run skips the slots it
+                // overran rather than catching up on them
+                while let Some(next) = schedule.upcoming(Utc).next() {
+                    let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                    // registered before the check, so a stop in between
+                    // still wakes us
+                    let woken = scheduler.wake.notified();
+                    if scheduler.stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        _ = woken => break,
+                    }
+                    if !scheduler.claim_slot(job, next).await {
+                        tracing::debug!("job {} ran elsewhere at {next}", job.name());
+                        continue;
+                    }
+                    match scheduler.lock(job).await {
+                        Some(token) => scheduler.run(job, Trigger::Schedule, token).await,
+                        None => tracing::debug!("job {} is running elsewhere", job.name()),
+                    }
+                }
+            });
+        }
+    }
+
+    /// Start `job` in the background. False when it's already running, or
+    /// the server is shutting down.
+    pub async fn trigger(self: &Arc<Self>, job: Job) -> bool {
+        if self.stopped.load(Ordering::SeqCst) {
+            return false;
+        }
+        let Some(token) = self.lock(job).await else {
+            return false;
+        };
+        let scheduler = self.clone();
+        tokio::spawn(async move { scheduler.run(job, Trigger::Manual, token).await });
+        true
+    }
+
+    /// Set `key` to `value` for `lock_ttl_secs` unless it exists. False
+    /// when it does or Redis is down, as going ahead without the key could
+    /// run the job twice.
+    async fn set_nx(&self, job: Job, key: String, value: &str) -> bool {
+        let set: redis::RedisResult<Option<String>> = async {
+            let mut con = self.redis.get_async_connection().await?;
+            redis::cmd("SET")
+                .arg(key)
+                .arg(value)
+                .arg("NX")
+                .arg("EX")
+                .arg(self.settings.lock_ttl_secs)
+                .query_async(&mut con)
+                .await
+        }
+        .await;
+        match set {
+            Ok(set) => set.is_some(),
+            Err(e) => {
+                tracing::error!("job {} lock error: {e}", job.name());
+                false
+            }
+        }
+    }
+
+    /// Take `job`'s lock, returning the token that releases it. `None` when
+    /// another run holds it.
+    async fn lock(&self, job: Job) -> Option<String> {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        self.set_nx(job, job.lock_key(), &token)
+            .await
+            .then_some(token)
+    }
+
+    /// Claim the scheduled run of `job` at `slot` for this replica. The
+    /// claim is never released, so a replica whose clock is behind can't
+    /// run the slot again after the first run has finished.
+    async fn claim_slot(&self, job: Job, slot: DateTime<Utc>) -> bool {
+        self.set_nx(job, job.slot_key(slot), "1").await
+    }
+
+    async fn unlock(&self, job: Job, token: &str) {
+        let res: redis::RedisResult<i32> = async {
+            let mut con = self.redis.get_async_connection().await?;
+            redis::Script::new(UNLOCK)
+                .key(job.lock_key())
+                .arg(token)
+                .invoke_async(&mut con)
+                .await
+        }
+        .await;
+        if let Err(e) = res {
+            tracing::error!("job {} unlock error: {e}", job.name());
+        }
+    }
+
+    /// Push the lock's expiry back to a full `lock_ttl_secs`. False when
+    /// the lock no longer holds `token`.
+    async fn extend(&self, job: Job, token: &str) -> redis::RedisResult<bool> {
+        let mut con = self.redis.get_async_connection().await?;
+        let extended: i32 = redis::Script::new(EXTEND)
+            .key(job.lock_key())
+            .arg(token)
+            .arg(self.settings.lock_ttl_secs)
+            .invoke_async(&mut con)
+            .await?;
+        Ok(extended == 1)
+    }
+
+    /// Keep renewing the lock for as long as the run lasts. Never resolves.
+    async fn heartbeat(&self, job: Job, token: &str) {
+        let every = Duration::from_secs((self.settings.lock_ttl_secs / 3).max(1));
+        loop {
+            tokio::time::sleep(every).await;
+            match self.extend(job, token).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    // another replica may be running it by now
+                    tracing::warn!("job {} lost its lock", job.name());
+                    return std::future::pending().await;
+                }
+                Err(e) => tracing::error!("job {} lock error: {e}", job.name()),
+            }
+        }
+    }
+
+    /// Run `job` under the lock `token` holds and record the run.
+    async fn run(&self, job: Job, trigger: Trigger, token: String) {
+        let run_id = sqlx::query("INSERT INTO job_run (job, triggered_by) VALUES (?, ?)")
+            .bind(job.name())
+            .bind(trigger.as_str())
+            .execute(&self.db)
+            .await
+            .map(|res| res.last_insert_id());
+        let work = async {
+            match job {
+                Job::WarmFeed => self.warm_feed().await,
+                Job::RollupListens => self.rollup_listens().await,
+                Job::PruneDevices => self.prune_devices().await,
+                Job::CheckLinks => self.check_links().await,
+            }
+        };
+        let res = tokio::select! {
+            res = work => res,
+            _ = self.heartbeat(job, &token) => unreachable!(),
+        };
+        let (status, detail) = match &res {
+            Ok(detail) => {
+                tracing::info!("job {}: {detail}", job.name());
+                ("ok", detail)
+            }
+            Err(e) => {
+                tracing::error!("job {} error: {e}", job.name());
+                ("failed", e)
+            }
+        };
+        match run_id {
+            Ok(run_id) => {
+                let finished = sqlx::query(
+                    "UPDATE job_run SET status = ?, detail = ?, finished_at = NOW() WHERE id = ?",
+                )
+                .bind(status)
+                .bind(detail)
+                .bind(run_id)
+                .execute(&self.db)
+                .await;
+                if let Err(e) = finished {
+                    tracing::error!("job {} history error: {e}", job.name());
+                }
+            }
+            Err(e) => tracing::error!("job {} history error: {e}", job.name()),
+        }
+        self.unlock(job, &token).await;
+    }
+
+    async fn warm_feed(&self) -> Result<String, String> {
+        let page_size = Pagination::default().page_size;
+        let summary = feed::warm(
+            &self.db,
+            &self.redis,
+            self.settings.warm_days,
+            self.settings.warm_pages,
+            page_size,
+        )
+        .await?;
+        Ok(format!(
+            "{} browsers: {} pages built, {} still cached, {} failed",
+            summary.clients, summary.built, summary.fresh, summary.failed
+        ))
+    }
+
+    /// Add what each `user_album_log` total grew by since the last rollup
+    /// to today's row in `user_album_daily`.
+    async fn rollup_listens(&self) -> Result<String, String> {
+        let res: Result<u64, sqlx::Error> = async {
+            let mut tx = self.db.begin().await?;
+            // the select locks the rows it reads, so a visit logged while
+            // this runs waits and lands in the next rollup
+            sqlx::query(
+                r#"INSERT INTO user_album_daily (day, user_id, album_id, click_count, listen_count)
+                SELECT CURDATE(), user_id, album_id,
+                    GREATEST(click_count - rolled_click_count, 0),
+                    GREATEST(listen_count - rolled_listen_count, 0)
+                FROM user_album_log
+                WHERE click_count <> rolled_click_count OR listen_count <> rolled_listen_count
+                ON DUPLICATE KEY UPDATE
+                    click_count = user_album_daily.click_count + VALUES(click_count),
+                    listen_count = user_album_daily.listen_count + VALUES(listen_count)"#,
+            )
+            .execute(&mut tx)
+            .await?;
+            let pairs = sqlx::query(
+                r#"UPDATE user_album_log
+                SET rolled_click_count = click_count, rolled_listen_count = listen_count
+                WHERE click_count <> rolled_click_count OR listen_count <> rolled_listen_count"#,
+            )
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            Ok(pairs)
+        }
+        .await;
+        res.map(|pairs| format!("{pairs} user and album pairs rolled up"))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Forget devices idle for `device_max_idle_days`, which signs them
+    /// out, and drop client ids without a device from the comma-joined
+    /// `rym_user.session_id` older accounts still carry.
+    async fn prune_devices(&self) -> Result<String, String> {
+        let days = self.settings.device_max_idle_days;
+        let idle: Vec<String> = sqlx::query_scalar(
+            "SELECT client_id FROM user_device WHERE last_seen < DATE_SUB(NOW(), INTERVAL ? DAY)",
+        )
+        .bind(days)
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| e.to_string())?;
+        sqlx::query("DELETE FROM user_device WHERE last_seen < DATE_SUB(NOW(), INTERVAL ? DAY)")
+            .bind(days)
+            .execute(&self.db)
+            .await
+            .map_err(|e| e.to_string())?;
+        for client_id in &idle {
+            device::clear_feed_cache(&self.redis, client_id).await;
+        }
+
+        let legacy = sqlx::query(
+            "SELECT id, session_id FROM rym_user WHERE session_id IS NOT NULL AND session_id <> ''",
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| e.to_string())?;
+        let mut dropped = 0;
+        for row in legacy {
+            let user_id: i32 = row.get(0);
+            let session_id: String = row.get(1);
+            let devices: Vec<String> =
+                sqlx::query_scalar("SELECT client_id FROM user_device WHERE user_id = ?")
+                    .bind(user_id)
+                    .fetch_all(&self.db)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            let (kept, stale): (Vec<&str>, Vec<&str>) = session_id
+                .split(',')
+                .filter(|id| !id.is_empty())
+                .partition(|id| devices.iter().any(|d| d == id));
+            if stale.is_empty() {
+                continue;
+            }
+            sqlx::query("UPDATE rym_user SET session_id = ? WHERE id = ? AND session_id = ?")
+                .bind(kept.join(","))
+                .bind(user_id)
+                .bind(&session_id)
+                .execute(&self.db)
+                .await
+                .map_err(|e| e.to_string())?;
+            for client_id in &stale {
+                device::clear_feed_cache(&self.redis, client_id).await;
+            }
+            dropped += stale.len();
+        }
+        Ok(format!(
+            "{} idle devices and {dropped} stale session ids removed",
+            idle.len()
+        ))
+    }
+
+    async fn check_links(&self) -> Result<String, String> {
+        let probe = link_check::HttpClient::new(Duration::from_secs(self.link_check.timeout_secs));
+        let opts = link_check::CheckOptions::from(&self.link_check);
+        let summary = link_check::run(&self.db, &self.redis, &probe, &opts, None)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(format!(
+            "checked {} links on {} albums: {} dead, {} unreachable",
+            summary.links, summary.albums, summary.dead, summary.unreachable
+        ))
+    }
+
+    /// Every job with its schedule, whether it's running and its last run.
+    pub async fn status(&self) -> Result<Vec<JobStatus>, String> {
+        let mut con = self
+            .redis
+            .get_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut jobs = Vec::new();
+        for job in Job::ALL {
+            let schedule = if self.settings.enabled {
+                job.schedule(&self.settings).to_string()
+            } else {
+                String::new()
+            };
+            let next_run = parse_schedule(&schedule)
+                .ok()
+                .flatten()
+                .and_then(|s| s.upcoming(Utc).next())
+                .map(|next| next.to_rfc3339());
+            let running: bool = redis::cmd("EXISTS")
+                .arg(job.lock_key())
+                .query_async(&mut con)
+                .await
+                .map_err(|e| e.to_string())?;
+            let last_run = sqlx::query_as::<_, JobRun>(
+                r#"SELECT id, triggered_by, status, detail,
+                CAST(started_at AS CHAR) AS started_at,
+                CAST(finished_at AS CHAR) AS finished_at
+                FROM job_run WHERE job = ? ORDER BY id DESC LIMIT 1"#,
+            )
+            .bind(job.name())
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|e| e.to_string())?;
+            jobs.push(JobStatus {
+                name: job.name(),
+                schedule,
+                next_run,
+                running,
+                last_run,
+            });
+        }
+        Ok(jobs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// A scheduler on the Redis at `TEST_REDIS_URL`, or a local one. The
+    /// database is never reached by these tests.
+    fn scheduler(lock_ttl_secs: u64) -> Scheduler {
+        let url = std::env::var("TEST_REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+        Scheduler::new(
+            MySqlPool::connect_lazy("mysql://localhost/test").unwrap(),
+            redis::Client::open(url).unwrap(),
+            JobSettings {
+                lock_ttl_secs,
+                ..JobSettings::default()
+            },
+            LinkCheckSettings::default(),
+        )
+    }
+
+    #[test]
+    fn slot_keys_name_the_job_and_the_slot() {
+        let slot = Utc.with_ymd_and_hms(2024, 5, 1, 3, 0, 0).unwrap();
+        assert_eq!(Job::WarmFeed.slot_key(slot), "job_slot:warm_feed:1714532400");
+        assert_ne!(Job::WarmFeed.slot_key(slot), Job::CheckLinks.slot_key(slot));
+        assert_ne!(
+            Job::WarmFeed.slot_key(slot),
+            Job::WarmFeed.slot_key(slot + chrono::Duration::minutes(1))
+        );
+    }
+
+    #[test]
+    fn jobs_round_trip_their_names() {
+        for job in Job::ALL {
+            assert_eq!(Job::from_name(job.name()), Some(job));
+        }
+        assert_eq!(Job::from_name("nope"), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn a_slot_is_claimed_once() {
+        let scheduler = scheduler(60);
+        let slot = Utc::now() + chrono::Duration::days(rand::random::<u16>() as i64);
+        assert!(scheduler.claim_slot(Job::PruneDevices, slot).await);
+        assert!(!scheduler.claim_slot(Job::PruneDevices, slot).await);
+        assert!(scheduler.claim_slot(Job::WarmFeed, slot).await);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see TEST_REDIS_URL"]
+    async fn unlock_and_extend_need_the_token() {
+        let scheduler = scheduler(60);
+        let job = Job::RollupListens;
+        let token = scheduler.lock(job).await.unwrap();
+        assert!(scheduler.lock(job).await.is_none());
+
+        assert!(!scheduler.extend(job, "someone else").await.unwrap());
+        assert!(scheduler.extend(job, &token).await.unwrap());
+        scheduler.unlock(job, "someone else").await;
+        assert!(scheduler.lock(job).await.is_none());
+
+        scheduler.unlock(job, &token).await;
+        assert!(!scheduler.extend(job, &token).await.unwrap());
+        let token = scheduler.lock(job).await.unwrap();
+        scheduler.unlock(job, &token).await;
+    }
+
+    #[tokio::test]
+    async fn nothing_is_triggered_after_shutdown() {
+        let scheduler = Arc::new(scheduler(60));
+        let stopping = Arc::new(Notify::new());
+        scheduler.spawn(stopping.clone());
+        tokio::task::yield_now().await;
+        stopping.notify_waiters();
+        tokio::task::yield_now().await;
+        assert!(scheduler.stopped.load(Ordering::SeqCst));
+        assert!(!scheduler.trigger(Job::WarmFeed).await);
+    }
+}
@@ -0,0 +1,407 @@
+use async_trait::async_trait;
+use clap::Args;
+use futures::{stream, StreamExt};
+use sqlx::mysql::{MySql, MySqlPool};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::{http_cache, media};
+use crate::settings::LinkCheckSettings;
+
+/// Albums loaded and written per round.
+const BATCH_SIZE: i64 = 200;
+
+#[derive(Debug, Args)]
+pub struct CheckLinksArgs {
+    /// Only check this album
+    #[arg(long)]
+    pub album: Option<i32>,
+    /// Requests in flight at once (default from `[link_check]` settings)
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+    /// Extra attempts for failing links (default from `[link_check]` settings)
+    #[arg(long)]
+    pub retries: Option<u32>,
+}
+
+/// Answers whether a url is still there. Abstracted so tests can point the
+/// checker at a local server.
+#[async_trait]
+pub trait HttpProbe: Send + Sync {
+    /// Status code for `url`, or why there was none.
+    async fn status(&self, url: &str) -> Result<u16, String>;
+}
+
+pub struct HttpClient {
+    client: reqwest::Client,
+}
+
+impl HttpClient {
+    pub fn new(timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent("random-my-music link checker")
+            .build()
+            .expect("can't build http client");
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl HttpProbe for HttpClient {
+    async fn status(&self, url: &str) -> Result<u16, String> {
+        let res = self.client.head(url).send().await.map_err(|e| e.to_string())?;
+        match res.status().as_u16() {
+            // some hosts only answer GET; ask for as little as possible
+            403 | 405 | 501 => self
+                .client
+                .get(url)
+                .header(reqwest::header::RANGE, "bytes=0-0")
+                .send()
+                .await
+                .map(|res| res.status().as_u16())
+                .map_err(|e| e.to_string()),
+            status => Ok(status),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Ok,
+    /// The host says the page is gone
+    Dead,
+    /// Errors or timeouts that outlasted the retries
+    Unreachable,
+}
+
+impl LinkState {
+    fn as_str(self) -> &'static str {
+        match self {
+            LinkState::Ok => "ok",
+            LinkState::Dead => "dead",
+            LinkState::Unreachable => "unreachable",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    pub concurrency: usize,
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl From<&LinkCheckSettings> for CheckOptions {
+    fn from(settings: &LinkCheckSettings) -> Self {
+        Self {
+            concurrency: settings.concurrency.max(1),
+            retries: settings.retries,
+            backoff: Duration::from_millis(settings.backoff_ms),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkResult {
+    pub state: LinkState,
+    pub http_status: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// `None` for answers worth asking again.
+fn classify(status: u16) -> Option<LinkState> {
+    match status {
+        200..=399 => Some(LinkState::Ok),
+        404 | 410 => Some(LinkState::Dead),
+        _ => None,
+    }
+}
+
+pub async fn check_url(probe: &dyn HttpProbe, url: &str, opts: &CheckOptions) -> LinkResult {
+    let mut attempt = 0;
+    loop {
+        let (http_status, error) = match probe.status(url).await {
+            Ok(status) => match classify(status) {
+                Some(state) => {
+                    return LinkResult {
+                        state,
+                        http_status: Some(status),
+                        error: None,
+                    }
+                }
+                None => (Some(status), None),
+            },
+            Err(e) => (None, Some(e)),
+        };
+        if attempt >= opts.retries {
+            return LinkResult {
+                state: LinkState::Unreachable,
+                http_status,
+                error,
+            };
+        }
+        tokio::time::sleep(opts.backoff * 2u32.saturating_pow(attempt)).await;
+        attempt += 1;
+    }
+}
+
+/// Check each distinct url once, at most `opts.concurrency` at a time.
+pub async fn check_urls(
+    probe: &dyn HttpProbe,
+    urls: impl IntoIterator<Item = String>,
+    opts: &CheckOptions,
+) -> HashMap<String, LinkResult> {
+    let urls: HashSet<String> = urls.into_iter().collect();
+    stream::iter(urls)
+        .map(|url| async move {
+            let res = check_url(probe, &url, opts).await;
+            (url, res)
+        })
+        .buffer_unordered(opts.concurrency.max(1))
+        .collect()
+        .await
+}
+
+#[derive(sqlx::FromRow)]
+struct AlbumLinks {
+    id: i32,
+    cover: String,
+    media_url: Option<sqlx::types::Json<HashMap<String, serde_json::Value>>>,
+}
+
+struct Target {
+    album_id: i32,
+    kind: &'static str,
+    provider: &'static str,
+    url: String,
+}
+
+fn targets(album: &AlbumLinks) -> Vec<Target> {
+    let mut targets = vec![];
+    if !album.cover.is_empty() {
+        targets.push(Target {
+            album_id: album.id,
+            kind: "cover",
+            provider: "",
+            url: album.cover.clone(),
+        });
+    }
+    if let Some(media_url) = &album.media_url {
+        for link in media::links(media_url) {
+            targets.push(Target {
+                album_id: album.id,
+                kind: "media",
+                provider: link.provider,
+                url: link.url,
+            });
+        }
+    }
+    targets
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub albums: usize,
+    pub links: usize,
+    pub dead: usize,
+    pub unreachable: usize,
+}
+
+/// Replace the stored results of the albums in `targets` and flag dead covers.
+async fn record(
+    pool: &MySqlPool,
+    album_ids: &[i32],
+    targets: &[Target],
+    results: &HashMap<String, LinkResult>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for album_id in album_ids {
+        sqlx::query("DELETE FROM link_status WHERE album_id = ?")
+            .bind(album_id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("UPDATE album SET cover_dead = 0 WHERE id = ?")
+            .bind(album_id)
+            .execute(&mut tx)
+            .await?;
+    }
+    for target in targets {
+        let res = &results[&target.url];
+        sqlx::query(
+            r#"INSERT INTO link_status (album_id, kind, provider, url, state, http_status, error)
+            VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(target.album_id)
+        .bind(target.kind)
+        .bind(target.provider)
+        .bind(&target.url)
+        .bind(res.state.as_str())
+        .bind(res.http_status)
+        .bind(res.error.as_deref().map(|e| e.chars().take(255).collect::<String>()))
+        .execute(&mut tx)
+        .await?;
+        if target.kind == "cover" && res.state == LinkState::Dead {
+            sqlx::query("UPDATE album SET cover_dead = 1 WHERE id = ?")
+                .bind(target.album_id)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+    tx.commit().await
+}
+
+/// Check every album's cover and media links, or only `album`'s.
+pub async fn run(
+    pool: &MySqlPool,
+    redis: &redis::Client,
+    probe: &dyn HttpProbe,
+    opts: &CheckOptions,
+    album: Option<i32>,
+) -> Result<Summary, sqlx::Error> {
+    let mut summary = Summary::default();
+    let mut last_id = 0;
+    loop {
+        let albums = sqlx::query_as::<MySql, AlbumLinks>(
+            r#"SELECT id, IFNULL(cover, '') AS cover, media_url FROM album
+            WHERE id > ? AND (? IS NULL OR id = ?) ORDER BY id LIMIT ?"#,
+        )
+        .bind(last_id)
+        .bind(album)
+        .bind(album)
+        .bind(BATCH_SIZE)
+        .fetch_all(pool)
+        .await?;
+        let Some(last) = albums.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        let targets = albums.iter().flat_map(targets).collect::<Vec<Target>>();
+        let results = check_urls(probe, targets.iter().map(|t| t.url.clone()), opts).await;
+        let album_ids = albums.iter().map(|a| a.id).collect::<Vec<i32>>();
+        record(pool, &album_ids, &targets, &results).await?;
+        // dead covers drop out of the listings
+        http_cache::touch_catalog(redis).await;
+
+        summary.albums += albums.len();
+        summary.links += targets.len();
+        for target in &targets {
+            match results[&target.url].state {
+                LinkState::Dead => summary.dead += 1,
+                LinkState::Unreachable => summary.unreachable += 1,
+                LinkState::Ok => {}
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::get, Router};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn opts() -> CheckOptions {
+        CheckOptions {
+            concurrency: 4,
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    /// Serve a few canned answers on a free local port.
+    fn stub_server(flaky_hits: Arc<AtomicUsize>) -> SocketAddr {
+        let app = Router::new()
+            .route("/ok", get(|| async { "cover" }))
+            .route("/gone", get(|| async { StatusCode::NOT_FOUND }))
+            .route("/down", get(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+            .route(
+                "/flaky",
+                get(move || {
+                    let hits = flaky_hits.clone();
+                    async move {
+                        if hits.fetch_add(1, Ordering::SeqCst) == 0 {
+                            StatusCode::BAD_GATEWAY
+                        } else {
+                            StatusCode::OK
+                        }
+                    }
+                }),
+            )
+            // GET only, HEAD gets 405
+            .route(
+                "/get-only",
+                axum::routing::on(axum::routing::MethodFilter::GET, || async {
+                    (StatusCode::PARTIAL_CONTENT, "c")
+                })
+                .head(|| async { StatusCode::METHOD_NOT_ALLOWED }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn classifies_links_from_stub_server() {
+        let flaky_hits = Arc::new(AtomicUsize::new(0));
+        let addr = stub_server(flaky_hits.clone());
+        let probe = HttpClient::new(Duration::from_secs(5));
+        let url = |path: &str| format!("http://{addr}{path}");
+
+        let results = check_urls(
+            &probe,
+            ["/ok", "/gone", "/down", "/flaky", "/get-only", "/ok"].map(url),
+            &opts(),
+        )
+        .await;
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[&url("/ok")].state, LinkState::Ok);
+        assert_eq!(results[&url("/gone")].state, LinkState::Dead);
+        assert_eq!(results[&url("/gone")].http_status, Some(404));
+        assert_eq!(results[&url("/down")].state, LinkState::Unreachable);
+        assert_eq!(results[&url("/down")].http_status, Some(503));
+        assert_eq!(results[&url("/flaky")].state, LinkState::Ok);
+        assert_eq!(flaky_hits.load(Ordering::SeqCst), 2);
+        assert_eq!(results[&url("/get-only")].state, LinkState::Ok);
+    }
+
+    #[tokio::test]
+    async fn network_errors_are_unreachable() {
+        // bind and drop to get a port nobody listens on
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let probe = HttpClient::new(Duration::from_secs(5));
+        let res = check_url(&probe, &format!("http://{addr}/cover"), &opts()).await;
+        assert_eq!(res.state, LinkState::Unreachable);
+        assert!(res.error.is_some());
+    }
+
+    struct CountingProbe(AtomicUsize);
+
+    #[async_trait]
+    impl HttpProbe for CountingProbe {
+        async fn status(&self, _: &str) -> Result<u16, String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Err("timed out".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_then_gives_up() {
+        let probe = CountingProbe(AtomicUsize::new(0));
+        let res = check_url(&probe, "http://example.invalid/", &opts()).await;
+        assert_eq!(res.state, LinkState::Unreachable);
+        assert_eq!(probe.0.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,557 @@
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_sessions::extractors::ReadableSession;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use sqlx::mysql::{MySql, MySqlPool};
+use sqlx::{QueryBuilder, Row};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{device, MyShared};
+
+/// Spotify only counts a track as streamed after 30 seconds.
+const MIN_MS_PLAYED: i64 = 30_000;
+const MIN_SIMILARITY: f64 = 0.85;
+/// Entries listed per section of the report.
+const REPORT_LIMIT: usize = 500;
+/// Play hashes inserted per statement.
+const HASH_BATCH: usize = 500;
+
+#[derive(Deserialize, IntoParams)]
+pub struct ImportQuery {
+    /// `lastfm` or `spotify`, sniffed from the upload when omitted
+    format: Option<String>,
+}
+
+/// One play from an export. `title` is the album, or the track for exports
+/// that don't name the album.
+struct Play {
+    artist: String,
+    title: String,
+    /// Empty when the export doesn't name it
+    track: String,
+    played_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct SpotifyEntry {
+    // StreamingHistory*.json
+    #[serde(rename = "endTime")]
+    end_time: Option<String>,
+    #[serde(rename = "artistName")]
+    artist_name: Option<String>,
+    #[serde(rename = "trackName")]
+    track_name: Option<String>,
+    #[serde(rename = "msPlayed")]
+    ms_played_basic: Option<i64>,
+    // extended streaming history
+    ts: Option<String>,
+    master_metadata_album_artist_name: Option<String>,
+    master_metadata_album_album_name: Option<String>,
+    master_metadata_track_name: Option<String>,
+    ms_played: Option<i64>,
+}
+
+/// Plays of one album, or one track for exports without albums.
+struct Group {
+    artist: String,
+    title: String,
+    /// One [`play_hash`] per play
+    hashes: Vec<String>,
+    last_played: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MatchedEntry {
+    artist: String,
+    title: String,
+    plays: i32,
+    /// Plays an earlier import already counted
+    already_imported: i32,
+    album_id: i32,
+    album_name: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UnmatchedEntry {
+    artist: String,
+    title: String,
+    plays: i32,
+}
+
+/// Seconds since the epoch for a proleptic Gregorian date and time.
+fn unix_time(year: i64, month: i64, day: i64, hour: i64, min: i64, sec: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    days * 86_400 + hour * 3_600 + min * 60 + sec
+}
+
+/// Understands unix seconds, `2023-01-31 12:34[:56]`, `2023-01-31T12:34:56Z`
+/// and Last.fm's `31 Jan 2023 12:34`.
+fn parse_time(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        return s.parse().ok();
+    }
+    // no month abbreviation contains an upper case T or Z
+    let s = s.replace(['T', 'Z'], " ");
+    let nums: Vec<&str> = s
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|p| !p.is_empty())
+        .collect();
+    let num = |i: usize| nums.get(i).and_then(|p| p.parse::<i64>().ok());
+    if nums.first().map(|p| p.len() == 4).unwrap_or(false) {
+        let (year, month, day) = (num(0)?, num(1)?, num(2)?);
+        return Some(unix_time(year, month, day, num(3).unwrap_or(0), num(4).unwrap_or(0), num(5).unwrap_or(0)));
+    }
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let month = nums.get(1).and_then(|m| {
+        let m = m.to_lowercase();
+        MONTHS.iter().position(|name| m.starts_with(name))
+    })? as i64
+        + 1;
+    Some(unix_time(num(2)?, month, num(0)?, num(3).unwrap_or(0), num(4).unwrap_or(0), 0))
+}
+
+fn parse_spotify(body: &[u8]) -> Result<Vec<Play>, String> {
+    let entries: Vec<SpotifyEntry> =
+        serde_json::from_slice(body).map_err(|e| format!("invalid Spotify export: {e}"))?;
+    let mut plays = vec![];
+    for entry in entries {
+        let ms_played = entry.ms_played.or(entry.ms_played_basic).unwrap_or(0);
+        if ms_played < MIN_MS_PLAYED {
+            continue;
+        }
+        let (artist, title, track, played_at) = match entry.master_metadata_album_artist_name {
+            Some(artist) => (
+                artist,
+                entry.master_metadata_album_album_name.unwrap_or_default(),
+                entry.master_metadata_track_name.unwrap_or_default(),
+                entry.ts,
+            ),
+            None => {
+                let track = entry.track_name.unwrap_or_default();
+                (entry.artist_name.unwrap_or_default(), track.clone(), track, entry.end_time)
+            }
+        };
+        if artist.is_empty() || title.is_empty() {
+            continue;
+        }
+        plays.push(Play {
+            artist,
+            title,
+            track,
+            played_at: played_at.as_deref().and_then(parse_time),
+        });
+    }
+    Ok(plays)
+}
+
+/// Last.fm scrobble exports come as `artist,album,track,date` with or without a
+/// header row.
+fn parse_lastfm(body: &[u8]) -> Result<Vec<Play>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(body);
+    let (mut artist_col, mut album_col, mut track_col, mut date_col) = (0, 1, 2, 3);
+    let mut plays = vec![];
+    for (i, row) in reader.records().enumerate() {
+        let row = row.map_err(|e| format!("invalid Last.fm export: {e}"))?;
+        if i == 0 {
+            let header: Vec<String> = row.iter().map(|c| c.trim().to_lowercase()).collect();
+            if header.iter().any(|c| c == "artist") && header.iter().any(|c| c == "album") {
+                let col = |names: &[&str]| header.iter().position(|c| names.contains(&c.as_str()));
+                artist_col = col(&["artist"]).unwrap_or(artist_col);
+                album_col = col(&["album"]).unwrap_or(album_col);
+                track_col = col(&["track"]).unwrap_or(track_col);
+                date_col = col(&["uts", "date", "utc_time"]).unwrap_or(date_col);
+                continue;
+            }
+        }
+        let artist = row.get(artist_col).unwrap_or_default().trim();
+        let title = row.get(album_col).unwrap_or_default().trim();
+        if artist.is_empty() || title.is_empty() {
+            continue;
+        }
+        plays.push(Play {
+            artist: artist.to_string(),
+            title: title.to_string(),
+            track: row.get(track_col).unwrap_or_default().trim().to_string(),
+            played_at: row.get(date_col).and_then(parse_time),
+        });
+    }
+    Ok(plays)
+}
+
+/// Lower case words without punctuation, edition suffixes or bracketed notes,
+/// so "OK Computer (Remastered)" and "Ok computer" compare equal.
+fn normalize(s: &str) -> String {
+    let s = s.to_lowercase();
+    let s = s.split(" - ").next().unwrap_or_default();
+    let mut out = String::new();
+    let mut depth = 0;
+    for c in s.replace('&', " and ").chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth > 0 => {}
+            c if c.is_alphanumeric() => out.push(c),
+            _ => out.push(' '),
+        }
+    }
+    out.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// What identifies a play across uploads: its artist, album, track and time,
+/// or for exports without times, the file and where in it the play is.
+fn play_hash(play: &Play, file_hash: &str, index: usize) -> String {
+    let key = match play.played_at {
+        Some(played_at) => format!(
+            "{}\n{}\n{}\n{played_at}",
+            normalize(&play.artist),
+            normalize(&play.title),
+            normalize(&play.track)
+        ),
+        None => format!("{file_hash}\n{index}"),
+    };
+    format!("{:x}", Sha3_256::digest(key.as_bytes()))
+}
+
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let len = a.len().max(b.len());
+    if len == 0 {
+        return 1.0;
+    }
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    1.0 - prev[b.len()] as f64 / len as f64
+}
+
+#[derive(Clone, sqlx::FromRow)]
+struct Candidate {
+    id: i32,
+    name: String,
+}
+
+/// Albums credited to `artist`. The column collation already ignores case and
+/// accents; a leading "The" is tried both ways.
+async fn artist_albums(db: &MySqlPool, artist: &str) -> Result<Vec<Candidate>, sqlx::Error> {
+    let alternative = match artist.get(..4) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("the ") => artist[4..].to_string(),
+        _ => format!("The {artist}"),
+    };
+    sqlx::query_as::<MySql, Candidate>("SELECT id, name FROM album WHERE artist = ? OR artist = ?")
+        .bind(artist)
+        .bind(alternative)
+        .fetch_all(db)
+        .await
+}
+
+fn best_match<'a>(title: &str, candidates: &'a [Candidate]) -> Option<&'a Candidate> {
+    let title = normalize(title);
+    candidates
+        .iter()
+        .map(|c| (c, similarity(&title, &normalize(&c.name))))
+        .filter(|(_, score)| *score >= MIN_SIMILARITY)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(c, _)| c)
+}
+
+/// Add the plays among `hashes` that no import counted yet to the user's
+/// log, and return how many that was.
+async fn record_listens(
+    db: &MySqlPool,
+    user_id: i32,
+    album_id: i32,
+    hashes: &[String],
+    played_at: Option<i64>,
+) -> Result<i32, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let mut plays = 0;
+    for chunk in hashes.chunks(HASH_BATCH) {
+        let mut insert = QueryBuilder::<MySql>::new("INSERT IGNORE INTO imported_play (user_id, play_hash, album_id) ");
+        insert.push_values(chunk, |mut row, hash| {
+            row.push_bind(user_id).push_bind(hash).push_bind(album_id);
+        });
+        plays += insert.build().execute(&mut tx).await?.rows_affected() as i32;
+    }
+    if plays == 0 {
+        tx.commit().await?;
+        return Ok(0);
+    }
+    // user_album_log stores ids as strings
+    let (user_key, album_key) = (user_id.to_string(), album_id.to_string());
+    let existing = sqlx::query("SELECT id FROM user_album_log WHERE user_id = ? AND album_id = ?")
+        .bind(&user_key)
+        .bind(&album_key)
+        .fetch_optional(&mut tx)
+        .await?;
+    match existing {
+        Some(row) => {
+            sqlx::query(
+                r#"UPDATE user_album_log SET listen_count = listen_count + ?,
+                create_time = GREATEST(create_time, IFNULL(FROM_UNIXTIME(?), create_time)) WHERE id = ?"#,
+            )
+            .bind(plays)
+            .bind(played_at)
+            .bind(row.get::<i32, usize>(0))
+            .execute(&mut tx)
+            .await?;
+        }
+        None => {
+            let genres: Vec<String> = sqlx::query_scalar("SELECT genre FROM album_genre WHERE album_id = ?")
+                .bind(album_id)
+                .fetch_all(&mut tx)
+                .await?;
+            sqlx::query(
+                r#"INSERT INTO user_album_log (user_id, album_id, album_genre, click_count, listen_count, create_time)
+                VALUES (?, ?, ?, 0, ?, IFNULL(FROM_UNIXTIME(?), NOW()))"#,
+            )
+            .bind(&user_key)
+            .bind(&album_key)
+            .bind(genres.join("|"))
+            .bind(plays)
+            .bind(played_at)
+            .execute(&mut tx)
+            .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(plays)
+}
+
+/// Match a Last.fm or Spotify export against the catalogue and add the
+/// plays to the user's history.
+#[utoipa::path(
+    post,
+    path = "/user_album_log/import",
+    params(ImportQuery),
+    request_body(content = String, description = "The export file, up to 64MB", content_type = "application/octet-stream"),
+    responses(
+        (status = 200, body = ImportEnvelope),
+        (status = 400, description = "Not logged in or unreadable export", body = EmptyEnvelope)
+    ),
+    security(("session" = [], "csrf" = []))
+)]
+pub async fn import_history(
+    Query(args): Query<ImportQuery>,
+    Extension(state): Extension<MyShared>,
+    session: ReadableSession,
+    body: Bytes,
+) -> impl IntoResponse {
+    let user_id = device::session_user_id(&session, &state.db).await;
+    if user_id == 0 {
+        let resp = serde_json::json!({
+            "code": 400,
+            "msg": "you are not logged in"
+        });
+        return (StatusCode::BAD_REQUEST, Json(resp));
+    }
+    let is_json = body
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .map(|b| *b == b'[')
+        .unwrap_or(false);
+    let plays = match args.format.as_deref() {
+        Some("spotify") => parse_spotify(&body),
+        Some("lastfm") => parse_lastfm(&body),
+        None if is_json => parse_spotify(&body),
+        None => parse_lastfm(&body),
+        Some(other) => Err(format!("unknown format {other}")),
+    };
+    let plays = match plays {
+        Ok(plays) => plays,
+        Err(msg) => {
+            let resp = serde_json::json!({
+                "code": 400,
+                "msg": msg
+            });
+            return (StatusCode::BAD_REQUEST, Json(resp));
+        }
+    };
+    let total = plays.len();
+    let file_hash = format!("{:x}", Sha3_256::digest(&body));
+
+    let mut grouped: HashMap<(String, String), Group> = HashMap::new();
+    for (index, play) in plays.into_iter().enumerate() {
+        let key = (normalize(&play.artist), normalize(&play.title));
+        let hash = play_hash(&play, &file_hash, index);
+        let group = grouped.entry(key).or_insert_with(|| Group {
+            artist: play.artist.clone(),
+            title: play.title.clone(),
+            hashes: vec![],
+            last_played: None,
+        });
+        group.hashes.push(hash);
+        group.last_played = group.last_played.max(play.played_at);
+    }
+
+    let mut artists: HashMap<String, Vec<Candidate>> = HashMap::new();
+    let (mut matched, mut unmatched, mut failed) = (vec![], vec![], vec![]);
+    let (mut imported_plays, mut already_imported_plays) = (0, 0);
+    let (mut matched_plays, mut unmatched_plays, mut failed_plays) = (0, 0, 0);
+    for ((artist_key, _), group) in grouped {
+        let Group {
+            artist,
+            title,
+            hashes,
+            last_played,
+        } = group;
+        let plays = hashes.len() as i32;
+        if !artists.contains_key(&artist_key) {
+            match artist_albums(&state.db, &artist).await {
+                Ok(candidates) => artists.insert(artist_key.clone(), candidates),
+                Err(e) => {
+                    tracing::error!("import history error: {e}");
+                    failed_plays += plays;
+                    failed.push(UnmatchedEntry { artist, title, plays });
+                    continue;
+                }
+            };
+        }
+        match best_match(&title, &artists[&artist_key]) {
+            Some(album) => match record_listens(&state.db, user_id, album.id, &hashes, last_played).await {
+                Ok(imported) => {
+                    matched_plays += plays;
+                    imported_plays += imported;
+                    already_imported_plays += plays - imported;
+                    matched.push(MatchedEntry {
+                        artist,
+                        title,
+                        plays,
+                        already_imported: plays - imported,
+                        album_id: album.id,
+                        album_name: album.name.clone(),
+                    });
+                }
+                Err(e) => {
+                    tracing::error!("import history error: {e}");
+                    failed_plays += plays;
+                    failed.push(UnmatchedEntry { artist, title, plays });
+                }
+            },
+            None => {
+                unmatched_plays += plays;
+                unmatched.push(UnmatchedEntry { artist, title, plays });
+            }
+        }
+    }
+    matched.sort_by_key(|e| std::cmp::Reverse(e.plays));
+    unmatched.sort_by_key(|e| std::cmp::Reverse(e.plays));
+    failed.sort_by_key(|e| std::cmp::Reverse(e.plays));
+    let (matched_count, unmatched_count, failed_count) = (matched.len(), unmatched.len(), failed.len());
+    matched.truncate(REPORT_LIMIT);
+    unmatched.truncate(REPORT_LIMIT);
+    failed.truncate(REPORT_LIMIT);
+
+    let resp = serde_json::json!({
+        "code": 200,
+        "msg": format!("imported {imported_plays} of {total} plays"),
+        "data": {
+            "total_plays": total,
+            "matched_plays": matched_plays,
+            "imported_plays": imported_plays,
+            "already_imported_plays": already_imported_plays,
+            "unmatched_plays": unmatched_plays,
+            "failed_plays": failed_plays,
+            "matched_count": matched_count,
+            "unmatched_count": unmatched_count,
+            "failed_count": failed_count,
+            "matched": matched,
+            "unmatched": unmatched,
+            "failed": failed,
+        }
+    });
+    (StatusCode::OK, Json(resp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_drops_editions_and_punctuation() {
+        assert_eq!(normalize("OK Computer (Remastered)"), "ok computer");
+        assert_eq!(normalize("Ok computer"), "ok computer");
+        assert_eq!(normalize("Abbey Road - 2019 Mix"), "abbey road");
+        assert_eq!(normalize("Simon & Garfunkel"), "simon and garfunkel");
+        assert_eq!(normalize("Loveless [Deluxe]  "), "loveless");
+        assert_eq!(normalize("Sigur Rós: ( ) Ágætis"), "sigur rós ágætis");
+    }
+
+    #[test]
+    fn similarity_is_one_minus_the_relative_edit_distance() {
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("loveless", "loveless"), 1.0);
+        assert_eq!(similarity("abc", ""), 0.0);
+        assert_eq!(similarity("kid a", "kid b"), 0.8);
+        assert!(similarity("in rainbows", "in rainbow") >= MIN_SIMILARITY);
+        assert!(similarity("amnesiac", "the bends") < MIN_SIMILARITY);
+    }
+
+    #[test]
+    fn parse_time_reads_every_export_format() {
+        assert_eq!(parse_time("1675168496"), Some(1_675_168_496));
+        assert_eq!(parse_time("2023-01-31 12:34:56"), Some(1_675_168_496));
+        assert_eq!(parse_time("2023-01-31T12:34:56Z"), Some(1_675_168_496));
+        assert_eq!(parse_time("2023-01-31 12:34"), Some(1_675_168_440));
+        assert_eq!(parse_time("31 Jan 2023 12:34"), Some(1_675_168_440));
+        assert_eq!(parse_time("31 January 2023, 12:34"), Some(1_675_168_440));
+        assert_eq!(parse_time("1970-01-01"), Some(0));
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn parse_lastfm_reads_files_with_and_without_header() {
+        let plays = parse_lastfm(b"Radiohead,Kid A,Idioteque,31 Jan 2023 12:34\nRadiohead,,Creep,\n").unwrap();
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].artist, "Radiohead");
+        assert_eq!(plays[0].title, "Kid A");
+        assert_eq!(plays[0].track, "Idioteque");
+        assert_eq!(plays[0].played_at, Some(1_675_168_440));
+
+        let plays = parse_lastfm(b"uts,utc_time,artist,album,track\n1675168496,31 Jan 2023,Slowdive,Souvlaki,Alison\n").unwrap();
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].artist, "Slowdive");
+        assert_eq!(plays[0].title, "Souvlaki");
+        assert_eq!(plays[0].track, "Alison");
+        assert_eq!(plays[0].played_at, Some(1_675_168_496));
+    }
+
+    #[test]
+    fn play_hash_ignores_the_file_for_timed_plays() {
+        let play = |played_at| Play {
+            artist: "Slowdive".to_string(),
+            title: "Souvlaki".to_string(),
+            track: "Alison".to_string(),
+            played_at,
+        };
+        assert_eq!(play_hash(&play(Some(1)), "a", 0), play_hash(&play(Some(1)), "b", 7));
+        assert_ne!(play_hash(&play(Some(1)), "a", 0), play_hash(&play(Some(2)), "a", 0));
+        assert_eq!(play_hash(&play(None), "a", 0), play_hash(&play(None), "a", 0));
+        assert_ne!(play_hash(&play(None), "a", 0), play_hash(&play(None), "a", 1));
+    }
+}
@@ -0,0 +1,123 @@
+use async_trait::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::settings::MailSettings;
+
+/// A plain text mail.
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), String>;
+}
+
+/// The mailer `[mail] transport` asks for.
+pub fn from_settings(settings: &MailSettings) -> Result<Box<dyn Mailer>, String> {
+    match settings.transport.as_str() {
+        "smtp" => Ok(Box::new(SmtpMailer::new(settings)?)),
+        "outbox" => Ok(Box::new(OutboxMailer::new(&settings.outbox_dir))),
+        other => Err(format!("unknown mail transport {other:?}")),
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(settings: &MailSettings) -> Result<Self, String> {
+        let from = settings
+            .from
+            .parse()
+            .map_err(|e| format!("mail from {:?}: {e}", settings.from))?;
+        // port 465 is TLS from the start, anything else upgrades with STARTTLS
+        let builder = if settings.smtp_port == 465 {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.smtp_host)
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.smtp_host)
+        }
+        .map_err(|e| e.to_string())?
+        .port(settings.smtp_port);
+        let builder = if settings.smtp_username.is_empty() {
+            builder
+        } else {
+            builder.credentials(Credentials::new(
+                settings.smtp_username.clone(),
+                settings.smtp_password.clone(),
+            ))
+        };
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        let to: Mailbox = mail.to.parse().map_err(|e| format!("{:?}: {e}", mail.to))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&mail.subject)
+            .body(mail.body.clone())
+            .map_err(|e| e.to_string())?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Writes each mail to a file in `dir` and logs it, for development and
+/// tests.
+pub struct OutboxMailer {
+    dir: PathBuf,
+}
+
+impl OutboxMailer {
+    pub fn new(dir: &str) -> Self {
+        OutboxMailer { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let name: String = mail
+            .to
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = self.dir.join(format!("{stamp}-{name}.txt"));
+        let content = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            mail.to, mail.subject, mail.body
+        );
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| e.to_string())?;
+        tokio::fs::write(&path, content)
+            .await
+            .map_err(|e| e.to_string())?;
+        tracing::info!("mail to {} written to {}", mail.to, path.display());
+        Ok(())
+    }
+}
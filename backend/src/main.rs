@@ -1,34 +1,65 @@
 extern crate redis;
+mod account;
+mod admin;
+mod cover;
+mod csrf;
+mod device;
+mod export;
+mod feed;
+mod health;
+mod history_export;
+mod http_cache;
+mod import;
+mod jobs;
+mod link_check;
+mod listen_import;
+mod mailer;
+mod media;
+mod openapi;
+mod pagination;
+mod rate_limit;
+mod server;
 mod settings;
+mod telemetry;
+mod users;
+mod validation;
 
 
 use async_trait::async_trait;
 use axum::{
-    extract::{FromRequestParts, Path, Query},
-    http::{self, header, request::Parts, Method, StatusCode},
+    extract::{DefaultBodyLimit, FromRequestParts, Path, Query},
+    http::{self, header, request::Parts, HeaderMap, Method, StatusCode},
+    middleware,
     // middleware::from_extractor,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::{get, get_service, post},
     Extension,
     Json,
     Router,
+    TypedHeader,
 };
+use clap::{Parser, Subcommand};
 use axum_sessions::{
     async_session::MemoryStore,
     extractors::{ReadableSession, WritableSession},
-    SessionLayer,
+    SameSite, SessionLayer,
 };
-use headers::{HeaderName, HeaderValue};
+use headers::{HeaderName, HeaderValue, UserAgent};
 use redis::{AsyncCommands, Client};
 use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
-use sqlx::mysql::{MySql, MySqlPool, MySqlPoolOptions};
+use sqlx::mysql::{MySql, MySqlPool};
 use sqlx::Row;
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use tower_http::cors::{Any, CorsLayer};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::set_header::SetResponseHeaderLayer;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use pagination::{Cursor, Pagination};
+use rate_limit::ClientIp;
 use settings::Settings;
 
 
@@ -36,6 +67,13 @@ use settings::Settings;
 struct MyShared {
     db: MySqlPool,
     redis: Client,
+    cover: Arc<cover::CoverProxy>,
+    limiter: Arc<rate_limit::RateLimiter>,
+    mail: Arc<account::AccountMail>,
+    metrics: Arc<telemetry::Metrics>,
+    jobs: Arc<jobs::Scheduler>,
+    /// `[cors] allowed_origins`, also trusted by the CSRF guard
+    allowed_origins: Arc<Vec<String>>,
 }
 
 #[allow(dead_code)]
@@ -69,156 +107,418 @@ where
     }
 }
 
+#[derive(Parser)]
+#[command(version, about = "random my music backend")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the api and the frontend (default)
+    Serve,
+    /// Import albums from a CSV or JSON Lines file
+    Import(import::ImportArgs),
+    /// Dump the catalog as JSON Lines
+    Export(export::ExportArgs),
+    /// Check cover and media links and record the dead ones
+    CheckLinks(link_check::CheckLinksArgs),
+    /// Compute cover placeholder colours and blurhashes
+    CoverPlaceholders(cover::BackfillArgs),
+    /// Apply pending database migrations and exit
+    Migrate,
+    /// Add a verified account, reading its password from stdin
+    CreateUser(users::CreateUserArgs),
+    /// Give a user the admin role, or take it away with --role user
+    Promote(users::PromoteArgs),
+    /// Set a user's password from stdin and sign them out everywhere
+    ResetPassword(users::ResetPasswordArgs),
+    /// Build the /today pages of recently active browsers ahead of time
+    WarmCache(feed::WarmCacheArgs),
+    /// Repair genre paths and refresh the catalogue indexes
+    RebuildIndexes(admin::RebuildIndexesArgs),
+    /// Validate the settings and try the database and Redis
+    CheckConfig,
+}
+
+/// Redis for commands that can't do without it.
+async fn redis_or_exit(settings: &Settings) -> Client {
+    match health::connect_redis(&settings.redis_url, &settings.startup).await {
+        Ok(redis) => redis,
+        Err(e) => {
+            tracing::error!("can't connect to redis: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+
     let settings = Settings::new();
     let settings = match settings {
         Ok(settings) => {
             settings
         }
         Err(e) => {
-            panic!("config error {}", e);
+            eprintln!("config error: {e}");
+            std::process::exit(1);
         }
     };
 
-    // initialize tracing
-    tracing_subscriber::fmt::init();
+    telemetry::init_logging(&settings.log);
+    tracing::debug!("{settings:?}");
 
-    let db_connection_str = settings.db_url;
+    let command = cli.command.unwrap_or(Command::Serve);
+    if let Command::CheckConfig = command {
+        if !health::check_config(&settings).await {
+            std::process::exit(1);
+        }
+        return;
+    }
 
     // setup connection pool
-    let pool = MySqlPoolOptions::new()
-        .max_connections(5)
-        .connect(&db_connection_str)
-        .await
-        .expect("can't connect to database");
+    let pool = match health::connect_db(&settings.db_url, settings.db_max_connections, &settings.startup).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            tracing::error!("can't connect to database: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = sqlx::migrate!().run(&pool).await {
+        tracing::error!("can't run database migrations: {e}");
+        std::process::exit(1);
+    }
+
+    match command {
+        Command::Serve => serve(settings, pool).await,
+        Command::Migrate => {
+            let migrations = health::Migrations::check(&pool).await;
+            println!("migrations applied up to {:?}", migrations.applied);
+        }
+        Command::CreateUser(args) => match users::create(&pool, args).await {
+            Ok(user_id) => println!("created user {user_id}"),
+            Err(e) => {
+                eprintln!("create user failed: {e}");
+                std::process::exit(1);
+            }
+        },
+        Command::Promote(args) => {
+            let (username, role) = (args.username.clone(), args.role.clone());
+            match users::promote(&pool, args).await {
+                Ok(()) => println!("{username} is now {role}"),
+                Err(e) => {
+                    eprintln!("promote failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::ResetPassword(args) => {
+            let redis = redis_or_exit(&settings).await;
+            let limiter = rate_limit::RateLimiter::new(redis.clone(), settings.rate_limit.clone());
+            match users::reset_password(&pool, &redis, &limiter, args).await {
+                Ok(()) => println!("password reset, all devices signed out"),
+                Err(e) => {
+                    eprintln!("reset password failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::WarmCache(args) => {
+            let redis = redis_or_exit(&settings).await;
+            let page_size = Pagination::default().page_size;
+            match feed::warm(&pool, &redis, args.days, args.pages, page_size).await {
+                Ok(summary) => println!(
+                    "{} browsers: {} pages built, {} still cached, {} failed",
+                    summary.clients, summary.built, summary.fresh, summary.failed
+                ),
+                Err(e) => {
+                    eprintln!("warm cache failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::RebuildIndexes(args) => match admin::rebuild_indexes(&pool, &args).await {
+            Ok(repaired) => {
+                // genre listings may have changed with the paths
+                if repaired > 0 {
+                    http_cache::touch_catalog(&redis_or_exit(&settings).await).await;
+                }
+                println!("{repaired} genre paths repaired, indexes rebuilt");
+            }
+            Err(e) => {
+                eprintln!("rebuild indexes failed: {e}");
+                std::process::exit(1);
+            }
+        },
+        Command::CheckConfig => unreachable!(),
+        Command::Import(args) => {
+            let dry_run = args.dry_run;
+            let res = import::run(&pool, args).await;
+            if res.is_ok() && !dry_run {
+                // a partial import still changes the catalogue
+                if let Ok(redis) = redis::Client::open(settings.redis_url.clone()) {
+                    http_cache::touch_catalog(&redis).await;
+                }
+            }
+            match res {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("import failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::CheckLinks(args) => {
+            let mut opts = link_check::CheckOptions::from(&settings.link_check);
+            opts.concurrency = args.concurrency.unwrap_or(opts.concurrency).max(1);
+            opts.retries = args.retries.unwrap_or(opts.retries);
+            let probe = link_check::HttpClient::new(std::time::Duration::from_secs(
+                settings.link_check.timeout_secs,
+            ));
+            let redis = redis_or_exit(&settings).await;
+            match link_check::run(&pool, &redis, &probe, &opts, args.album).await {
+                Ok(summary) => println!(
+                    "checked {} links on {} albums: {} dead, {} unreachable",
+                    summary.links, summary.albums, summary.dead, summary.unreachable
+                ),
+                Err(e) => {
+                    eprintln!("link check failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::CoverPlaceholders(args) => {
+            let proxy = match cover::CoverProxy::new(&settings.cover) {
+                Ok(proxy) => proxy,
+                Err(e) => {
+                    eprintln!("cover placeholders failed: {e}");
+                    std::process::exit(1);
+                }
+            };
+            let redis = redis_or_exit(&settings).await;
+            match cover::backfill(&pool, &redis, &proxy, &args).await {
+                Ok((done, failed)) => println!("{done} covers done, {failed} failed"),
+                Err(e) => {
+                    eprintln!("cover placeholders failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Export(args) => {
+            if let Err(e) = export::run(&pool, args).await {
+                eprintln!("export failed: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
 
-    let redis = redis::Client::open(settings.redis_url)
-    .expect("can't connect to redis");
+async fn serve(settings: Settings, pool: MySqlPool) {
+    let redis = redis_or_exit(&settings).await;
 
+    let allowed_origins = Arc::new(settings.cors.allowed_origins.clone());
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(AllowOrigin::list(
+            allowed_origins.iter().filter_map(|o| o.parse().ok()),
+        ))
         .allow_methods([Method::GET, Method::POST])
-        .allow_headers([header::CONTENT_TYPE])
-        .allow_credentials(false);
+        .allow_headers([header::CONTENT_TYPE, HeaderName::from_static(csrf::HEADER)])
+        .expose_headers([HeaderName::from_static(csrf::HEADER)])
+        .allow_credentials(true);
+
+    let cover = match cover::CoverProxy::new(&settings.cover) {
+        Ok(cover) => Arc::new(cover),
+        Err(e) => {
+            tracing::error!("cover proxy error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let limiter = Arc::new(rate_limit::RateLimiter::new(
+        redis.clone(),
+        settings.rate_limit.clone(),
+    ));
+
+    let mail = Arc::new(account::AccountMail::new(&settings.mail).expect("mail config error"));
+    let metrics = Arc::new(telemetry::Metrics::new(
+        &settings.metrics,
+        settings.db_max_connections,
+    ));
+    let jobs = Arc::new(jobs::Scheduler::new(
+        pool.clone(),
+        redis.clone(),
+        settings.jobs.clone(),
+        settings.link_check.clone(),
+    ));
+    let stopping = Arc::new(Notify::new());
+    jobs.spawn(stopping.clone());
+    let state = MyShared {
+        db: pool,
+        redis,
+        cover,
+        limiter,
+        mail,
+        metrics,
+        jobs,
+        allowed_origins,
+    };
 
     let store = MemoryStore::new();
-    let session_layer = SessionLayer::new(store, settings.secret.as_bytes()).with_secure(false);
+    let mut session_layer = SessionLayer::new(store, settings.secret.as_bytes()).with_secure(settings.server.secure_cookies);
+    if !settings.cors.allowed_origins.is_empty() {
+        // sent along on the allowed origins' cross site requests
+        session_layer = session_layer.with_same_site_policy(SameSite::None);
+    }
+
+    let admin = Router::new()
+        .route("/album", post(admin::create_album))
+        .route("/album/:album_id", post(admin::update_album))
+        .route("/album/:album_id/detail", post(admin::update_album_detail))
+        .route("/album/:album_id/genres", post(admin::update_album_genres))
+        .route("/album/:album_id/merge", post(admin::merge_album))
+        .route("/genres", get(admin::genre_tree))
+        .route("/genre", post(admin::create_genre))
+        .route("/genre/:genre_id", post(admin::update_genre))
+        .route("/genre/:genre_id/delete", post(admin::delete_genre))
+        .route("/audit", get(admin::audit_log))
+        .route_layer(middleware::from_fn(http_cache::touch_catalog_on_write))
+        .route("/jobs", get(admin::jobs))
+        .route("/jobs/:name/run", post(admin::run_job));
 
     let api = Router::new()
         .route("/register", post(register))
         .route("/login", post(login))
-        .route("/logout", get(logout))
+        .route("/logout", post(logout))
         .route("/user_config", post(user_config))
         .route("/user", get(user_info))
+        .route("/verify_email", post(account::verify_email))
+        .route("/verify_email/resend", post(account::resend_verification))
+        .route("/password/forgot", post(account::forgot_password))
+        .route("/password/reset", post(account::reset_password))
+        .route("/account/password", post(account::change_password))
+        .route("/account/email", post(account::change_email))
+        .route("/account/delete", post(account::delete_account))
         .route("/today", get(get_today_album))
         .route("/album/:album_id", get(get_album_detail))
+        .route("/cover/:album_id", get(cover::cover))
         .route("/artist/:artist", get(get_artist_album))
         .route("/genres", get(genres))
+        .route("/providers", get(media::list_providers))
         .route("/genre/:genre", get(get_genre_album))
         .route("/user_album_log", get(get_user_album_log))
+        .route(
+            "/user_album_log/import",
+            post(listen_import::import_history).layer(DefaultBodyLimit::max(64 * 1024 * 1024)),
+        )
+        .route("/user_album_log/export", get(history_export::export_history))
+        .route("/devices", get(device::list_devices))
+        .route("/devices/:client_id", post(device::label_device))
+        .route("/devices/:client_id/logout", post(device::logout_device))
+        .nest("/admin", admin)
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/docs", get(openapi::docs))
+        .layer(middleware::from_fn(csrf::guard))
         .layer(cors)
         // .route_layer(from_extractor::<RequireAuth>())
-        .layer(SetResponseHeaderLayer::overriding(
+        .layer(SetResponseHeaderLayer::if_not_present(
             HeaderName::from_static("content-type"),
             HeaderValue::from_static("application/json"),
         ))
         .layer(session_layer)
-        .layer(Extension(MyShared { db: pool, redis }));
+        .layer(middleware::from_fn(telemetry::track))
+        .layer(Extension(state.clone()));
 
     let static_files_service = get_service(
-        ServeDir::new("../dist").fallback(ServeFile::new("../dist/index.html")), // .append_index_html_on_directories(true),
+        ServeDir::new(&settings.server.static_dir)
+            .fallback(ServeFile::new(format!("{}/index.html", settings.server.static_dir))), // .append_index_html_on_directories(true),
     )
     .handle_error(|error: std::io::Error| async move {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Unhandled internal error: {error}"),
         )
-    });
+    })
+    .layer(middleware::from_fn(http_cache::static_cache_control));
     // build our application with a route
     let app = Router::new()
+        .route("/metrics", get(telemetry::metrics))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .layer(Extension(state))
         .fallback(static_files_service)
         .nest("/api/v1", api);
 
-    // run our app with hyper
-    // `axum::Server` is a re-export of `hyper::Server`
-    let addr = SocketAddr::from(([0, 0, 0, 0], 5001));
-    tracing::debug!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    if settings.mail.transport == "outbox" {
+        tracing::warn!(
+            "mail.transport is \"outbox\", account mail is written to {} and never sent",
+            settings.mail.outbox_dir
+        );
+    }
+    if !settings.server.unix_socket.is_empty() && !settings.rate_limit.trust_forwarded {
+        tracing::warn!("listening on a unix socket without rate_limit.trust_forwarded, every client shares one address");
+    }
+    if let Err(e) = server::run(&settings.server, app, stopping).await {
+        tracing::error!("server error: {e}");
+        std::process::exit(1);
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
 pub struct SubjectArgs {
+    /// Random id the browser keeps in local storage
     pub client_id: String,
 }
 
+/// A page of random albums, from the user's genres when they set any.
+/// Pages are cached per client for the user's `fresh_time` minutes.
+#[utoipa::path(
+    get,
+    path = "/today",
+    params(SubjectArgs, Pagination),
+    responses((status = 200, description = "Albums, not wrapped in an envelope", body = [Album]))
+)]
 async fn get_today_album(
     Query(args): Query<SubjectArgs>,
     pagination: Option<Query<Pagination>>,
     session: ReadableSession,
     Extension(state): Extension<MyShared>,
-) -> impl IntoResponse {
+) -> Response {
     let client_id = args.client_id;
+    if let Err(e) = common::validation::client_id(&client_id) {
+        return bad_request(&e.message.unwrap_or_default()).into_response();
+    }
     let Query(pagination) = pagination.unwrap_or_default();
-    let page_client_id = format!("{}_{}", client_id, pagination.page);
+    if let Err(e) = pagination.validate() {
+        return bad_request(&e).into_response();
+    }
+    let page_client_id = feed::page_key(&client_id, pagination.page);
 
     let mut con = state.redis.get_async_connection().await.unwrap();
     let res: String = con.get(&page_client_id).await.unwrap_or_default();
-    if res.is_empty() {
-        let (fresh_time, user_genres) = match session.get::<usize>("fresh_time") {
+    state.metrics.feed_cache(!res.is_empty());
+    let body = if res.is_empty() {
+        let started = std::time::Instant::now();
+        let session_user_id = device::session_user_id(&session, &state.db).await;
+        let logged_in = session_user_id != 0;
+        let reader = match session.get::<usize>("fresh_time").filter(|_| logged_in) {
             // try get data in session
-            Some(fresh_time) => {
-                let user_genres: String = session.get("user_genres").unwrap_or_default();
-                (fresh_time, user_genres)
-            }
-            None => {
-                // try get data in database
-                let sql = format!(
-                    r#"SELECT id, username, email, password, session_id, genre_data, fresh_time from rym_user where
-                                  session_id like "%{client_id}%""#
-                );
-                match sqlx::query_as::<MySql, User>(&sql)
-                    .fetch_one(&state.db)
-                    .await
-                {
-                    Ok(user) => {
-                        let fresh_time: usize = user.fresh_time as usize;
-                        let user_genres: String = user.genre_data.unwrap_or_default();
-                        (fresh_time, user_genres)
-                    }
-                    Err(_) => (10, String::new()),
-                }
-            }
+            Some(fresh_time) => feed::Reader {
+                user_id: session_user_id,
+                fresh_time,
+                genres: session.get("user_genres").unwrap_or_default(),
+            },
+            // try get data in database
+            None => feed::reader_for_client(&state.db, &client_id).await,
         };
-        // no genres settings
-        let album_list = if user_genres.is_empty() {
-            let sql = format!(
-                r#"SELECT r1.id, name, cover FROM album AS r1 where locate("cdn", r1.cover) ORDER BY rand() ASC LIMIT {}"#,
-                pagination.page_size
-            );
-            sqlx::query_as::<MySql, Album>(&sql)
-                .fetch_all(&state.db)
-                .await
-        } else {
-            // use user genres settings
-            let search_key = user_genres.replace(',', "|");
-            let search_query = format!(
-                r#"r2.genre in (select name from genres where path REGEXP '^({search_key})')"#
-            );
-            let sql = format!(
-                r#"SELECT r1.id, name, cover FROM album AS r1 left join album_genre r2
-            on r1.id = r2.album_id where locate("cdn", r1.cover) and {}
-            ORDER BY rand() ASC LIMIT {}"#,
-                search_query, pagination.page_size
-            );
-            sqlx::query_as::<MySql, Album>(&sql)
-                .fetch_all(&state.db)
-                .await
-        };
-        if let Ok(album_list) = album_list {
+        // keeps the device from being pruned as idle
+        if reader.user_id != 0 {
+            device::touch(&state.db, &client_id).await;
+        }
+        if let Ok(album_list) = feed::generate(&state.db, &reader, pagination.page_size).await {
             // let mut res: Vec<Album> = vec![];
             // if pagination.page > 1 {
             // for i in 1..pagination.page - 1 {
@@ -230,32 +530,56 @@ async fn get_today_album(
             // }
             // res.extend(album_list);
             let json = serde_json::to_string(&album_list).unwrap();
-            let _: () = con
-                .set_ex(&page_client_id, &json, fresh_time * 60)
-                .await
-                .unwrap();
+            if let Err(e) = feed::cache_page(
+                &mut con,
+                &client_id,
+                pagination.page,
+                &json,
+                reader.fresh_time * 60,
+            )
+            .await
+            {
+                tracing::error!("redis error: {e}");
+            }
+            state.metrics.feed_generated(started.elapsed());
             json
         } else {
             "error".to_string()
         }
     } else {
         res
-    }
+    };
+    // the cached page is already serialized
+    ([(header::CONTENT_TYPE, "application/json")], body).into_response()
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, sqlx::FromRow)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, sqlx::FromRow, ToSchema)]
 pub struct Album {
     id: i32,
     name: String,
     cover: String,
+    /// Cover placeholders, once the cover proxy has seen the cover
+    #[sqlx(default)]
+    color: Option<String>,
+    #[sqlx(default)]
+    blurhash: Option<String>,
+    #[serde(skip)]
+    #[sqlx(default)]
+    media_url: Option<sqlx::types::Json<HashMap<String, serde_json::Value>>>,
+    /// The user's preferred provider page for the album
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    link: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, ToSchema)]
 pub struct AlbumDetail {
     id: i32,
     name: String,
     artist: String,
     cover: String,
+    /// Provider ids for the album, see `links` for the pages they make
+    #[schema(value_type = Object)]
     media_url: sqlx::types::Json<HashMap<String, serde_json::Value>>,
     descriptors: String,
     language: String,
@@ -263,17 +587,30 @@ pub struct AlbumDetail {
     released: String,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, Default, ToSchema)]
 pub struct AlbumGenre {
     genre: String,
     genre_type: String,
 }
 
+/// Album details with its genres and provider links. Logged in users get
+/// the visit counted in their history.
+#[utoipa::path(
+    get,
+    path = "/album/{album_id}",
+    params(("album_id" = u64, Path, description = "Album id")),
+    responses(
+        (status = 304, description = "Unchanged since If-None-Match or If-Modified-Since"),
+        (status = 200, body = AlbumPage),
+        (status = 400, description = "Unknown album", body = EmptyEnvelope)
+    )
+)]
 async fn get_album_detail(
     Path(album_id): Path<u64>,
     session: ReadableSession,
+    headers: HeaderMap,
     Extension(state): Extension<MyShared>,
-) -> impl IntoResponse {
+) -> Response {
     let sql = format!(
         r#"SELECT a.id, a.name, a.artist, a.cover, a.media_url, IFNULL(b.descriptors, '') as descriptors,
         IFNULL(b.released, '') as released, IFNULL(b.language, '') as language, IFNULL(b.rate, '') as rate
@@ -291,11 +628,14 @@ async fn get_album_detail(
                 .fetch_all(&state.db)
                 .await
                 .unwrap();
+            let user_id = device::session_user_id(&session, &state.db).await;
+            let mut links = media::links(&detail.media_url);
+            media::rank_links(&mut links, &media::user_preferred(&state.db, user_id).await);
             let mut j = serde_json::to_value(&detail).unwrap();
             j["genres"] = serde_json::to_value(genres.clone()).unwrap();
+            j["links"] = serde_json::to_value(links).unwrap();
 
             // insert album log
-            let user_id: i32 = session.get("user_id").unwrap_or_default();
             if user_id != 0 {
                 let album_genre: String = genres
                     .iter()
@@ -318,7 +658,7 @@ async fn get_album_detail(
                         sqlx::query(&update_sql).execute(&state.db).await.unwrap();
                     }
                     Err(e) => {
-                        println!("{e:#?}");
+                        tracing::debug!("first visit: {e}");
                         let insert_sql = format!(
                             r#"INSERT INTO user_album_log (user_id, album_id, album_genre, click_count,
                             listen_count) VALUES ("{}", "{}", "{}", 1, 1) "#,
@@ -328,14 +668,19 @@ async fn get_album_detail(
                     }
                 }
             }
-            (StatusCode::OK, Json(j))
+            // the visit is logged either way
+            let validators = http_cache::Validators::body(j.to_string().as_bytes());
+            if validators.fresh(&headers) {
+                return validators.not_modified(http_cache::ALBUM_CACHE_CONTROL);
+            }
+            validators.respond(Json(j), http_cache::ALBUM_CACHE_CONTROL)
         }
         Err(_) => {
             let resp = serde_json::json!({
                 "code": 400,
                 "msg": "api error"
             });
-            (StatusCode::BAD_REQUEST, Json(resp))
+            (StatusCode::BAD_REQUEST, Json(resp)).into_response()
         }
     }
 }
@@ -351,22 +696,30 @@ async fn generate_password(password: &str) -> String {
 }
 
 // the input to our `create_user` handler
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
 struct CreateUser {
+    /// 3 to 32 letters, digits, `_`, `-` or `.`
+    #[validate(custom = "common::validation::username")]
     username: String,
+    #[validate(custom = "common::validation::email")]
     email: String,
+    /// At least 8 characters, with a letter and a digit
+    #[validate(custom = "common::validation::password")]
     password: String,
+    #[validate(must_match(other = "password", message = "Passwords do not match"))]
     password_confirm: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
 struct Login {
     username: String,
     password: String,
+    /// Registered as one of the user's devices
+    #[validate(custom = "common::validation::client_id")]
     client_id: String,
 }
 
-#[derive(Serialize, Debug, sqlx::FromRow)]
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
 struct User {
     id: i32,
     username: String,
@@ -374,23 +727,47 @@ struct User {
     #[serde(skip_serializing)]
     password: String,
     #[sqlx(default)]
-    session_id: Option<String>,
-    #[sqlx(default)]
     genre_data: Option<String>,
     fresh_time: i32,
+    #[sqlx(default)]
+    role: String,
+    #[sqlx(default)]
+    preferred_providers: String,
+    #[sqlx(default)]
+    open_direct: bool,
+    #[sqlx(default)]
+    email_verified: bool,
 }
 
+#[utoipa::path(
+    post,
+    path = "/login",
+    request_body = Login,
+    responses(
+        (status = 200, description = "Sets the session cookie; `code` is 400 on a wrong password", body = UserEnvelope),
+        (status = 400, description = "Unknown user", body = EmptyEnvelope),
+        (status = 429, description = "Rate limited or locked out, see Retry-After", body = EmptyEnvelope)
+    ),
+    security(("csrf" = []))
+)]
 async fn login(
     Extension(state): Extension<MyShared>,
     mut session: WritableSession,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<Login>,
-) -> impl IntoResponse {
-    let sql = format!(
-        r#"SELECT id, username, email, password, session_id, genre_data, fresh_time from rym_user where
-                      username = "{}""#,
-        payload.username
-    );
-    match sqlx::query_as::<MySql, User>(&sql)
+) -> Response {
+    if let Err(e) = payload.validate() {
+        return validation::invalid(validation::field_errors(&e)).into_response();
+    }
+    let limit = state.limiter.login(ip, &payload.username).await;
+    if !limit.allowed() {
+        return limit.reject();
+    }
+    let sql = r#"SELECT id, username, email, password, genre_data, fresh_time, role,
+        preferred_providers, open_direct, email_verified from rym_user where username = ?"#;
+    match sqlx::query_as::<MySql, User>(sql)
+        .bind(&payload.username)
         .fetch_one(&state.db)
         .await
     {
@@ -406,22 +783,28 @@ async fn login(
                     .insert("fresh_time", exist_user.fresh_time)
                     .unwrap();
 
-                // update client session id
-                let session_id = match &exist_user.session_id {
-                    Some(session_id) => {
-                        if session_id.contains(&payload.client_id) {
-                            session_id.to_string()
-                        } else {
-                            format!("{},{}", session_id, payload.client_id)
-                        }
-                    }
-                    None => payload.client_id,
-                };
-                let update_sql = format!(
-                    r#"update rym_user set session_id = "{}" where id = {}"#,
-                    session_id, &exist_user.id
-                );
-                sqlx::query(&update_sql).execute(&state.db).await.unwrap();
+                session
+                    .insert("client_id", &payload.client_id)
+                    .unwrap();
+
+                // register this browser as one of the user's devices
+                let user_agent = user_agent
+                    .map(|TypedHeader(ua)| ua.to_string())
+                    .unwrap_or_default();
+                if let Err(e) =
+                    device::register(&state.db, &payload.client_id, exist_user.id, &user_agent).await
+                {
+                    tracing::error!("register device error: {e}");
+                    session.destroy();
+                    let resp = serde_json::json!({
+                        "code": 500,
+                        "msg": "login failed, try again"
+                    });
+                    return limit.respond((StatusCode::INTERNAL_SERVER_ERROR, Json(resp)));
+                }
+                // the anonymous feed cached for this browser is stale now
+                device::clear_feed_cache(&state.redis, &payload.client_id).await;
+                state.limiter.login_succeeded(&payload.username).await;
 
                 let resp = serde_json::json!({
                     "code": 200,
@@ -429,26 +812,49 @@ async fn login(
                     "data": exist_user
                 });
                 // let res = res_j.as_str();
-                return (StatusCode::OK, Json(resp));
+                return limit.respond((StatusCode::OK, Json(resp)));
             }
+            state.limiter.login_failed(&payload.username).await;
             let resp = serde_json::json!({
                 "code": 400,
                 "msg": "login failed"
             });
-            (StatusCode::OK, Json(resp))
+            limit.respond((StatusCode::OK, Json(resp)))
         }
         Err(e) => {
-            println!("error {e:#?}");
+            tracing::debug!("login of unknown user: {e}");
+            // unknown names count too, so probing for them locks out the same
+            state.limiter.login_failed(&payload.username).await;
             let resp = serde_json::json!({
                 "code": 400,
                 "msg": "login failed"
             });
-            (StatusCode::BAD_REQUEST, Json(resp))
+            limit.respond((StatusCode::BAD_REQUEST, Json(resp)))
         }
     }
 }
 
-async fn logout(mut session: WritableSession) -> impl IntoResponse {
+/// End the session and forget the current device.
+#[utoipa::path(
+    post,
+    path = "/logout",
+    responses((status = 200, body = EmptyEnvelope)),
+    security(("session" = [], "csrf" = []))
+)]
+async fn logout(
+    Extension(state): Extension<MyShared>,
+    mut session: WritableSession,
+) -> impl IntoResponse {
+    if let Some(client_id) = session.get::<String>("client_id") {
+        let user_id: i32 = session.get("user_id").unwrap_or_default();
+        sqlx::query("DELETE FROM user_device WHERE client_id = ? AND user_id = ?")
+            .bind(&client_id)
+            .bind(user_id)
+            .execute(&state.db)
+            .await
+            .ok();
+        device::clear_feed_cache(&state.redis, &client_id).await;
+    }
     session.destroy();
     let resp = serde_json::json!({
         "code": 200,
@@ -458,76 +864,112 @@ async fn logout(mut session: WritableSession) -> impl IntoResponse {
     (StatusCode::OK, Json(resp))
 }
 
+#[utoipa::path(
+    post,
+    path = "/register",
+    request_body = CreateUser,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Invalid fields, or the name or address is taken", body = Invalid),
+        (status = 429, description = "Rate limited or locked out, see Retry-After", body = EmptyEnvelope)
+    ),
+    security(("csrf" = []))
+)]
 async fn register(
     Extension(state): Extension<MyShared>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<CreateUser>,
-) -> impl IntoResponse {
-    // insert your application logic here
-
-    if payload.password_confirm != payload.password {
-        let resp = serde_json::json!({
-            "code": 400,
-            "msg": "error"
-        });
-        let resp = serde_json::to_string(&resp).unwrap();
-        return (StatusCode::BAD_REQUEST, resp);
+) -> Response {
+    let limit = state.limiter.register(ip).await;
+    if !limit.allowed() {
+        return limit.reject();
     }
-    let sql = format!(
-        r#"SELECT id, username, email, password, session_id from rym_user where
-                      username = "{}" and email = "{}""#,
-        payload.username, payload.email
-    );
-    match sqlx::query_as::<MySql, User>(&sql)
-        .fetch_one(&state.db)
-        .await
-    {
-        Ok(_) => {
-            // println!("found user {exist_user:#?}");
+    limit.respond(create_user(&state, payload).await)
+}
+
+async fn create_user(state: &MyShared, payload: CreateUser) -> (StatusCode, Json<serde_json::Value>) {
+    let mut errors = match payload.validate() {
+        Ok(()) => validation::FieldErrors::new(),
+        Err(e) => validation::field_errors(&e),
+    };
+    // checked apart so a taken name and a taken address are both reported
+    for (field, value, msg) in [
+        ("username", &payload.username, "Username is already taken"),
+        ("email", &payload.email, "Email is already registered"),
+    ] {
+        if errors.contains_key(field) {
+            continue;
+        }
+        let sql = format!("SELECT COUNT(*) FROM rym_user WHERE {field} = ?");
+        match sqlx::query_scalar::<_, i64>(&sql)
+            .bind(value)
+            .fetch_one(&state.db)
+            .await
+        {
+            Ok(0) => {}
+            Ok(_) => {
+                errors.insert(field.to_string(), vec![msg.to_string()]);
+            }
+            Err(e) => {
+                tracing::error!("register error: {e}");
+                return bad_request("error");
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return validation::invalid(errors);
+    }
+
+    let password = generate_password(&payload.password).await;
+    let res = sqlx::query(
+        "INSERT INTO rym_user (username, email, password, fresh_time) VALUES (?, ?, ?, 10)",
+    )
+    .bind(&payload.username)
+    .bind(&payload.email)
+    .bind(password)
+    .execute(&state.db)
+    .await;
+    match res {
+        Ok(res) => {
+            let user_id = res.last_insert_id() as i32;
+            // the account works without it; the profile offers to resend
+            if let Err(e) =
+                account::send_verification(state, user_id, &payload.username, &payload.email)
+                    .await
+            {
+                tracing::error!("verification mail error: {e}");
+            }
             let resp = serde_json::json!({
-                "code": 400,
-                "msg": "error"
+                "code": 200,
+                "msg": "register success",
+                "data": {}
             });
-            let resp = serde_json::to_string(&resp).unwrap();
-            (StatusCode::BAD_REQUEST, resp)
+            (StatusCode::OK, Json(resp))
         }
-        Err(_) => {
-            // println!("no user {e:#?}");
-            let password = generate_password(&payload.password).await;
-            let insert_sql = format!(
-                r#"INSERT INTO rym_user (username, email, password, fresh_time) VALUES ("{}",
-            "{}", "{}", 10) "#,
-                payload.username, payload.email, password
-            );
-            match sqlx::query(&insert_sql).execute(&state.db).await {
-                Ok(_) => {
-                    let resp = serde_json::json!({
-                        "code": 200,
-                        "msg": "register success",
-                        "data": {}
-                    });
-                    let resp = serde_json::to_string(&resp).unwrap();
-                    (StatusCode::OK, resp)
-                }
-                Err(_) => {
-                    let resp = serde_json::json!({
-                        "code": 400,
-                        "msg": "error"
-                    });
-                    let resp = serde_json::to_string(&resp).unwrap();
-                    (StatusCode::BAD_REQUEST, resp)
-                }
-            }
+        Err(e) => {
+            tracing::error!("register error: {e}");
+            bad_request("error")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/user",
+    responses(
+        (status = 200, body = UserEnvelope),
+        (status = 400, description = "Not logged in", body = EmptyEnvelope)
+    ),
+    security(("session" = []))
+)]
 async fn user_info(
     Extension(state): Extension<MyShared>,
     session: ReadableSession,
 ) -> impl IntoResponse {
-    let user_id: i32 = session.get("user_id").unwrap_or_default();
+    let user_id = device::session_user_id(&session, &state.db).await;
     let sql = format!(
-        r#"SELECT id, username, email, password, session_id, genre_data, fresh_time from rym_user where
+        r#"SELECT id, username, email, password, genre_data, fresh_time, role,
+        preferred_providers, open_direct, email_verified from rym_user where
                       id = "{user_id}""#
     );
     match sqlx::query_as::<MySql, User>(&sql)
@@ -552,17 +994,31 @@ async fn user_info(
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, ToSchema)]
 pub struct Genre {
     id: i32,
     name: String,
     key_name: String,
 }
 
-async fn genres(Extension(state): Extension<MyShared>) -> impl IntoResponse {
+/// Top level genres.
+#[utoipa::path(
+    get,
+    path = "/genres",
+    responses(
+        (status = 304, description = "Unchanged since If-None-Match or If-Modified-Since"),
+        (status = 200, body = GenresEnvelope),
+        (status = 400, body = EmptyEnvelope)
+    )
+)]
+async fn genres(headers: HeaderMap, Extension(state): Extension<MyShared>) -> Response {
+    http_cache::catalog(&state.redis, &headers, genre_list(&state.db)).await
+}
+
+async fn genre_list(db: &MySqlPool) -> impl IntoResponse {
     let sql = r#"select id, name, key_name from genres where parents = """#.to_string();
     match sqlx::query_as::<MySql, Genre>(&sql)
-        .fetch_all(&state.db)
+        .fetch_all(db)
         .await
     {
         Ok(genres) => {
@@ -586,26 +1042,81 @@ async fn genres(Extension(state): Extension<MyShared>) -> impl IntoResponse {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
 struct UserConfig {
+    /// Genre paths, comma separated
+    #[validate(custom = "common::validation::genres")]
     genres: String,
+    /// Minutes a feed page stays cached, 1 to 3600
+    #[validate(custom = "common::validation::fresh_time")]
     fresh_time: String,
+    /// Provider keys, best first; left alone when missing
+    providers: Option<Vec<String>>,
+    open_direct: Option<bool>,
 }
 
+#[utoipa::path(
+    post,
+    path = "/user_config",
+    request_body = UserConfig,
+    responses(
+        (status = 200, body = EmptyEnvelope),
+        (status = 400, description = "Invalid fields, unknown provider or update failed", body = Invalid)
+    ),
+    security(("session" = [], "csrf" = []))
+)]
 async fn user_config(
     Extension(state): Extension<MyShared>,
     session: ReadableSession,
     Json(payload): Json<UserConfig>,
 ) -> impl IntoResponse {
-    let user_id: i32 = session.get("user_id").unwrap_or_default();
-    let sql = format!(
-        r#"update rym_user set genre_data = '{}', fresh_time = '{}' where id = {}"#,
-        &payload.genres, payload.fresh_time, user_id,
-    );
-    // println!("{:#?}", sql);
-    match sqlx::query(&sql).execute(&state.db).await {
-        Ok(res) => {
-            println!("res {res:#?}");
+    let user_id = device::session_user_id(&session, &state.db).await;
+    if let Err(e) = payload.validate() {
+        return validation::invalid(validation::field_errors(&e));
+    }
+    for path in payload.genres.split(',').filter(|p| !p.is_empty()) {
+        let known: Option<i32> = sqlx::query_scalar("SELECT id FROM genres WHERE path = ? LIMIT 1")
+            .bind(path)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or_default();
+        if known.is_none() {
+            let errors = [("genres".to_string(), vec![format!("Unknown genre {path}")])];
+            return validation::invalid(errors.into_iter().collect());
+        }
+    }
+    let fresh_time: i32 = payload.fresh_time.trim().parse().unwrap_or_default();
+    let providers = match &payload.providers {
+        Some(keys) => match keys.iter().find(|k| media::provider(k).is_none()) {
+            Some(unknown) => {
+                let resp = serde_json::json!({
+                    "code": 400,
+                    "msg": format!("unknown provider {unknown}")
+                });
+                return (StatusCode::BAD_REQUEST, Json(resp));
+            }
+            None => Some(media::preferred(&keys.join(",")).join(",")),
+        },
+        None => None,
+    };
+    let res = sqlx::query(
+        r#"update rym_user set genre_data = ?, fresh_time = ?,
+        preferred_providers = IFNULL(?, preferred_providers), open_direct = IFNULL(?, open_direct)
+        where id = ?"#,
+    )
+    .bind(&payload.genres)
+    .bind(fresh_time)
+    .bind(providers)
+    .bind(payload.open_direct)
+    .bind(user_id)
+    .execute(&state.db)
+    .await;
+    match res {
+        Ok(_) => {
+            // cached feed pages carry links for the old preferences
+            if let Some(client_id) = session.get::<String>("client_id") {
+                device::clear_feed_cache(&state.redis, &client_id).await;
+            }
             let resp = serde_json::json!({
                 "code": 200,
                 "msg": "success",
@@ -614,7 +1125,7 @@ async fn user_config(
             (StatusCode::OK, Json(resp))
         }
         Err(e) => {
-            println!("no user {e:#?}");
+            tracing::error!("user config error: {e}");
             let resp = serde_json::json!({
                 "code": 400,
                 "msg": "failed"
@@ -629,23 +1140,20 @@ pub struct TotalResponse {
     total: i32,
 }
 
-#[derive(Deserialize)]
-pub struct Pagination {
-    pub page: usize,
-    pub page_size: usize,
-}
-
-impl Default for Pagination {
-    fn default() -> Self {
-        Self {
-            page: 1,
-            page_size: 40,
-        }
-    }
+fn bad_request(msg: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let resp = serde_json::json!({
+        "code": 400,
+        "msg": msg
+    });
+    (StatusCode::BAD_REQUEST, Json(resp))
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, sqlx::FromRow)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, sqlx::FromRow, ToSchema)]
 pub struct UserAlbumLog {
+    #[serde(skip)]
+    id: i32,
+    #[serde(skip)]
+    create_time: String,
     album_id: String,
     album_name: String,
     cover: String,
@@ -653,35 +1161,68 @@ pub struct UserAlbumLog {
     listen_count: i32,
 }
 
+/// The user's listening history, latest first.
+#[utoipa::path(
+    get,
+    path = "/user_album_log",
+    params(Pagination),
+    responses(
+        (status = 200, body = AlbumLogEnvelope),
+        (status = 400, body = EmptyEnvelope)
+    ),
+    security(("session" = []))
+)]
 async fn get_user_album_log(
     pagination: Option<Query<Pagination>>,
     Extension(state): Extension<MyShared>,
     session: ReadableSession,
 ) -> impl IntoResponse {
-    let user_id: i32 = session.get("user_id").unwrap_or_default();
+    let user_id = device::session_user_id(&session, &state.db).await;
     let Query(pagination) = pagination.unwrap_or_default();
+    let after = match pagination.validate().and_then(|_| pagination.after()) {
+        Ok(after) => after,
+        Err(e) => return bad_request(&e),
+    };
 
-    let total_sql = format!(
-        "SELECT count(*) AS total FROM user_album_log WHERE user_id = '{user_id}'"
-    );
-    let total_count = sqlx::query_as::<_, TotalResponse>(&total_sql)
+    let total = if pagination.with_total() {
+        let total_count = sqlx::query_as::<_, TotalResponse>(
+            "SELECT count(*) AS total FROM user_album_log WHERE user_id = ?",
+        )
+        .bind(user_id.to_string())
         .fetch_one(&state.db)
         .await
         .unwrap();
+        Some(total_count.total)
+    } else {
+        None
+    };
 
+    let keyset = if after.is_some() {
+        "AND (r1.create_time < ? OR (r1.create_time = ? AND r1.id < ?))"
+    } else {
+        ""
+    };
     let sql = format!(
-        r#"select album_id, r2.name as album_name, r2.cover, click_count, listen_count from
+        r#"select r1.id, CAST(r1.create_time AS CHAR) as create_time, album_id,
+        r2.name as album_name, r2.cover, click_count, listen_count from
         user_album_log as r1 left join album as r2 on r1.album_id = r2.id
-        where r1.user_id = '{}' ORDER BY r1.create_time desc limit {}, {}"#,
-        user_id,
-        pagination.page_size * (pagination.page - 1),
-        pagination.page_size
+        where r1.user_id = ? {keyset} ORDER BY r1.create_time desc, r1.id desc limit ?, ?"#
     );
-    match sqlx::query_as::<MySql, UserAlbumLog>(&sql)
+    let mut query = sqlx::query_as::<MySql, UserAlbumLog>(&sql).bind(user_id.to_string());
+    if let Some(after) = &after {
+        query = query.bind(&after.key).bind(&after.key).bind(after.id);
+    }
+    let offset = if after.is_some() { 0 } else { pagination.offset() };
+    match query
+        .bind(offset as u64)
+        .bind(pagination.page_size as u64)
         .fetch_all(&state.db)
         .await
     {
         Ok(res) => {
+            let next_cursor = pagination::next_cursor(&res, pagination.page_size, |l| {
+                Cursor::new(&l.create_time, l.id)
+            });
             let resp = serde_json::json!({
                 "code": 200,
                 "msg": "success",
@@ -689,7 +1230,8 @@ async fn get_user_album_log(
                     "res": res,
                     "page": pagination.page,
                     "page_size": pagination.page_size,
-                    "total": total_count.total,
+                    "total": total,
+                    "next_cursor": next_cursor,
                 }
             });
             (StatusCode::OK, Json(resp))
@@ -704,43 +1246,101 @@ async fn get_user_album_log(
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, sqlx::FromRow)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, sqlx::FromRow, ToSchema)]
 pub struct AlbumChart {
     id: i32,
     name: String,
     artist: String,
     cover: String,
+    #[sqlx(default)]
+    color: Option<String>,
+    #[sqlx(default)]
+    blurhash: Option<String>,
     rate: String,
 }
 
+/// Albums of a genre, best rated first.
+#[utoipa::path(
+    get,
+    path = "/genre/{genre}",
+    params(("genre" = String, Path, description = "Genre name"), Pagination),
+    responses(
+        (status = 304, description = "Unchanged since If-None-Match or If-Modified-Since"),
+        (status = 200, body = AlbumChartEnvelope),
+        (status = 400, body = EmptyEnvelope)
+    )
+)]
 async fn get_genre_album(
     pagination: Option<Query<Pagination>>,
     Path(genre): Path<String>,
+    headers: HeaderMap,
     Extension(state): Extension<MyShared>,
     // session: ReadableSession,
-) -> impl IntoResponse {
+) -> Response {
     // let user_id: i32 = session.get("user_id").unwrap_or_default();
     let Query(pagination) = pagination.unwrap_or_default();
+    let chart = album_chart(
+        &state.db,
+        &pagination,
+        "SELECT count(*) AS total FROM album left join album_genre on album.id = album_genre.album_id where genre = ?",
+        r#"left join album_genre as r2 on r1.id = r2.album_id
+        left join album_detail as r3 on r1.id = r3.album_id where r2.genre = ?"#,
+        &genre,
+    );
+    http_cache::catalog(&state.redis, &headers, chart).await
+}
 
-    let total_sql = format!("SELECT count(*) AS total FROM album left join album_genre on album.id = album_genre.album_id where genre = '{genre}'");
-    let total_count = sqlx::query_as::<_, TotalResponse>(&total_sql)
-        .fetch_one(&state.db)
-        .await
-        .unwrap();
+/// One page of albums ranked by rate, for `filter` (a join and where clause
+/// with one placeholder) bound to `value`.
+async fn album_chart(
+    db: &MySqlPool,
+    pagination: &Pagination,
+    total_sql: &str,
+    filter: &str,
+    value: &str,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let after = match pagination.validate().and_then(|_| pagination.after()) {
+        Ok(after) => after,
+        Err(e) => return bad_request(&e),
+    };
+
+    let total = if pagination.with_total() {
+        let total_count = sqlx::query_as::<_, TotalResponse>(total_sql)
+            .bind(value)
+            .fetch_one(db)
+            .await
+            .unwrap();
+        Some(total_count.total)
+    } else {
+        None
+    };
 
+    let rate = "IFNULL(r3.rate, '0.00')";
+    let keyset = if after.is_some() {
+        format!("AND ({rate} < ? OR ({rate} = ? AND r1.id < ?))")
+    } else {
+        String::new()
+    };
     let sql = format!(
-        r#"select r1.id, r1.name, r1.artist, r1.cover, r3.rate from album as r1 left join album_genre as r2 on r1.id = r2.album_id
-        left join album_detail as r3 on r1.id = r3.album_id where r2.genre = "{}"
-        order by r3.rate desc limit {},{}"#,
-        genre,
-        pagination.page_size * (pagination.page - 1),
-        pagination.page_size
+        r#"select r1.id, r1.name, r1.artist, r1.cover, r1.cover_color as color,
+        r1.cover_blurhash as blurhash, {rate} as rate from album as r1 {filter} {keyset}
+        order by {rate} desc, r1.id desc limit ?, ?"#
     );
-    match sqlx::query_as::<MySql, AlbumChart>(&sql)
-        .fetch_all(&state.db)
+    let mut query = sqlx::query_as::<MySql, AlbumChart>(&sql).bind(value);
+    if let Some(after) = &after {
+        query = query.bind(&after.key).bind(&after.key).bind(after.id);
+    }
+    let offset = if after.is_some() { 0 } else { pagination.offset() };
+    match query
+        .bind(offset as u64)
+        .bind(pagination.page_size as u64)
+        .fetch_all(db)
         .await
     {
         Ok(res) => {
+            let next_cursor = pagination::next_cursor(&res, pagination.page_size, |a| {
+                Cursor::new(&a.rate, a.id)
+            });
             let resp = serde_json::json!({
                 "code": 200,
                 "msg": "success",
@@ -748,7 +1348,8 @@ async fn get_genre_album(
                     "res": res,
                     "page": pagination.page,
                     "page_size": pagination.page_size,
-                    "total": total_count.total,
+                    "total": total,
+                    "next_cursor": next_cursor,
                 }
             });
             (StatusCode::OK, Json(resp))
@@ -763,54 +1364,32 @@ async fn get_genre_album(
     }
 }
 
+/// Albums of an artist, best rated first.
+#[utoipa::path(
+    get,
+    path = "/artist/{artist}",
+    params(("artist" = String, Path, description = "Artist name"), Pagination),
+    responses(
+        (status = 304, description = "Unchanged since If-None-Match or If-Modified-Since"),
+        (status = 200, body = AlbumChartEnvelope),
+        (status = 400, body = EmptyEnvelope)
+    )
+)]
 async fn get_artist_album(
     pagination: Option<Query<Pagination>>,
     Path(artist): Path<String>,
+    headers: HeaderMap,
     Extension(state): Extension<MyShared>,
     // session: ReadableSession,
-) -> impl IntoResponse {
+) -> Response {
     // let user_id: i32 = session.get("user_id").unwrap_or_default();
     let Query(pagination) = pagination.unwrap_or_default();
-
-    let total_sql = format!(
-        "SELECT count(*) AS total FROM album where artist = '{artist}'"
-    );
-    let total_count = sqlx::query_as::<_, TotalResponse>(&total_sql)
-        .fetch_one(&state.db)
-        .await
-        .unwrap();
-
-    let sql = format!(
-        r#"select r1.id, r1.name, r1.artist, r1.cover, IFNULL(r3.rate, '0.00') as rate from album as r1
-        left join album_detail as r3 on r1.id = r3.album_id where r1.artist = "{}"
-        order by r3.rate desc limit {},{}"#,
-        artist,
-        pagination.page_size * (pagination.page - 1),
-        pagination.page_size
+    let chart = album_chart(
+        &state.db,
+        &pagination,
+        "SELECT count(*) AS total FROM album where artist = ?",
+        "left join album_detail as r3 on r1.id = r3.album_id where r1.artist = ?",
+        &artist,
     );
-    match sqlx::query_as::<MySql, AlbumChart>(&sql)
-        .fetch_all(&state.db)
-        .await
-    {
-        Ok(res) => {
-            let resp = serde_json::json!({
-                "code": 200,
-                "msg": "success",
-                "data": {
-                    "res": res,
-                    "page": pagination.page,
-                    "page_size": pagination.page_size,
-                    "total": total_count.total,
-                }
-            });
-            (StatusCode::OK, Json(resp))
-        }
-        Err(_) => {
-            let resp = serde_json::json!({
-                "code": 400,
-                "msg": "failed"
-            });
-            (StatusCode::BAD_REQUEST, Json(resp))
-        }
-    }
+    http_cache::catalog(&state.redis, &headers, chart).await
 }
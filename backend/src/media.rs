@@ -0,0 +1,588 @@
+use axum::{response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::MySqlPool;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// One entry under a provider in `album.media_url`, keyed by the provider's
+/// id for the release: `{"spotify": {"7dxKtc08dYeRVHt3p9CZJn": {"default": true}}}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MediaEntry {
+    pub default: bool,
+    /// Release kind on providers that host more than albums, e.g. `playlist`
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// Apple Music album slug
+    pub album: Option<String>,
+    /// Apple Music storefront
+    pub loc: Option<String>,
+    /// Full page address, without scheme, for providers whose ids don't make
+    /// a url (Bandcamp, SoundCloud)
+    pub url: Option<String>,
+}
+
+/// How a provider's ids are stored and turned into a page address. Adding
+/// a provider is one entry in [`PROVIDERS`] plus its icon under
+/// `public/media_link`.
+#[derive(Serialize, ToSchema)]
+pub struct Provider {
+    pub key: &'static str,
+    pub title: &'static str,
+    pub icon: &'static str,
+    /// Entry fields besides `default` the builder reads
+    #[schema(value_type = Vec<String>)]
+    pub fields: &'static [&'static str],
+    #[serde(skip)]
+    pub build: fn(&str, &MediaEntry) -> Result<String, String>,
+}
+
+pub const PROVIDERS: &[Provider] = &[
+    Provider {
+        key: "spotify",
+        title: "Spotify",
+        icon: "/public/media_link/spotifyDark32x32.svg",
+        fields: &["type"],
+        build: spotify,
+    },
+    Provider {
+        key: "applemusic",
+        title: "Apple Music",
+        icon: "/public/media_link/itunes32x32.svg",
+        fields: &["album", "loc"],
+        build: apple_music,
+    },
+    Provider {
+        key: "tidal",
+        title: "Tidal",
+        icon: "/public/media_link/tidal32x32.svg",
+        fields: &[],
+        build: |id, _| Ok(format!("https://tidal.com/browse/album/{}", digits(id, "tidal")?)),
+    },
+    Provider {
+        key: "deezer",
+        title: "Deezer",
+        icon: "/public/media_link/deezer32x32.svg",
+        fields: &[],
+        build: |id, _| Ok(format!("https://www.deezer.com/album/{}", digits(id, "deezer")?)),
+    },
+    Provider {
+        key: "bandcamp",
+        title: "Bandcamp",
+        icon: "/public/media_link/bandcamp32x32.svg",
+        fields: &["url"],
+        build: |_, entry| page_url(entry, "bandcamp", BANDCAMP_HOSTS),
+    },
+    Provider {
+        key: "soundcloud",
+        title: "SoundCloud",
+        icon: "/public/media_link/soundcloud32x32.svg",
+        fields: &["url"],
+        build: |_, entry| page_url(entry, "soundcloud", SOUNDCLOUD_HOSTS),
+    },
+    Provider {
+        key: "youtube",
+        title: "YouTube",
+        icon: "/public/media_link/youtube32x32.svg",
+        fields: &["type"],
+        build: youtube,
+    },
+    Provider {
+        key: "youtubemusic",
+        title: "YouTube Music",
+        icon: "/public/media_link/youtubemusic32x32.svg",
+        fields: &[],
+        build: youtube_music,
+    },
+    Provider {
+        key: "netease",
+        title: "Netease",
+        icon: "/public/media_link/netease32x32.jpg",
+        fields: &[],
+        build: |id, _| Ok(format!("https://music.163.com/#/album?id={}", digits(id, "netease")?)),
+    },
+    Provider {
+        key: "qqmusic",
+        title: "QQMusic",
+        icon: "/public/media_link/qqmusic32x32.png",
+        fields: &[],
+        build: qq_music,
+    },
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct MediaLink {
+    pub provider: &'static str,
+    pub title: &'static str,
+    pub icon: &'static str,
+    pub url: String,
+    pub is_default: bool,
+    /// Set on the links of the user's best ranked provider
+    pub is_preferred: bool,
+}
+
+/// Storefront for Apple Music entries without `loc`, the one album pages
+/// have always linked to.
+const APPLE_MUSIC_STOREFRONT: &str = "gb";
+
+fn is_id(id: &str, extra: &[char]) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || extra.contains(&c))
+}
+
+fn digits<'a>(id: &'a str, provider: &str) -> Result<&'a str, String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("{id:?} is not a {provider} id"));
+    }
+    Ok(id)
+}
+
+fn spotify(id: &str, entry: &MediaEntry) -> Result<String, String> {
+    if id.len() != 22 || !is_id(id, &[]) {
+        return Err(format!("{id:?} is not a spotify id"));
+    }
+    let kind = entry.kind.as_deref().unwrap_or("album");
+    if !matches!(kind, "album" | "track" | "playlist") {
+        return Err(format!("unknown spotify type {kind:?}"));
+    }
+    Ok(format!("https://open.spotify.com/{kind}/{id}"))
+}
+
+fn apple_music(id: &str, entry: &MediaEntry) -> Result<String, String> {
+    let id = digits(id, "apple music")?;
+    let album = match entry.album.as_deref() {
+        Some(album) if is_id(album, &['-', '_', '.']) => album,
+        Some(album) => return Err(format!("{album:?} is not an apple music album slug")),
+        None => return Err("apple music entries need an album slug".to_string()),
+    };
+    let loc = match entry.loc.as_deref() {
+        Some(loc) if loc.len() == 2 && is_id(loc, &[]) => loc.to_ascii_lowercase(),
+        Some(loc) => return Err(format!("{loc:?} is not a storefront")),
+        None => APPLE_MUSIC_STOREFRONT.to_string(),
+    };
+    Ok(format!("https://geo.music.apple.com/{loc}/album/{album}/{id}"))
+}
+
+fn youtube(id: &str, entry: &MediaEntry) -> Result<String, String> {
+    if !is_id(id, &['-', '_']) {
+        return Err(format!("{id:?} is not a youtube id"));
+    }
+    match entry.kind.as_deref() {
+        Some("playlist") => Ok(format!("https://www.youtube.com/playlist?list={id}")),
+        _ if id.len() == 11 => Ok(format!("https://www.youtube.com/watch?v={id}")),
+        _ => Err(format!("{id:?} is not a youtube video id")),
+    }
+}
+
+/// Album pages are `MPREb_` browse ids; `OLAK5uy_` ids are their playlists.
+fn youtube_music(id: &str, _: &MediaEntry) -> Result<String, String> {
+    let valid = |prefix: &str| {
+        id.strip_prefix(prefix)
+            .is_some_and(|rest| is_id(rest, &['-', '_']))
+    };
+    if valid("MPREb") {
+        Ok(format!("https://music.youtube.com/browse/{id}"))
+    } else if valid("OLAK5uy_") {
+        Ok(format!("https://music.youtube.com/playlist?list={id}"))
+    } else {
+        Err(format!("{id:?} is not a youtube music album id"))
+    }
+}
+
+fn qq_music(id: &str, _: &MediaEntry) -> Result<String, String> {
+    if !is_id(id, &[]) {
+        return Err(format!("{id:?} is not a qq music id"));
+    }
+    Ok(format!("https://y.qq.com/n/ryqq/albumDetail/{id}"))
+}
+
+/// Artist subdomains only; custom domains can point anywhere.
+const BANDCAMP_HOSTS: &[&str] = &["*.bandcamp.com"];
+const SOUNDCLOUD_HOSTS: &[&str] = &["soundcloud.com", "www.soundcloud.com", "on.soundcloud.com"];
+
+/// Whether `host` is one of `hosts`, where `*.` stands for one subdomain.
+fn host_allowed(host: &str, hosts: &[&str]) -> bool {
+    hosts.iter().any(|allowed| match allowed.strip_prefix('*') {
+        Some(suffix) => host
+            .strip_suffix(suffix)
+            .is_some_and(|sub| !sub.is_empty() && !sub.contains('.')),
+        None => host == *allowed,
+    })
+}
+
+/// `url` entries are stored without scheme; tolerate one anyway. The host
+/// must be one of the provider's.
+fn page_url(entry: &MediaEntry, provider: &str, hosts: &[&str]) -> Result<String, String> {
+    let url = entry.url.as_deref().unwrap_or_default().trim();
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    if url.is_empty() || url.contains(char::is_whitespace) {
+        return Err(format!("{provider} entries need a page url"));
+    }
+    let host = url.split('/').next().unwrap_or_default().to_ascii_lowercase();
+    let plain = host
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'));
+    if !plain || !host_allowed(&host, hosts) {
+        return Err(format!("{host:?} is not a {provider} host"));
+    }
+    Ok(format!("https://{url}"))
+}
+
+pub fn provider(key: &str) -> Option<&'static Provider> {
+    PROVIDERS.iter().find(|p| p.key == key)
+}
+
+fn entry_link(
+    provider: &'static Provider,
+    id: &str,
+    entry: &serde_json::Value,
+) -> Result<MediaLink, String> {
+    let entry = serde_json::from_value::<MediaEntry>(entry.clone())
+        .map_err(|e| format!("media_url.{}.{id}: {e}", provider.key))?;
+    let url = (provider.build)(id.trim(), &entry)
+        .map_err(|e| format!("media_url.{}.{id}: {e}", provider.key))?;
+    Ok(MediaLink {
+        provider: provider.key,
+        title: provider.title,
+        icon: provider.icon,
+        url,
+        is_default: entry.default,
+        is_preferred: false,
+    })
+}
+
+/// Exactly one default per provider: the first flagged one, or the first one.
+fn normalize_defaults(links: &mut [MediaLink]) {
+    let mut start = 0;
+    while start < links.len() {
+        let provider = links[start].provider;
+        let end = links[start..]
+            .iter()
+            .position(|l| l.provider != provider)
+            .map_or(links.len(), |n| start + n);
+        let default = links[start..end].iter().position(|l| l.is_default).unwrap_or(0);
+        for (n, link) in links[start..end].iter_mut().enumerate() {
+            link.is_default = n == default;
+        }
+        start = end;
+    }
+}
+
+/// Check `media_url` before it is stored. Entries for providers we don't
+/// build links for are kept as they are.
+pub fn validate(media_url: &HashMap<String, serde_json::Value>) -> Result<(), String> {
+    for (key, entries) in media_url {
+        match provider(key) {
+            Some(provider) => {
+                let entries = entries
+                    .as_object()
+                    .ok_or_else(|| format!("media_url.{key} must be an object"))?;
+                for (id, entry) in entries {
+                    entry_link(provider, id, entry)?;
+                }
+            }
+            None if entries.is_object() => {}
+            None => return Err(format!("media_url.{key} must be an object")),
+        }
+    }
+    Ok(())
+}
+
+/// Links for the album page, in provider order with the default of each
+/// provider first. Entries that don't validate are left out.
+pub fn links(media_url: &HashMap<String, serde_json::Value>) -> Vec<MediaLink> {
+    let mut links = vec![];
+    for provider in PROVIDERS {
+        let Some(entries) = media_url.get(provider.key) else {
+            continue;
+        };
+        let Some(map) = entries.as_object() else {
+            tracing::warn!("skipping media_url.{}: not an object", provider.key);
+            continue;
+        };
+        for (id, entry) in map {
+            match entry_link(provider, id, entry) {
+                Ok(link) => links.push(link),
+                Err(e) => tracing::warn!("skipping {e}"),
+            }
+        }
+    }
+    normalize_defaults(&mut links);
+    links.sort_by_key(|l| {
+        let order = PROVIDERS.iter().position(|p| p.key == l.provider);
+        (order, !l.is_default)
+    });
+    links
+}
+
+/// Provider keys from a stored preference list, best first. Keys we no
+/// longer know are dropped.
+pub fn preferred(list: &str) -> Vec<&'static str> {
+    let mut keys: Vec<&'static str> = vec![];
+    for key in list.split(',').map(str::trim) {
+        if let Some(p) = provider(key) {
+            if !keys.contains(&p.key) {
+                keys.push(p.key);
+            }
+        }
+    }
+    keys
+}
+
+pub async fn user_preferred(db: &MySqlPool, user_id: i32) -> Vec<&'static str> {
+    if user_id == 0 {
+        return vec![];
+    }
+    let list: Option<String> =
+        sqlx::query_scalar("SELECT preferred_providers FROM rym_user WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(db)
+            .await
+            .unwrap_or_default();
+    preferred(&list.unwrap_or_default())
+}
+
+/// Move links of preferred providers to the front, in preference order, and
+/// mark the best one the album has.
+pub fn rank_links(links: &mut [MediaLink], preferred: &[&str]) {
+    let rank = |l: &MediaLink| {
+        preferred
+            .iter()
+            .position(|p| *p == l.provider)
+            .unwrap_or(preferred.len())
+    };
+    links.sort_by_key(rank);
+    if let Some(best) = links.first().filter(|l| rank(l) < preferred.len()) {
+        let best = best.provider;
+        for link in links.iter_mut().filter(|l| l.provider == best) {
+            link.is_preferred = true;
+        }
+    }
+}
+
+/// Where "open in my provider" goes for an album, if it has any of them.
+pub fn preferred_link(
+    media_url: &HashMap<String, serde_json::Value>,
+    preferred: &[&str],
+) -> Option<String> {
+    let mut links = links(media_url);
+    rank_links(&mut links, preferred);
+    links
+        .into_iter()
+        .find(|l| l.is_preferred && l.is_default)
+        .map(|l| l.url)
+}
+
+/// The provider registry, for the profile and album editor.
+#[utoipa::path(get, path = "/providers", responses((status = 200, body = ProvidersEnvelope)))]
+pub async fn list_providers() -> impl IntoResponse {
+    Json(serde_json::json!({
+        "code": 200,
+        "msg": "success",
+        "data": { "providers": PROVIDERS }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn build(key: &str, id: &str, entry: serde_json::Value) -> Result<String, String> {
+        let entry = serde_json::from_value::<MediaEntry>(entry).unwrap();
+        (provider(key).unwrap().build)(id, &entry)
+    }
+
+    fn media_url(value: serde_json::Value) -> HashMap<String, serde_json::Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn spotify_links_by_type() {
+        let id = "7dxKtc08dYeRVHt3p9CZJn";
+        assert_eq!(
+            build("spotify", id, json!({})).unwrap(),
+            "https://open.spotify.com/album/7dxKtc08dYeRVHt3p9CZJn"
+        );
+        assert_eq!(
+            build("spotify", id, json!({"type": "playlist"})).unwrap(),
+            "https://open.spotify.com/playlist/7dxKtc08dYeRVHt3p9CZJn"
+        );
+        assert!(build("spotify", id, json!({"type": "artist"})).is_err());
+        assert!(build("spotify", "7dxKtc08dYeRVHt3p9CZJ", json!({})).is_err());
+        assert!(build("spotify", "7dxKtc08dYeRVHt3p9CZJ/", json!({})).is_err());
+    }
+
+    #[test]
+    fn netease_ids_are_numeric() {
+        assert_eq!(
+            build("netease", "3084216", json!({})).unwrap(),
+            "https://music.163.com/#/album?id=3084216"
+        );
+        assert!(build("netease", "30842a6", json!({})).is_err());
+        assert!(build("netease", "", json!({})).is_err());
+    }
+
+    #[test]
+    fn qq_music_ids_are_alphanumeric() {
+        assert_eq!(
+            build("qqmusic", "002KGk6V1n8XE7", json!({})).unwrap(),
+            "https://y.qq.com/n/ryqq/albumDetail/002KGk6V1n8XE7"
+        );
+        assert!(build("qqmusic", "002KGk6V?x=1", json!({})).is_err());
+    }
+
+    #[test]
+    fn apple_music_needs_a_slug_and_defaults_the_storefront() {
+        assert_eq!(
+            build("applemusic", "68290017", json!({"album": "terrifyer"})).unwrap(),
+            "https://geo.music.apple.com/gb/album/terrifyer/68290017"
+        );
+        assert_eq!(
+            build("applemusic", "68290017", json!({"album": "terrifyer", "loc": "US"})).unwrap(),
+            "https://geo.music.apple.com/us/album/terrifyer/68290017"
+        );
+        assert!(build("applemusic", "68290017", json!({})).is_err());
+        assert!(build("applemusic", "68290017", json!({"album": "a/b"})).is_err());
+        assert!(build("applemusic", "68290017", json!({"album": "terrifyer", "loc": "usa"})).is_err());
+        assert!(build("applemusic", "terrifyer", json!({"album": "terrifyer"})).is_err());
+    }
+
+    #[test]
+    fn bandcamp_needs_a_page_url() {
+        assert_eq!(
+            build("bandcamp", "1", json!({"url": "artist.bandcamp.com/album/x"})).unwrap(),
+            "https://artist.bandcamp.com/album/x"
+        );
+        assert_eq!(
+            build("bandcamp", "1", json!({"url": "http://artist.bandcamp.com/album/x"})).unwrap(),
+            "https://artist.bandcamp.com/album/x"
+        );
+        assert!(build("bandcamp", "1", json!({})).is_err());
+        assert!(build("bandcamp", "1", json!({"url": "localhost/album"})).is_err());
+        assert!(build("bandcamp", "1", json!({"url": "artist.bandcamp.com/x y"})).is_err());
+        assert!(build("bandcamp", "1", json!({"url": "bandcamp.com/album/x"})).is_err());
+        assert!(build("bandcamp", "1", json!({"url": "evil.com/artist.bandcamp.com"})).is_err());
+        assert!(build("bandcamp", "1", json!({"url": "a.b.bandcamp.com/album/x"})).is_err());
+        assert!(build("bandcamp", "1", json!({"url": "evil.com@artist.bandcamp.com/x"})).is_err());
+        assert!(build("bandcamp", "1", json!({"url": "artist.bandcamp.com.evil.com/x"})).is_err());
+    }
+
+    #[test]
+    fn soundcloud_needs_a_soundcloud_page() {
+        assert_eq!(
+            build("soundcloud", "1", json!({"url": "soundcloud.com/artist/sets/x"})).unwrap(),
+            "https://soundcloud.com/artist/sets/x"
+        );
+        assert!(build("soundcloud", "1", json!({"url": "on.soundcloud.com/abc"})).is_ok());
+        assert!(build("soundcloud", "1", json!({"url": "artist.soundcloud.com/x"})).is_err());
+        assert!(build("soundcloud", "1", json!({"url": "soundcloud.com:8080/x"})).is_err());
+    }
+
+    #[test]
+    fn tidal_ids_are_numeric() {
+        assert_eq!(
+            build("tidal", "77646012", json!({})).unwrap(),
+            "https://tidal.com/browse/album/77646012"
+        );
+        assert!(build("tidal", "7764601a", json!({})).is_err());
+    }
+
+    #[test]
+    fn deezer_ids_are_numeric() {
+        assert_eq!(
+            build("deezer", "302127", json!({})).unwrap(),
+            "https://www.deezer.com/album/302127"
+        );
+        assert!(build("deezer", "302127/tracks", json!({})).is_err());
+    }
+
+    #[test]
+    fn youtube_music_links_albums_and_their_playlists() {
+        assert_eq!(
+            build("youtubemusic", "MPREb_4pL8gzRtw1p", json!({})).unwrap(),
+            "https://music.youtube.com/browse/MPREb_4pL8gzRtw1p"
+        );
+        assert_eq!(
+            build("youtubemusic", "OLAK5uy_nMr9h2VlS-2PULA", json!({})).unwrap(),
+            "https://music.youtube.com/playlist?list=OLAK5uy_nMr9h2VlS-2PULA"
+        );
+        assert!(build("youtubemusic", "PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG", json!({})).is_err());
+        assert!(build("youtubemusic", "MPREb", json!({})).is_err());
+        assert!(build("youtubemusic", "MPREb_4pL8&x=1", json!({})).is_err());
+    }
+
+    #[test]
+    fn youtube_links_videos_and_playlists() {
+        assert_eq!(
+            build("youtube", "dQw4w9WgXcQ", json!({})).unwrap(),
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+        );
+        assert_eq!(
+            build("youtube", "PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG", json!({"type": "playlist"})).unwrap(),
+            "https://www.youtube.com/playlist?list=PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG"
+        );
+        assert!(build("youtube", "PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG", json!({})).is_err());
+        assert!(build("youtube", "dQw4w9WgXc&", json!({})).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_malformed_entries_of_known_providers() {
+        assert!(validate(&media_url(json!({
+            "spotify": {"7dxKtc08dYeRVHt3p9CZJn": {"default": true}},
+            "netease": {"3084216": {}},
+            "someday": {"anything": "goes"},
+        })))
+        .is_ok());
+        assert!(validate(&media_url(json!({"spotify": ["7dxKtc08dYeRVHt3p9CZJn"]}))).is_err());
+        assert!(validate(&media_url(json!({"spotify": {"7dxKtc08dYeRVHt3p9CZJn": {"default": "yes"}}}))).is_err());
+        assert!(validate(&media_url(json!({"netease": {"abc": {}}}))).is_err());
+        assert!(validate(&media_url(json!({"someday": "not an object"}))).is_err());
+    }
+
+    #[test]
+    fn links_skip_bad_entries_and_keep_one_default_per_provider() {
+        let links = links(&media_url(json!({
+            "netease": {"1": {}, "2": {"default": true}, "x": {}},
+            "spotify": {"7dxKtc08dYeRVHt3p9CZJn": {}},
+        })));
+        let summary: Vec<(&str, &str, bool)> = links
+            .iter()
+            .map(|l| (l.provider, l.url.as_str(), l.is_default))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("spotify", "https://open.spotify.com/album/7dxKtc08dYeRVHt3p9CZJn", true),
+                ("netease", "https://music.163.com/#/album?id=2", true),
+                ("netease", "https://music.163.com/#/album?id=1", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn rank_links_puts_the_best_preferred_provider_first() {
+        let mut ranked = links(&media_url(json!({
+            "spotify": {"7dxKtc08dYeRVHt3p9CZJn": {}},
+            "netease": {"1": {}, "2": {}},
+            "qqmusic": {"002KGk6V1n8XE7": {}},
+        })));
+        rank_links(&mut ranked, &preferred("deezer, netease,qqmusic,netease"));
+        let order: Vec<(&str, bool)> = ranked.iter().map(|l| (l.provider, l.is_preferred)).collect();
+        assert_eq!(
+            order,
+            [
+                ("netease", true),
+                ("netease", true),
+                ("qqmusic", false),
+                ("spotify", false),
+            ]
+        );
+
+        let mut unranked = links(&media_url(json!({"spotify": {"7dxKtc08dYeRVHt3p9CZJn": {}}})));
+        rank_links(&mut unranked, &[]);
+        assert!(!unranked[0].is_preferred);
+    }
+}
@@ -0,0 +1,258 @@
+//! OpenAPI document for the `/api/v1` routes. Paths come from the
+//! `#[utoipa::path]` attributes on the handlers; the schemas here describe
+//! the `{code, msg, data}` envelope the handlers build with `json!`. Admin
+//! routes are left out.
+use axum::{
+    response::{Html, IntoResponse},
+    Json,
+};
+use serde::Serialize;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    Modify, OpenApi, ToSchema,
+};
+
+use crate::{
+    account, device, history_export, listen_import, media, AlbumChart, AlbumDetail, AlbumGenre, Genre,
+    UserAlbumLog, __path_genres, __path_get_album_detail, __path_get_artist_album,
+    __path_get_genre_album, __path_get_today_album, __path_get_user_album_log, __path_login,
+    __path_logout, __path_register, __path_user_config, __path_user_info, Album, CreateUser, Login,
+    User, UserConfig,
+};
+
+/// Every JSON response except `/today` and `/album/{album_id}`.
+#[derive(Serialize, ToSchema)]
+#[aliases(
+    EmptyEnvelope = Envelope<Empty>,
+    UserEnvelope = Envelope<User>,
+    GenresEnvelope = Envelope<GenreList>,
+    AlbumLogEnvelope = Envelope<AlbumLogPage>,
+    AlbumChartEnvelope = Envelope<AlbumChartPage>,
+    ProvidersEnvelope = Envelope<ProviderList>,
+    DevicesEnvelope = Envelope<DeviceList>,
+    ImportEnvelope = Envelope<ImportReport>
+)]
+pub struct Envelope<T> {
+    /// 200 on success, otherwise the HTTP status
+    code: u16,
+    msg: String,
+    /// Missing on errors
+    data: Option<T>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Empty {}
+
+/// A 400 for input that breaks the rules in `common::validation`.
+#[derive(Serialize, ToSchema)]
+pub struct Invalid {
+    code: u16,
+    /// Every message, joined
+    msg: String,
+    /// Messages by field name
+    errors: std::collections::BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[aliases(AlbumLogPage = Page<UserAlbumLog>, AlbumChartPage = Page<AlbumChart>)]
+pub struct Page<T> {
+    res: Vec<T>,
+    page: usize,
+    page_size: usize,
+    /// Null unless counted, see the `total` parameter
+    total: Option<i32>,
+    /// Pass as `cursor` for the next page; null on the last one
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GenreList {
+    genres: Vec<Genre>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ProviderList {
+    providers: Vec<media::Provider>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceList {
+    devices: Vec<device::Device>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportReport {
+    total_plays: usize,
+    matched_plays: i32,
+    /// Matched plays added to the history by this upload
+    imported_plays: i32,
+    /// Matched plays an earlier upload already added
+    already_imported_plays: i32,
+    unmatched_plays: i32,
+    /// Plays that couldn't be saved; uploading again retries them
+    failed_plays: i32,
+    matched_count: usize,
+    unmatched_count: usize,
+    failed_count: usize,
+    matched: Vec<listen_import::MatchedEntry>,
+    unmatched: Vec<listen_import::UnmatchedEntry>,
+    failed: Vec<listen_import::UnmatchedEntry>,
+}
+
+/// `/album/{album_id}` answers with the album itself, not an envelope.
+#[derive(Serialize, ToSchema)]
+pub struct AlbumPage {
+    #[serde(flatten)]
+    detail: AlbumDetail,
+    genres: Vec<AlbumGenre>,
+    /// Ranked by the user's preferred providers
+    links: Vec<media::MediaLink>,
+}
+
+/// The session cookie, and the CSRF header POSTs need.
+struct SessionCookie;
+
+impl Modify for SessionCookie {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("axum.sid"))),
+        );
+        components.add_security_scheme(
+            "csrf",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-CSRF-Token",
+                "Every POST must echo the `csrf_token` cookie any API response sets",
+            ))),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Random Your Music API"),
+    servers((url = "/api/v1")),
+    paths(
+        register,
+        login,
+        logout,
+        user_config,
+        user_info,
+        account::verify_email,
+        account::resend_verification,
+        account::forgot_password,
+        account::reset_password,
+        account::change_password,
+        account::change_email,
+        account::delete_account,
+        get_today_album,
+        get_album_detail,
+        crate::cover::cover,
+        get_artist_album,
+        genres,
+        media::list_providers,
+        get_genre_album,
+        get_user_album_log,
+        listen_import::import_history,
+        history_export::export_history,
+        device::list_devices,
+        device::label_device,
+        device::logout_device,
+    ),
+    components(schemas(
+        CreateUser,
+        Login,
+        UserConfig,
+        User,
+        account::TokenPayload,
+        account::ForgotPassword,
+        account::ResetPassword,
+        account::ChangePassword,
+        account::ChangeEmail,
+        account::DeleteAccount,
+        Album,
+        AlbumDetail,
+        AlbumGenre,
+        AlbumPage,
+        AlbumChart,
+        UserAlbumLog,
+        Genre,
+        GenreList,
+        Empty,
+        Invalid,
+        EmptyEnvelope,
+        UserEnvelope,
+        GenresEnvelope,
+        AlbumLogPage,
+        AlbumLogEnvelope,
+        AlbumChartPage,
+        AlbumChartEnvelope,
+        media::Provider,
+        media::MediaLink,
+        ProviderList,
+        ProvidersEnvelope,
+        device::Device,
+        device::DeviceLabel,
+        DeviceList,
+        DevicesEnvelope,
+        listen_import::MatchedEntry,
+        listen_import::UnmatchedEntry,
+        ImportReport,
+        ImportEnvelope,
+    )),
+    modifiers(&SessionCookie)
+)]
+pub struct ApiDoc;
+
+pub async fn openapi_json() -> impl IntoResponse {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI comes from our own static files, which the Dockerfile fills
+/// from a pinned `swagger-ui-dist` into `frontend/public/swagger-ui`; no
+/// third party script runs next to the session cookie.
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8" />
+    <title>Random Your Music API</title>
+    <link rel="stylesheet" href="/public/swagger-ui/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="docs"></div>
+    <script src="/public/swagger-ui/swagger-ui-bundle.js"></script>
+    <script>
+      SwaggerUIBundle({ url: "/api/v1/openapi.json", dom_id: "#docs" });
+    </script>
+  </body>
+</html>
+"##;
+
+pub async fn docs() -> impl IntoResponse {
+    Html(DOCS_PAGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_mutating_path_needs_the_csrf_header() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut mutating = 0;
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                if method == "get" {
+                    continue;
+                }
+                mutating += 1;
+                let csrf = operation["security"]
+                    .as_array()
+                    .is_some_and(|s| s.iter().all(|r| r.get("csrf").is_some()));
+                assert!(csrf, "{method} {path} doesn't ask for the csrf header");
+            }
+        }
+        assert!(mutating > 0);
+    }
+}
@@ -0,0 +1,158 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Either a page number, or the `next_cursor` of the previous page for
+/// keyset paging that doesn't slow down deep into a list.
+#[derive(Deserialize, IntoParams)]
+#[serde(default)]
+pub struct Pagination {
+    /// Starts at 1, ignored when `cursor` is set
+    pub page: usize,
+    /// 1 to 100
+    pub page_size: usize,
+    /// `next_cursor` from the previous page
+    pub cursor: Option<String>,
+    /// Count all matching rows; defaults to on for page numbers and off
+    /// for cursors
+    pub total: Option<bool>,
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            page_size: 40,
+            cursor: None,
+            total: None,
+        }
+    }
+}
+
+impl Pagination {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.page == 0 {
+            return Err("page starts at 1".to_string());
+        }
+        if !(1..=MAX_PAGE_SIZE).contains(&self.page_size) {
+            return Err(format!("page_size must be between 1 and {MAX_PAGE_SIZE}"));
+        }
+        Ok(())
+    }
+
+    pub fn offset(&self) -> usize {
+        self.page_size * self.page.saturating_sub(1)
+    }
+
+    pub fn with_total(&self) -> bool {
+        self.total.unwrap_or(self.cursor.is_none())
+    }
+
+    /// The position the page starts after, `None` in page mode.
+    pub fn after(&self) -> Result<Option<Cursor>, String> {
+        self.cursor.as_deref().map(Cursor::decode).transpose()
+    }
+}
+
+/// Sort key and id of the last row of a page. Clients get it base64 encoded
+/// and shouldn't look inside.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub key: String,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn new(key: impl Into<String>, id: i32) -> Self {
+        Cursor {
+            key: key.into(),
+            id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> Result<Cursor, String> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| "invalid cursor".to_string())
+    }
+}
+
+/// Cursor for the page after `rows`, when it was a full one.
+pub fn next_cursor<T>(
+    rows: &[T],
+    page_size: usize,
+    cursor: impl Fn(&T) -> Cursor,
+) -> Option<String> {
+    if rows.len() < page_size {
+        return None;
+    }
+    rows.last().map(|row| cursor(row).encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pagination(page: usize, page_size: usize) -> Pagination {
+        Pagination {
+            page,
+            page_size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_bounds_page_and_page_size() {
+        assert!(Pagination::default().validate().is_ok());
+        assert!(pagination(0, 40).validate().is_err());
+        assert!(pagination(1, 0).validate().is_err());
+        assert!(pagination(1, 1).validate().is_ok());
+        assert!(pagination(1, MAX_PAGE_SIZE).validate().is_ok());
+        assert!(pagination(1, MAX_PAGE_SIZE + 1).validate().is_err());
+        assert_eq!(pagination(3, 40).offset(), 80);
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor::new("2023-01-31 12:34:56", 42);
+        let encoded = cursor.encode();
+        assert!(encoded
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(Cursor::decode(&encoded), Ok(cursor));
+
+        let paging = Pagination {
+            cursor: Some(encoded),
+            ..Default::default()
+        };
+        assert_eq!(paging.after(), Ok(Some(Cursor::new("2023-01-31 12:34:56", 42))));
+        assert!(!paging.with_total());
+        assert_eq!(Pagination::default().after(), Ok(None));
+        assert!(Pagination::default().with_total());
+    }
+
+    #[test]
+    fn garbage_cursors_are_rejected() {
+        for garbage in ["", "not base64!", "bm90IGpzb24", "eyJrZXkiOjF9"] {
+            assert_eq!(Cursor::decode(garbage), Err("invalid cursor".to_string()), "{garbage}");
+        }
+    }
+
+    #[test]
+    fn next_cursor_only_follows_full_pages() {
+        let rows = [(1, "a"), (2, "b"), (3, "c")];
+        let cursor = |row: &(i32, &str)| Cursor::new(row.1, row.0);
+        assert_eq!(next_cursor(&rows, 4, cursor), None);
+        assert_eq!(next_cursor(&rows[..0], 0, cursor), None);
+        let next = next_cursor(&rows, 3, cursor).unwrap();
+        assert_eq!(Cursor::decode(&next), Ok(Cursor::new("c", 3)));
+    }
+}
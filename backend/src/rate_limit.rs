@@ -0,0 +1,308 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{settings::RateLimitSettings, MyShared};
+
+/// Fixed window counters and lockouts, in Redis so every instance shares
+/// them, or in memory while Redis is unreachable.
+pub struct RateLimiter {
+    redis: redis::Client,
+    memory: Mutex<HashMap<String, (u64, Instant)>>,
+    settings: RateLimitSettings,
+}
+
+/// Where a request stands against one limit.
+pub struct Decision {
+    limit: u64,
+    remaining: u64,
+    /// Seconds until the window resets or the lockout ends
+    reset: u64,
+    allowed: bool,
+}
+
+impl Decision {
+    fn unlimited() -> Decision {
+        Decision {
+            limit: 0,
+            remaining: 0,
+            reset: 0,
+            allowed: true,
+        }
+    }
+
+    fn locked(seconds: u64) -> Decision {
+        Decision {
+            limit: 0,
+            remaining: 0,
+            reset: seconds,
+            allowed: false,
+        }
+    }
+
+    pub fn allowed(&self) -> bool {
+        self.allowed
+    }
+
+    /// The `RateLimit-*` headers, plus `Retry-After` when over the limit.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if self.limit > 0 {
+            headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+            headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+            headers.insert("ratelimit-reset", HeaderValue::from(self.reset));
+        }
+        if !self.allowed {
+            headers.insert("retry-after", HeaderValue::from(self.reset.max(1)));
+        }
+    }
+
+    pub fn respond(&self, resp: impl IntoResponse) -> Response {
+        let mut resp = resp.into_response();
+        self.apply(resp.headers_mut());
+        resp
+    }
+
+    pub fn reject(&self) -> Response {
+        let resp = serde_json::json!({
+            "code": 429,
+            "msg": format!("too many attempts, try again in {} seconds", self.reset.max(1))
+        });
+        self.respond((StatusCode::TOO_MANY_REQUESTS, Json(resp)))
+    }
+}
+
+impl RateLimiter {
+    pub fn new(redis: redis::Client, settings: RateLimitSettings) -> Self {
+        RateLimiter {
+            redis,
+            memory: Mutex::new(HashMap::new()),
+            settings,
+        }
+    }
+
+    /// Count a hit in `key`'s window. Returns the count and the seconds left
+    /// in the window.
+    async fn incr(&self, key: &str, window: u64) -> (u64, u64) {
+        match self.redis_incr(key, window).await {
+            Ok(res) => res,
+            Err(e) => {
+                tracing::warn!("rate limit falling back to memory: {e}");
+                let mut memory = self.memory.lock().unwrap();
+                let now = Instant::now();
+                memory.retain(|_, (_, expires)| *expires > now);
+                let entry = memory
+                    .entry(key.to_string())
+                    .or_insert((0, now + Duration::from_secs(window)));
+                entry.0 += 1;
+                (entry.0, entry.1.saturating_duration_since(now).as_secs())
+            }
+        }
+    }
+
+    async fn redis_incr(&self, key: &str, window: u64) -> redis::RedisResult<(u64, u64)> {
+        let mut con = self.redis.get_async_connection().await?;
+        let count: u64 = con.incr(key, 1).await?;
+        let mut ttl: i64 = con.ttl(key).await?;
+        if ttl < 0 {
+            let _: () = con.expire(key, window as usize).await?;
+            ttl = window as i64;
+        }
+        Ok((count, ttl as u64))
+    }
+
+    /// Seconds left on `key`, if it is set.
+    async fn ttl(&self, key: &str) -> Option<u64> {
+        let res: redis::RedisResult<i64> = async {
+            let mut con = self.redis.get_async_connection().await?;
+            con.ttl(key).await
+        }
+        .await;
+        match res {
+            Ok(ttl) if ttl >= 0 => Some(ttl as u64),
+            Ok(_) => None,
+            Err(_) => {
+                let memory = self.memory.lock().unwrap();
+                let (_, expires) = memory.get(key)?;
+                expires
+                    .checked_duration_since(Instant::now())
+                    .map(|left| left.as_secs())
+            }
+        }
+    }
+
+    async fn set(&self, key: &str, seconds: u64) {
+        let res: redis::RedisResult<()> = async {
+            let mut con = self.redis.get_async_connection().await?;
+            con.set_ex(key, 1, seconds as usize).await
+        }
+        .await;
+        if res.is_err() {
+            let expires = Instant::now() + Duration::from_secs(seconds);
+            self.memory.lock().unwrap().insert(key.to_string(), (1, expires));
+        }
+    }
+
+    async fn del(&self, keys: &[String]) {
+        let res: redis::RedisResult<()> = async {
+            let mut con = self.redis.get_async_connection().await?;
+            con.del(keys).await
+        }
+        .await;
+        if res.is_err() {
+            let mut memory = self.memory.lock().unwrap();
+            for key in keys {
+                memory.remove(key);
+            }
+        }
+    }
+
+    async fn hit(&self, key: &str, limit: u64, window: u64) -> Decision {
+        if !self.settings.enabled || limit == 0 {
+            return Decision::unlimited();
+        }
+        let (count, reset) = self.incr(key, window).await;
+        Decision {
+            limit,
+            remaining: limit.saturating_sub(count),
+            reset,
+            allowed: count <= limit,
+        }
+    }
+
+    /// Check a login attempt before the password is: the account lockout,
+    /// then the per address and per username windows. The tightest one
+    /// decides the headers.
+    pub async fn login(&self, ip: IpAddr, username: &str) -> Decision {
+        if !self.settings.enabled {
+            return Decision::unlimited();
+        }
+        let username = username.trim().to_lowercase();
+        if let Some(left) = self.ttl(&format!("rl:login_lock:{username}")).await {
+            return Decision::locked(left);
+        }
+        let s = &self.settings;
+        let by_ip = self
+            .hit(&format!("rl:login_ip:{ip}"), s.login_per_ip, s.login_window_secs)
+            .await;
+        let by_user = self
+            .hit(&format!("rl:login_user:{username}"), s.login_per_username, s.login_window_secs)
+            .await;
+        [by_ip, by_user]
+            .into_iter()
+            .min_by_key(|d| (d.allowed, d.limit == 0, d.remaining))
+            .unwrap_or_else(Decision::unlimited)
+    }
+
+    /// Count a wrong password. From `lockout_after` failures on, each one
+    /// locks the account for twice as long as the last, up to
+    /// `lockout_max_secs`.
+    pub async fn login_failed(&self, username: &str) {
+        let s = &self.settings;
+        if !s.enabled || s.lockout_after == 0 {
+            return;
+        }
+        let username = username.trim().to_lowercase();
+        let (failures, _) = self
+            .incr(&format!("rl:login_fail:{username}"), s.lockout_max_secs)
+            .await;
+        if failures >= s.lockout_after {
+            let doublings = (failures - s.lockout_after).min(32) as u32;
+            let seconds = s
+                .lockout_base_secs
+                .saturating_mul(1 << doublings)
+                .min(s.lockout_max_secs);
+            self.set(&format!("rl:login_lock:{username}"), seconds).await;
+        }
+    }
+
+    pub async fn login_succeeded(&self, username: &str) {
+        let username = username.trim().to_lowercase();
+        self.del(&[
+            format!("rl:login_fail:{username}"),
+            format!("rl:login_lock:{username}"),
+        ])
+        .await;
+    }
+
+    pub async fn register(&self, ip: IpAddr) -> Decision {
+        let s = &self.settings;
+        self.hit(&format!("rl:register_ip:{ip}"), s.register_per_ip, s.register_window_secs)
+            .await
+    }
+
+    /// A request that sends mail: a password reset or another verification.
+    pub async fn mail(&self, ip: IpAddr) -> Decision {
+        let s = &self.settings;
+        self.hit(&format!("rl:mail_ip:{ip}"), s.mail_per_ip, s.mail_window_secs)
+            .await
+    }
+}
+
+/// The client's address: the `X-Forwarded-For` hop `trusted_proxies` from
+/// the right when `trust_forwarded` is on, the peer address otherwise.
+pub struct ClientIp(pub IpAddr);
+
+/// The hop the nearest of `proxies` trusted proxies saw connect. Hops left
+/// of it come from the client and can say anything.
+fn forwarded_client(header: &str, proxies: usize) -> Option<IpAddr> {
+    let hops: Vec<&str> = header.split(',').map(str::trim).collect();
+    let hop = hops.len().checked_sub(proxies.max(1))?;
+    hops[hop].parse().ok()
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let proxies = parts
+            .extensions
+            .get::<MyShared>()
+            .map(|state| &state.limiter.settings)
+            .filter(|settings| settings.trust_forwarded)
+            .map(|settings| settings.trusted_proxies);
+        let forwarded = parts
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .zip(proxies)
+            .and_then(|(header, proxies)| forwarded_client(header, proxies));
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Ok(ClientIp(
+            forwarded
+                .or(peer)
+                .unwrap_or(IpAddr::from([0, 0, 0, 0])),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_client_ignores_spoofed_hops() {
+        // the client sent its own X-Forwarded-For, the proxy appended the
+        // address it really connected from
+        let header = "1.2.3.4, 203.0.113.7";
+        assert_eq!(forwarded_client(header, 1), "203.0.113.7".parse().ok());
+        // two proxies: the outer one's hop is the client
+        let header = "1.2.3.4, 203.0.113.7, 10.0.0.2";
+        assert_eq!(forwarded_client(header, 2), "203.0.113.7".parse().ok());
+        // fewer hops than proxies, or garbage, falls back to the peer
+        assert_eq!(forwarded_client("203.0.113.7", 2), None);
+        assert_eq!(forwarded_client("1.2.3.4, not-an-ip", 1), None);
+    }
+}
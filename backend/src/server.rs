@@ -0,0 +1,124 @@
+//! Serving the app on TCP or a Unix socket, and shutting down cleanly.
+use axum::Router;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::settings::ServerSettings;
+
+/// Resolves on SIGTERM or Ctrl-C.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("can't listen for ctrl-c: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("can't listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Serve `app` until SIGTERM, then stop accepting connections and wait up
+/// to `shutdown_timeout_secs` for the requests in flight. `stopping` is
+/// notified as the shutdown starts, for the background tasks.
+pub async fn run(settings: &ServerSettings, app: Router, stopping: Arc<Notify>) -> std::io::Result<()> {
+    let stopped = stopping.notified();
+    let signal = {
+        let stopping = stopping.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("shutting down, draining requests in flight");
+            stopping.notify_waiters();
+        }
+    };
+    let deadline = async {
+        stopped.await;
+        tokio::time::sleep(Duration::from_secs(settings.shutdown_timeout_secs)).await;
+    };
+
+    let server = async {
+        if settings.unix_socket.is_empty() {
+            // checked by Settings::new
+            let addr: SocketAddr = settings.bind.parse().expect("invalid server.bind");
+            tracing::info!("listening on {addr}");
+            axum::Server::try_bind(&addr)
+                .map_err(std::io::Error::other)?
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(signal)
+                .await
+                .map_err(std::io::Error::other)
+        } else {
+            serve_unix(&settings.unix_socket, app, signal).await
+        }
+    };
+
+    tokio::select! {
+        res = server => res,
+        _ = deadline => {
+            tracing::warn!(
+                "requests still in flight after {}s, exiting anyway",
+                settings.shutdown_timeout_secs
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    path: &str,
+    app: Router,
+    signal: impl std::future::Future<Output = ()>,
+) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::net::UnixListener;
+    use tokio_stream::wrappers::UnixListenerStream;
+
+    // a socket left behind by a previous run would fail the bind
+    if let Ok(meta) = std::fs::metadata(path) {
+        if meta.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)?;
+    tracing::info!("listening on unix:{path}");
+    let res = axum::Server::builder(hyper::server::accept::from_stream(
+        UnixListenerStream::new(listener),
+    ))
+    .serve(app.into_make_service())
+    .with_graceful_shutdown(signal)
+    .await
+    .map_err(std::io::Error::other);
+    let _ = std::fs::remove_file(path);
+    res
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(
+    _path: &str,
+    _app: Router,
+    _signal: impl std::future::Future<Output = ()>,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "unix sockets need a unix platform",
+    ))
+}
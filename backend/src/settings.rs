@@ -1,15 +1,311 @@
 use config::{Config, ConfigError, Environment, File};
 use serde_derive::Deserialize;
 use std::env;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize)]
 #[allow(unused)]
 pub struct Settings {
     pub db_url : String,
     pub redis_url: String,
     pub debug: bool,
     pub secret: String,
+    #[serde(default = "default_db_max_connections")]
+    pub db_max_connections: u32,
+    #[serde(default)]
+    pub server: ServerSettings,
+    #[serde(default)]
+    pub link_check: LinkCheckSettings,
+    #[serde(default)]
+    pub cover: CoverSettings,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub cors: CorsSettings,
+    #[serde(default)]
+    pub mail: MailSettings,
+    #[serde(default)]
+    pub log: LogSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    #[serde(default)]
+    pub startup: StartupSettings,
+    #[serde(default)]
+    pub jobs: JobSettings,
+}
+
+/// `[jobs]`: background jobs `serve` runs. Schedules are cron expressions
+/// with seconds, `sec min hour day month weekday`, in UTC; an empty one
+/// turns that job off.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobSettings {
+    pub enabled: bool,
+    /// Build `/today` pages for recently active browsers
+    pub warm_feed: String,
+    /// Add listening since the last run to `user_album_daily`
+    pub rollup_listens: String,
+    /// Forget devices that haven't been seen in `device_max_idle_days`
+    pub prune_devices: String,
+    /// Re-check album covers and media links, see `[link_check]`
+    pub check_links: String,
+    pub warm_days: u32,
+    pub warm_pages: usize,
+    pub device_max_idle_days: u32,
+    /// A job's lock expires after this, should its replica die mid-run; a
+    /// live run renews it every third of this.
+    /// Each scheduled slot is claimed for as long, so replicas whose clocks
+    /// disagree by less don't run it twice
+    pub lock_ttl_secs: u64,
+}
+
+impl Default for JobSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            warm_feed: "0 */15 * * * *".to_string(),
+            rollup_listens: "0 5 * * * *".to_string(),
+            prune_devices: "0 30 3 * * *".to_string(),
+            check_links: "0 0 4 * * *".to_string(),
+            warm_days: 7,
+            warm_pages: 1,
+            device_max_idle_days: 90,
+            lock_ttl_secs: 3600,
+        }
+    }
+}
+
+fn default_db_max_connections() -> u32 {
+    5
+}
+
+/// `[server]`: how `serve` listens and what it serves.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub bind: String,
+    /// Listen on this Unix socket instead of `bind`. Client addresses then
+    /// only come from `X-Forwarded-For`, see `rate_limit.trust_forwarded`
+    pub unix_socket: String,
+    /// The built frontend
+    pub static_dir: String,
+    /// Send the session cookie over HTTPS only
+    pub secure_cookies: bool,
+    /// How long a SIGTERM waits for in-flight requests before exiting
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:5001".to_string(),
+            unix_socket: String::new(),
+            static_dir: "../dist".to_string(),
+            secure_cookies: false,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+/// `[startup]`: waiting for MySQL and Redis when the process starts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StartupSettings {
+    /// Attempts after the first before giving up and exiting
+    pub connect_retries: u32,
+    /// Wait before the first retry, doubled for each further one
+    pub connect_backoff_ms: u64,
+    pub connect_backoff_max_ms: u64,
+}
+
+impl Default for StartupSettings {
+    fn default() -> Self {
+        Self {
+            connect_retries: 10,
+            connect_backoff_ms: 500,
+            connect_backoff_max_ms: 10_000,
+        }
+    }
+}
+
+/// `[log]`: what `serve` and the commands log, and how.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    /// `text`, or `json` for one JSON object per line
+    pub format: String,
+    /// Filter directives such as `info` or `backend=debug,sqlx=warn`;
+    /// `RUST_LOG` overrides it
+    pub level: String,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            format: "text".to_string(),
+            level: "info".to_string(),
+        }
+    }
+}
+
+/// `[metrics]`: the Prometheus `/metrics` endpoint.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+    /// When set, scrapes must send `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            token: String::new(),
+        }
+    }
+}
+
+/// `[mail]`: verification and password reset mail.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct MailSettings {
+    /// `smtp`, or `outbox` to write mail to `outbox_dir` instead of sending it
+    pub transport: String,
+    pub from: String,
+    pub outbox_dir: String,
+    pub smtp_host: String,
+    /// 465 connects over TLS, any other port upgrades with STARTTLS
+    pub smtp_port: u16,
+    /// Leave empty for a relay that needs no login
+    pub smtp_username: String,
+    pub smtp_password: String,
+    /// Where the frontend is served, for the links in mail
+    pub public_url: String,
+    pub verify_token_hours: u64,
+    pub reset_token_minutes: u64,
+}
+
+impl Default for MailSettings {
+    fn default() -> Self {
+        Self {
+            transport: "outbox".to_string(),
+            from: "Random Your Music <noreply@localhost>".to_string(),
+            outbox_dir: "outbox".to_string(),
+            smtp_host: "localhost".to_string(),
+            smtp_port: 587,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            public_url: "http://localhost:5001".to_string(),
+            verify_token_hours: 48,
+            reset_token_minutes: 60,
+        }
+    }
+}
+
+/// `[cors]`: other sites allowed to call the API with the user's cookies.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CorsSettings {
+    /// Full origins, e.g. `https://app.example.com`; the API's own origin
+    /// is always allowed. They read the CSRF token from the `X-CSRF-Token`
+    /// response header
+    pub allowed_origins: Vec<String>,
+}
+
+/// `[link_check]`: how the dead link checker probes, for the `check_links`
+/// job and `check-links`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LinkCheckSettings {
+    /// Requests in flight at once
+    pub concurrency: usize,
+    /// Extra attempts for links that time out or answer 429/5xx
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each further one
+    pub backoff_ms: u64,
+    pub timeout_secs: u64,
+}
+
+/// `[cover]`: the `/api/v1/cover` image proxy.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CoverSettings {
+    pub cache_dir: String,
+    /// Least recently served thumbnails are dropped beyond this
+    pub cache_max_mb: u64,
+    pub timeout_secs: u64,
+}
+
+/// `[rate_limit]`: login and registration throttling. A limit of 0 turns
+/// that check off.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    /// Take the client address from `X-Forwarded-For`; only behind a proxy
+    /// that sets it
+    pub trust_forwarded: bool,
+    /// Proxies in front of the backend, each appending a hop to
+    /// `X-Forwarded-For`; the client is that many hops from the right
+    pub trusted_proxies: usize,
+    pub login_per_ip: u64,
+    pub login_per_username: u64,
+    pub login_window_secs: u64,
+    /// Wrong passwords in a row before the account is locked
+    pub lockout_after: u64,
+    /// First lockout, doubled for each further failure
+    pub lockout_base_secs: u64,
+    pub lockout_max_secs: u64,
+    pub register_per_ip: u64,
+    pub register_window_secs: u64,
+    /// Verification and reset mails requested per address
+    pub mail_per_ip: u64,
+    pub mail_window_secs: u64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            trust_forwarded: false,
+            trusted_proxies: 1,
+            login_per_ip: 20,
+            login_per_username: 10,
+            login_window_secs: 300,
+            lockout_after: 5,
+            lockout_base_secs: 30,
+            lockout_max_secs: 3600,
+            register_per_ip: 5,
+            register_window_secs: 3600,
+            mail_per_ip: 5,
+            mail_window_secs: 3600,
+        }
+    }
+}
+
+impl Default for CoverSettings {
+    fn default() -> Self {
+        Self {
+            cache_dir: "cover_cache".to_string(),
+            cache_max_mb: 512,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl Default for LinkCheckSettings {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            retries: 2,
+            backoff_ms: 1000,
+            timeout_secs: 10,
+        }
+    }
 }
 
 impl Settings {
@@ -31,18 +327,182 @@ impl Settings {
             .add_source(File::with_name("config/local").required(false))
             // Add in settings from the environment (with a prefix of APP)
             // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
-            .add_source(Environment::with_prefix("APP"))
+            .add_source(environment())
             // You may also programmatically change settings
             // .set_override("database.url", "postgres://")?
             .build()?;
 
-        // Now that we're done, let's access our configuration
-        println!("debug: {:?}", s.get_bool("debug"));
-        println!("database: {:?}", s.get::<String>("db_url"));
-        println!("database: {:?}", s.get::<String>("redis_url"));
-        println!("database: {:?}", s.get::<String>("secret"));
-
         // You can deserialize (and thus freeze) the entire configuration as
-        s.try_deserialize()
+        let settings: Settings = s.try_deserialize()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Everything wrong with the settings at once, rather than the first
+    /// thing to fail at runtime.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if self.db_url.is_empty() {
+            problems.push("db_url is empty".to_string());
+        }
+        if self.redis_url.is_empty() {
+            problems.push("redis_url is empty".to_string());
+        }
+        // the session layer refuses shorter keys
+        if self.secret.len() < 64 {
+            problems.push("secret must be at least 64 bytes".to_string());
+        }
+        if self.db_max_connections == 0 {
+            problems.push("db_max_connections must be at least 1".to_string());
+        }
+        if self.server.unix_socket.is_empty() && self.server.bind.parse::<SocketAddr>().is_err() {
+            problems.push(format!(
+                "server.bind {:?} is not an address like 0.0.0.0:5001",
+                self.server.bind
+            ));
+        }
+        // SameSite=None cookies, which those origins need, must be Secure
+        if !self.cors.allowed_origins.is_empty() && !self.server.secure_cookies {
+            problems.push("cors.allowed_origins needs server.secure_cookies".to_string());
+        }
+        if self.server.static_dir.is_empty() {
+            problems.push("server.static_dir is empty".to_string());
+        }
+        if !["text", "json"].contains(&self.log.format.as_str()) {
+            problems.push(format!("log.format {:?} is not text or json", self.log.format));
+        }
+        if !["smtp", "outbox"].contains(&self.mail.transport.as_str()) {
+            problems.push(format!(
+                "mail.transport {:?} is not smtp or outbox",
+                self.mail.transport
+            ));
+        }
+        for (job, schedule) in [
+            ("warm_feed", &self.jobs.warm_feed),
+            ("rollup_listens", &self.jobs.rollup_listens),
+            ("prune_devices", &self.jobs.prune_devices),
+            ("check_links", &self.jobs.check_links),
+        ] {
+            if let Err(e) = parse_schedule(schedule) {
+                problems.push(format!("jobs.{job} {schedule:?}: {e}"));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Message(problems.join("; ")))
+        }
+    }
+}
+
+/// `APP_` variables, with `__` between a section and its key:
+/// `APP_DB_URL` sets `db_url` and `APP_SERVER__BIND` sets `server.bind`.
+fn environment() -> Environment {
+    Environment::with_prefix("APP")
+        .prefix_separator("_")
+        .separator("__")
+}
+
+/// A job schedule, `None` when it's empty and the job is off.
+pub fn parse_schedule(schedule: &str) -> Result<Option<cron::Schedule>, cron::error::Error> {
+    if schedule.trim().is_empty() {
+        return Ok(None);
+    }
+    cron::Schedule::from_str(schedule).map(Some)
+}
+
+/// `url` with any password replaced, for logs.
+pub fn redact_url(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    // userinfo ends at the last @ before the path
+    let authority = rest.split('/').next().unwrap_or(rest);
+    match authority.rfind('@') {
+        Some(at) => {
+            let user = authority[..at].split(':').next().unwrap_or_default();
+            format!("{scheme}://{user}:***{}", &rest[at..])
+        }
+        None => url.to_string(),
+    }
+}
+
+fn redact(secret: &str) -> &'static str {
+    if secret.is_empty() {
+        ""
+    } else {
+        "***"
+    }
+}
+
+impl fmt::Debug for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Settings")
+            .field("db_url", &redact_url(&self.db_url))
+            .field("redis_url", &redact_url(&self.redis_url))
+            .field("debug", &self.debug)
+            .field("secret", &redact(&self.secret))
+            .field("db_max_connections", &self.db_max_connections)
+            .field("server", &self.server)
+            .field("link_check", &self.link_check)
+            .field("cover", &self.cover)
+            .field("rate_limit", &self.rate_limit)
+            .field("cors", &self.cors)
+            .field("mail", &self.mail)
+            .field("log", &self.log)
+            .field("metrics", &self.metrics)
+            .field("startup", &self.startup)
+            .field("jobs", &self.jobs)
+            .finish()
+    }
+}
+
+impl fmt::Debug for MailSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MailSettings")
+            .field("transport", &self.transport)
+            .field("from", &self.from)
+            .field("outbox_dir", &self.outbox_dir)
+            .field("smtp_host", &self.smtp_host)
+            .field("smtp_port", &self.smtp_port)
+            .field("smtp_username", &self.smtp_username)
+            .field("smtp_password", &redact(&self.smtp_password))
+            .field("public_url", &self.public_url)
+            .field("verify_token_hours", &self.verify_token_hours)
+            .field("reset_token_minutes", &self.reset_token_minutes)
+            .finish()
+    }
+}
+
+impl fmt::Debug for MetricsSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsSettings")
+            .field("enabled", &self.enabled)
+            .field("token", &redact(&self.token))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_sets_top_level_and_nested_keys() {
+        let vars = [
+            ("APP_DB_URL", "mysql://db/rym"),
+            ("APP_SERVER__BIND", "127.0.0.1:8080"),
+            ("APP_JOBS__CHECK_LINKS", ""),
+            ("OTHER_DB_URL", "ignored"),
+        ];
+        let env = environment().source(Some(
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        ));
+        let config = Config::builder().add_source(env).build().unwrap();
+        assert_eq!(config.get_string("db_url").unwrap(), "mysql://db/rym");
+        assert_eq!(config.get_string("server.bind").unwrap(), "127.0.0.1:8080");
+        assert_eq!(config.get_string("jobs.check_links").unwrap(), "");
     }
 }
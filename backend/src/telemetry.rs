@@ -0,0 +1,216 @@
+//! Logging setup, request spans and the Prometheus `/metrics` endpoint.
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use rand::RngCore;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    settings::{LogSettings, MetricsSettings},
+    MyShared,
+};
+
+pub const REQUEST_ID: &str = "x-request-id";
+
+/// Install the global subscriber `[log]` describes.
+pub fn init_logging(settings: &LogSettings) {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&settings.level))
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if settings.format == "json" {
+        builder.json().with_current_span(true).init();
+    } else {
+        builder.init();
+    }
+}
+
+pub struct Metrics {
+    settings: MetricsSettings,
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    db_connections: IntGaugeVec,
+    feed_cache: IntCounterVec,
+    feed_generation: Histogram,
+}
+
+impl Metrics {
+    pub fn new(settings: &MetricsSettings, db_max_connections: u32) -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "API requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let latency = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "API request latency"),
+            &["method", "route"],
+        )
+        .unwrap();
+        let db_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "MySQL pool connections by state"),
+            &["state"],
+        )
+        .unwrap();
+        let max = IntGauge::new("db_pool_max_connections", "MySQL pool size limit").unwrap();
+        max.set(db_max_connections.into());
+        let feed_cache = IntCounterVec::new(
+            Opts::new("feed_cache_requests_total", "/today pages served from Redis or not"),
+            &["result"],
+        )
+        .unwrap();
+        let feed_generation = Histogram::with_opts(
+            HistogramOpts::new("feed_generation_seconds", "Time to build a /today page on a cache miss")
+                .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(db_connections.clone())).unwrap();
+        registry.register(Box::new(max.clone())).unwrap();
+        registry.register(Box::new(feed_cache.clone())).unwrap();
+        registry.register(Box::new(feed_generation.clone())).unwrap();
+        Metrics {
+            settings: settings.clone(),
+            registry,
+            requests,
+            latency,
+            db_connections,
+            feed_cache,
+            feed_generation,
+        }
+    }
+
+    fn observe(&self, method: &str, route: &str, status: StatusCode, latency: Duration) {
+        self.requests
+            .with_label_values(&[method, route, status.as_str()])
+            .inc();
+        self.latency
+            .with_label_values(&[method, route])
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Count a `/today` page lookup in Redis.
+    pub fn feed_cache(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.feed_cache.with_label_values(&[result]).inc();
+    }
+
+    pub fn feed_generated(&self, took: Duration) {
+        self.feed_generation.observe(took.as_secs_f64());
+    }
+}
+
+/// A caller supplied request id if it looks like one, a new one otherwise.
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 64
+                && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            let mut bytes = [0u8; 8];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            bytes.iter().map(|b| format!("{b:02x}")).collect()
+        })
+}
+
+/// Run each API request in a span carrying its id, log its status and
+/// latency, and count it by route. The id goes back in `X-Request-Id`.
+pub async fn track(
+    Extension(state): Extension<MyShared>,
+    matched: Option<MatchedPath>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let start = Instant::now();
+    let id = request_id(req.headers());
+    // the route pattern, not the path, to keep the label set bounded
+    let route = matched
+        .map(|m| m.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let span = tracing::info_span!(
+        "request",
+        id = %id,
+        method = %method,
+        route = %route,
+        status = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+    );
+    let mut resp = next.run(req).instrument(span.clone()).await;
+    let latency = start.elapsed();
+    let status = resp.status();
+    span.record("status", status.as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    span.in_scope(|| {
+        if status.is_server_error() {
+            tracing::error!("request failed");
+        } else {
+            tracing::info!("request done");
+        }
+    });
+    state.metrics.observe(&method, &route, status, latency);
+    if let Ok(id) = HeaderValue::from_str(&id) {
+        resp.headers_mut().insert(REQUEST_ID, id);
+    }
+    resp
+}
+
+/// `GET /metrics` in the Prometheus text format.
+pub async fn metrics(Extension(state): Extension<MyShared>, headers: HeaderMap) -> Response {
+    let metrics = &state.metrics;
+    let settings = &metrics.settings;
+    if !settings.enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if !settings.token.is_empty() {
+        let expected = format!("Bearer {}", settings.token);
+        let given = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if given != expected {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    let idle = state.db.num_idle() as i64;
+    metrics
+        .db_connections
+        .with_label_values(&["idle"])
+        .set(idle);
+    metrics
+        .db_connections
+        .with_label_values(&["in_use"])
+        .set(i64::from(state.db.size()) - idle);
+
+    let mut body = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut body) {
+        tracing::error!("metrics encode error: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4"))],
+        body,
+    )
+        .into_response()
+}
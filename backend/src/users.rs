@@ -0,0 +1,130 @@
+//! `create-user`, `promote` and `reset-password`, the account chores that
+//! used to be done by hand in SQL.
+use clap::Args;
+use sqlx::mysql::MySqlPool;
+use std::io::{BufRead, IsTerminal, Write};
+use validator::ValidationError;
+
+use crate::{
+    account,
+    admin::{ROLE_ADMIN, ROLE_USER},
+    generate_password,
+    rate_limit::RateLimiter,
+};
+
+#[derive(Args)]
+pub struct CreateUserArgs {
+    pub username: String,
+    pub email: String,
+    /// Make the account an admin
+    #[arg(long)]
+    pub admin: bool,
+}
+
+#[derive(Args)]
+pub struct PromoteArgs {
+    pub username: String,
+    /// `admin`, or `user` to take admin rights away
+    #[arg(long, default_value = ROLE_ADMIN)]
+    pub role: String,
+}
+
+#[derive(Args)]
+pub struct ResetPasswordArgs {
+    pub username: String,
+}
+
+fn check(rule: Result<(), ValidationError>) -> Result<(), String> {
+    rule.map_err(|e| e.message.unwrap_or_default().to_string())
+}
+
+/// One line of stdin, so passwords stay out of the shell history and the
+/// process list. Prompts when stdin is a terminal.
+fn read_password() -> Result<String, String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("new password: ");
+        let _ = std::io::stderr().flush();
+    }
+    let mut line = String::new();
+    stdin
+        .lock()
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    check(common::validation::password(&password))?;
+    Ok(password)
+}
+
+async fn user_id(db: &MySqlPool, username: &str) -> Result<i32, String> {
+    sqlx::query_scalar("SELECT id FROM rym_user WHERE username = ?")
+        .bind(username)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("no user named {username}"))
+}
+
+/// A verified account with the password read from stdin.
+pub async fn create(db: &MySqlPool, args: CreateUserArgs) -> Result<i32, String> {
+    check(common::validation::username(&args.username))?;
+    check(common::validation::email(&args.email))?;
+    let taken: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM rym_user WHERE username = ? OR email = ?")
+        .bind(&args.username)
+        .bind(&args.email)
+        .fetch_one(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    if taken > 0 {
+        return Err("username or email already registered".to_string());
+    }
+    let password = generate_password(&read_password()?).await;
+    let role = if args.admin { ROLE_ADMIN } else { ROLE_USER };
+    let res = sqlx::query(
+        "INSERT INTO rym_user (username, email, password, fresh_time, role, email_verified)
+        VALUES (?, ?, ?, 10, ?, 1)",
+    )
+    .bind(&args.username)
+    .bind(&args.email)
+    .bind(password)
+    .bind(role)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(res.last_insert_id() as i32)
+}
+
+pub async fn promote(db: &MySqlPool, args: PromoteArgs) -> Result<(), String> {
+    if ![ROLE_ADMIN, ROLE_USER].contains(&args.role.as_str()) {
+        return Err(format!("role must be {ROLE_ADMIN} or {ROLE_USER}"));
+    }
+    let user_id = user_id(db, &args.username).await?;
+    sqlx::query("UPDATE rym_user SET role = ? WHERE id = ?")
+        .bind(&args.role)
+        .bind(user_id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Set the password read from stdin, lift any login lockout and sign the
+/// user out everywhere, as the emailed reset link does.
+pub async fn reset_password(
+    db: &MySqlPool,
+    redis: &redis::Client,
+    limiter: &RateLimiter,
+    args: ResetPasswordArgs,
+) -> Result<(), String> {
+    let user_id = user_id(db, &args.username).await?;
+    let password = generate_password(&read_password()?).await;
+    sqlx::query("UPDATE rym_user SET password = ? WHERE id = ?")
+        .bind(password)
+        .bind(user_id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    account::sign_out_devices(db, redis, user_id, "").await;
+    limiter.login_succeeded(&args.username).await;
+    Ok(())
+}
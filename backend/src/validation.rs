@@ -0,0 +1,40 @@
+use axum::{http::StatusCode, Json};
+use std::collections::BTreeMap;
+use validator::ValidationErrors;
+
+/// Field name to messages, as returned in the `errors` of a 400.
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+pub fn field_errors(errors: &ValidationErrors) -> FieldErrors {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let messages = errors
+                .iter()
+                .map(|e| match &e.message {
+                    Some(message) => message.to_string(),
+                    None => format!("{field} is invalid"),
+                })
+                .collect();
+            (field.to_string(), messages)
+        })
+        .collect()
+}
+
+/// A 400 carrying every field's messages; `msg` joins them for clients that
+/// only show one line.
+pub fn invalid(errors: FieldErrors) -> (StatusCode, Json<serde_json::Value>) {
+    let msg = errors
+        .values()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join("; ");
+    let resp = serde_json::json!({
+        "code": 400,
+        "msg": msg,
+        "errors": errors
+    });
+    (StatusCode::BAD_REQUEST, Json(resp))
+}
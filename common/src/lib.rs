@@ -0,0 +1,2 @@
+//! Code shared by the backend and the frontend.
+pub mod validation;
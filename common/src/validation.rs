@@ -0,0 +1,199 @@
+//! Rules for user input. The frontend runs them as the user types through
+//! `#[validate(custom = "...")]`, the backend enforces them the same way, so
+//! both report the same messages.
+use std::borrow::Cow;
+use validator::ValidationError;
+
+pub const USERNAME_MIN: usize = 3;
+pub const USERNAME_MAX: usize = 32;
+pub const EMAIL_MAX: usize = 128;
+pub const PASSWORD_MIN: usize = 8;
+pub const PASSWORD_MAX: usize = 128;
+pub const FRESH_TIME_MIN: i32 = 1;
+pub const FRESH_TIME_MAX: i32 = 3600;
+pub const GENRES_MAX: usize = 64;
+/// `user_device.client_id` is a VARCHAR(64)
+pub const CLIENT_ID_MAX: usize = 64;
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Borrowed(message));
+    error
+}
+
+/// 3 to 32 letters, digits, `_`, `-` or `.`.
+pub fn username(value: &str) -> Result<(), ValidationError> {
+    let len = value.chars().count();
+    if len == 0 {
+        return Err(error("required", "Username is required"));
+    }
+    if !(USERNAME_MIN..=USERNAME_MAX).contains(&len) {
+        return Err(error("length", "Username must be 3 to 32 characters"));
+    }
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(error(
+            "charset",
+            "Username may only contain letters, digits, _, - and .",
+        ));
+    }
+    Ok(())
+}
+
+pub fn email(value: &str) -> Result<(), ValidationError> {
+    if value.is_empty() {
+        return Err(error("required", "Email is required"));
+    }
+    if value.len() > EMAIL_MAX || !validator::validate_email(value) {
+        return Err(error("email", "Email is invalid"));
+    }
+    Ok(())
+}
+
+/// At least 8 characters, with a letter and a digit.
+pub fn password(value: &str) -> Result<(), ValidationError> {
+    let len = value.chars().count();
+    if len == 0 {
+        return Err(error("required", "Password is required"));
+    }
+    if len < PASSWORD_MIN {
+        return Err(error("length", "Password must be at least 8 characters"));
+    }
+    if len > PASSWORD_MAX {
+        return Err(error("length", "Password must be at most 128 characters"));
+    }
+    if !value.chars().any(char::is_alphabetic) || !value.chars().any(|c| c.is_ascii_digit()) {
+        return Err(error("strength", "Password must contain a letter and a digit"));
+    }
+    Ok(())
+}
+
+/// Minutes a feed page stays cached, as typed into the profile form.
+pub fn fresh_time(value: &str) -> Result<(), ValidationError> {
+    match value.trim().parse::<i32>() {
+        Ok(minutes) if (FRESH_TIME_MIN..=FRESH_TIME_MAX).contains(&minutes) => Ok(()),
+        _ => Err(error("range", "Fresh time must be a whole number from 1 to 3600 minutes")),
+    }
+}
+
+/// Comma-separated genre paths, each `key_name` segments joined by `/`.
+/// Empty for any genre.
+pub fn genres(value: &str) -> Result<(), ValidationError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    let paths: Vec<&str> = value.split(',').collect();
+    if paths.len() > GENRES_MAX {
+        return Err(error("length", "Pick at most 64 genres"));
+    }
+    let valid = paths.iter().all(|path| {
+        path.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+        })
+    });
+    if !valid {
+        return Err(error("charset", "Genres must be genre paths separated by commas"));
+    }
+    Ok(())
+}
+
+/// The random id a browser keeps in local storage, a UUID from our
+/// frontend: letters, digits and `-`, at most 64 of them.
+pub fn client_id(value: &str) -> Result<(), ValidationError> {
+    if value.is_empty() {
+        return Err(error("required", "Client id is required"));
+    }
+    if value.len() > CLIENT_ID_MAX
+        || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(error("charset", "Client id is invalid"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(res: Result<(), ValidationError>) -> Option<&'static str> {
+        res.err().map(|e| match e.code {
+            Cow::Borrowed(code) => code,
+            Cow::Owned(_) => unreachable!(),
+        })
+    }
+
+    #[test]
+    fn username_bounds_and_charset() {
+        assert_eq!(code(username("")), Some("required"));
+        assert_eq!(code(username("ab")), Some("length"));
+        assert_eq!(code(username("abc")), None);
+        assert_eq!(code(username(&"a".repeat(USERNAME_MAX))), None);
+        assert_eq!(code(username(&"a".repeat(USERNAME_MAX + 1))), Some("length"));
+        assert_eq!(code(username("j.doe_2-x")), None);
+        assert_eq!(code(username("john doe")), Some("charset"));
+        assert_eq!(code(username("jöhn")), Some("charset"));
+        assert_eq!(code(username("john@x")), Some("charset"));
+    }
+
+    #[test]
+    fn email_bounds_and_format() {
+        assert_eq!(code(email("")), Some("required"));
+        assert_eq!(code(email("a@example.com")), None);
+        assert_eq!(code(email("not an email")), Some("email"));
+        let local = "a".repeat(64);
+        let at_max = format!("{local}@{}.com", "b".repeat(EMAIL_MAX - local.len() - 5));
+        assert_eq!(at_max.len(), EMAIL_MAX);
+        assert_eq!(code(email(&at_max)), None);
+        let over = format!("{local}@{}.com", "b".repeat(EMAIL_MAX - local.len() - 4));
+        assert_eq!(code(email(&over)), Some("email"));
+    }
+
+    #[test]
+    fn password_bounds_and_strength() {
+        assert_eq!(code(password("")), Some("required"));
+        assert_eq!(code(password("abcdef1")), Some("length"));
+        assert_eq!(code(password("abcdefg1")), None);
+        assert_eq!(code(password(&format!("{}1", "a".repeat(PASSWORD_MAX - 1)))), None);
+        assert_eq!(code(password(&format!("{}1", "a".repeat(PASSWORD_MAX)))), Some("length"));
+        assert_eq!(code(password("abcdefgh")), Some("strength"));
+        assert_eq!(code(password("12345678")), Some("strength"));
+        // counted in characters, not bytes
+        assert_eq!(code(password("äöüäöü1")), Some("length"));
+    }
+
+    #[test]
+    fn fresh_time_range() {
+        assert_eq!(code(fresh_time("0")), Some("range"));
+        assert_eq!(code(fresh_time("1")), None);
+        assert_eq!(code(fresh_time(" 3600 ")), None);
+        assert_eq!(code(fresh_time("3601")), Some("range"));
+        assert_eq!(code(fresh_time("1.5")), Some("range"));
+    }
+
+    #[test]
+    fn genres_are_comma_separated_paths() {
+        assert_eq!(code(genres("")), None);
+        assert_eq!(code(genres("rock,rock/shoegaze,hip-hop/boom_bap")), None);
+        assert_eq!(code(genres(&vec!["rock"; GENRES_MAX].join(","))), None);
+        assert_eq!(code(genres(&vec!["rock"; GENRES_MAX + 1].join(","))), Some("length"));
+        assert_eq!(code(genres("rock,")), Some("charset"));
+        assert_eq!(code(genres("rock//shoegaze")), Some("charset"));
+        assert_eq!(code(genres("rock, pop")), Some("charset"));
+        assert_eq!(code(genres("rock/shoe%")), Some("charset"));
+    }
+
+    #[test]
+    fn client_id_bounds_and_charset() {
+        assert_eq!(code(client_id("")), Some("required"));
+        assert_eq!(code(client_id("3f2b8c1e-5d4a-4e7b-9c6d-0a1b2c3d4e5f")), None);
+        assert_eq!(code(client_id(&"a".repeat(CLIENT_ID_MAX))), None);
+        assert_eq!(code(client_id(&"a".repeat(CLIENT_ID_MAX + 1))), Some("charset"));
+        assert_eq!(code(client_id("abc*")), Some("charset"));
+        assert_eq!(code(client_id("abc_pages")), Some("charset"));
+    }
+}
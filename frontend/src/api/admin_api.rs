@@ -0,0 +1,42 @@
+use super::types::{AuditEntry, GenreNode, JsonResponse};
+use super::user_api::make_request;
+use serde::de::DeserializeOwned;
+
+static ADMIN_URL: &str = "/api/v1/admin";
+
+fn data_field<T: DeserializeOwned>(response: &str, field: &str) -> Result<T, String> {
+    match serde_json::from_str::<JsonResponse>(response) {
+        Ok(data) => {
+            let serialized = serde_json::to_string(&data.data.get(field)).unwrap();
+            serde_json::from_str::<T>(&serialized)
+                .map_err(|_| "Failed to parse response".to_string())
+        }
+        Err(_) => Err("Failed to parse response".to_string()),
+    }
+}
+
+/// POST `json` to an admin endpoint below `/api/v1/admin`
+pub async fn admin_post_api(path: &str, json: &str) -> Result<JsonResponse, String> {
+    let url = format!("{ADMIN_URL}{path}");
+    match make_request(&url, "POST", Some(json)).await {
+        Ok(response) => serde_json::from_str::<JsonResponse>(&response)
+            .map_err(|_| "Failed to parse response".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn admin_genres_api() -> Result<Vec<GenreNode>, String> {
+    let url = format!("{ADMIN_URL}/genres");
+    match make_request(&url, "GET", None).await {
+        Ok(response) => data_field::<Vec<GenreNode>>(&response, "genres"),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn admin_audit_api(page: u32, page_size: u32) -> Result<Vec<AuditEntry>, String> {
+    let url = format!("{ADMIN_URL}/audit?page={page}&page_size={page_size}");
+    match make_request(&url, "GET", None).await {
+        Ok(response) => data_field::<Vec<AuditEntry>>(&response, "res"),
+        Err(e) => Err(e),
+    }
+}
@@ -1,2 +1,3 @@
+pub mod admin_api;
 pub mod types;
 pub mod user_api;
@@ -10,6 +10,15 @@ pub struct User {
     pub username: String,
     pub genre_data: Option<String>,
     pub fresh_time: i32,
+    #[serde(default)]
+    pub role: String,
+    /// Comma separated provider keys, best first
+    #[serde(default)]
+    pub preferred_providers: String,
+    #[serde(default)]
+    pub open_direct: bool,
+    #[serde(default)]
+    pub email_verified: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -41,6 +50,13 @@ pub struct Album {
     pub id: i32,
     // pub name: String,
     pub cover: String,
+    #[serde(default)]
+    pub link: Option<String>,
+    /// Dominant cover colour, `#rrggbb`
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub blurhash: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -61,6 +77,27 @@ pub struct AlbumDetail {
     pub language: String,
     pub rate: String,
     pub genres: Vec<AlbumGenre>,
+    #[serde(default)]
+    pub links: Vec<MediaLinkData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
+pub struct MediaLinkData {
+    pub provider: String,
+    pub title: String,
+    pub icon: String,
+    pub url: String,
+    pub is_default: bool,
+    #[serde(default)]
+    pub is_preferred: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MediaProvider {
+    pub key: String,
+    pub title: String,
+    pub icon: String,
+    pub fields: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -82,9 +119,32 @@ pub struct AlbumLog {
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct AlbumLogData {
     pub res: Vec<AlbumLog>,
-    pub total: u32,
+    #[serde(default)]
+    pub total: Option<u32>,
     pub page: u32,
     pub page_size: u32,
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ImportedPlay {
+    pub artist: String,
+    pub title: String,
+    pub plays: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct HistoryImportReport {
+    pub total_plays: u32,
+    pub matched_plays: u32,
+    pub imported_plays: u32,
+    pub already_imported_plays: u32,
+    pub unmatched_plays: u32,
+    pub failed_plays: u32,
+    pub matched_count: u32,
+    pub unmatched_count: u32,
+    pub unmatched: Vec<ImportedPlay>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -94,12 +154,47 @@ pub struct AlbumChart {
     pub artist: String,
     pub cover: String,
     pub rate: String,
+    #[serde(default)]
+    pub color: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct ChartData {
     pub res: Vec<AlbumChart>,
-    pub total: u32,
+    #[serde(default)]
+    pub total: Option<u32>,
     pub page: u32,
     pub page_size: u32,
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Device {
+    pub client_id: String,
+    pub label: String,
+    pub user_agent: String,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub current: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GenreNode {
+    pub id: i32,
+    pub name: String,
+    pub key_name: String,
+    pub parents: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub user_id: i32,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: i32,
+    pub detail: Option<serde_json::Value>,
+    pub create_time: String,
 }
@@ -1,13 +1,36 @@
 use super::types::{
-    Album, AlbumDetail, AlbumLogData, ErrorResponse, Genre, ChartData, JsonResponse, User,
+    Album, AlbumDetail, AlbumLogData, Device, ErrorResponse, Genre, ChartData, HistoryImportReport,
+    JsonResponse, MediaProvider, User,
 };
 #[allow(unused)]
 use crate::{app::log, console_log};
 use gloo_net::http::{Request, RequestCredentials};
+use wasm_bindgen::JsCast;
+use web_sys::HtmlDocument;
 use serde::de::Deserialize;
 
 static BASE_URL: &str = "/api/v1";
 
+/// Thumbnail of an album cover served through our cover proxy
+pub fn cover_url(album_id: impl std::fmt::Display, size: u32) -> String {
+    format!("{BASE_URL}/cover/{album_id}?size={size}")
+}
+
+/// The `csrf_token` cookie the API hands out, echoed back on every request.
+fn csrf_token() -> String {
+    let cookies = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.dyn_into::<HtmlDocument>().ok())
+        .and_then(|d| d.cookie().ok())
+        .unwrap_or_default();
+    cookies
+        .split(';')
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == "csrf_token")
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_default()
+}
+
 pub async fn make_request(url: &str, method: &str, data: Option<&str>) -> Result<String, String> {
     let req = match method {
         "GET" => Request::get(url)
@@ -21,6 +44,7 @@ pub async fn make_request(url: &str, method: &str, data: Option<&str>) -> Result
             .credentials(RequestCredentials::Include)
             .body(data),
     };
+    let req = req.header("X-CSRF-Token", &csrf_token());
 
     let response = match req.send().await {
         Ok(res) => res,
@@ -67,7 +91,7 @@ pub async fn login_api(credentials: &str) -> Result<JsonResponse, String> {
 
 pub async fn logout_api() -> Result<JsonResponse, String> {
     let url = format!("{BASE_URL}/logout");
-    match make_request(&url, "GET", None).await {
+    match make_request(&url, "POST", Some("{}")).await {
         Ok(response) => {
             let res = convert_result::<JsonResponse>(&response);
             match res {
@@ -93,6 +117,104 @@ pub async fn register_api(credentials: &str) -> Result<JsonResponse, String> {
     }
 }
 
+pub async fn verify_email_api(data: &str) -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/verify_email");
+    match make_request(&url, "POST", Some(data)).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn resend_verification_api() -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/verify_email/resend");
+    match make_request(&url, "POST", Some("{}")).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn forgot_password_api(data: &str) -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/password/forgot");
+    match make_request(&url, "POST", Some(data)).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn reset_password_api(data: &str) -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/password/reset");
+    match make_request(&url, "POST", Some(data)).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn change_password_api(data: &str) -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/account/password");
+    match make_request(&url, "POST", Some(data)).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn change_email_api(data: &str) -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/account/email");
+    match make_request(&url, "POST", Some(data)).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn delete_account_api(data: &str) -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/account/delete");
+    match make_request(&url, "POST", Some(data)).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
 pub async fn today_album_api(
     client_id: &str,
     page: i32,
@@ -167,6 +289,26 @@ pub async fn genres_api() -> Result<Vec<Genre>, String> {
     }
 }
 
+pub async fn providers_api() -> Result<Vec<MediaProvider>, String> {
+    let url = format!("{BASE_URL}/providers");
+    match make_request(&url, "GET", None).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => {
+                    let serialized = serde_json::to_string(&data.data.get("providers")).unwrap();
+                    match serde_json::from_str::<Vec<MediaProvider>>(&serialized) {
+                        Ok(data) => Ok(data),
+                        Err(_) => Err("Failed to parse response".to_string()),
+                    }
+                }
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
 pub async fn user_config_api(json: &str) -> Result<JsonResponse, String> {
     let url = format!("{BASE_URL}/user_config");
     match make_request(&url, "POST", Some(json)).await {
@@ -181,10 +323,12 @@ pub async fn user_config_api(json: &str) -> Result<JsonResponse, String> {
     }
 }
 
-pub async fn album_log_api(page: u32, page_size: u32) -> Result<AlbumLogData, String> {
-    let url = format!(
-        "{BASE_URL}/user_album_log?page_size={page_size}&page={page}"
-    );
+/// A page of history, after `cursor` when given.
+pub async fn album_log_api(cursor: Option<&str>, page_size: u32) -> Result<AlbumLogData, String> {
+    let url = match cursor {
+        Some(cursor) => format!("{BASE_URL}/user_album_log?page_size={page_size}&cursor={cursor}"),
+        None => format!("{BASE_URL}/user_album_log?page_size={page_size}&total=false"),
+    };
     match make_request(&url, "GET", None).await {
         Ok(response) => {
             let res = convert_result::<JsonResponse>(&response);
@@ -203,6 +347,26 @@ pub async fn album_log_api(page: u32, page_size: u32) -> Result<AlbumLogData, St
     }
 }
 
+pub async fn history_import_api(format: &str, content: &str) -> Result<(String, HistoryImportReport), String> {
+    let url = format!("{BASE_URL}/user_album_log/import?format={format}");
+    match make_request(&url, "POST", Some(content)).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => {
+                    let serialized = serde_json::to_string(&data.data).unwrap();
+                    match serde_json::from_str::<HistoryImportReport>(&serialized) {
+                        Ok(report) => Ok((data.msg, report)),
+                        Err(_) => Err("Failed to parse response".to_string()),
+                    }
+                }
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
 pub async fn genre_album_api(genre: &str, page: u32, page_size: u32) -> Result<ChartData, String> {
     let url = format!(
         "{BASE_URL}/genre/{genre}?page_size={page_size}&page={page}"
@@ -247,3 +411,51 @@ pub async fn artist_album_api(artist: &str, page: u32, page_size: u32) -> Result
         Err(e) => Err(e),
     }
 }
+
+pub async fn devices_api() -> Result<Vec<Device>, String> {
+    let url = format!("{BASE_URL}/devices");
+    match make_request(&url, "GET", None).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => {
+                    let serialized = serde_json::to_string(&data.data.get("devices")).unwrap();
+                    match serde_json::from_str::<Vec<Device>>(&serialized) {
+                        Ok(devices) => Ok(devices),
+                        Err(_) => Err("Failed to parse response".to_string()),
+                    }
+                }
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn device_label_api(client_id: &str, json: &str) -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/devices/{client_id}");
+    match make_request(&url, "POST", Some(json)).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn device_logout_api(client_id: &str) -> Result<JsonResponse, String> {
+    let url = format!("{BASE_URL}/devices/{client_id}/logout");
+    match make_request(&url, "POST", Some("{}")).await {
+        Ok(response) => {
+            let res = convert_result::<JsonResponse>(&response);
+            match res {
+                Ok(data) => Ok(data),
+                Err(_) => Err("Failed to parse response".to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,177 @@
+use crate::api::user_api::{change_email_api, change_password_api, delete_account_api};
+use crate::router::Route;
+use crate::store::{set_auth_user, set_show_alert, Store};
+use common::validation;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yew_router::prelude::*;
+use yewdux::prelude::*;
+
+fn value(input: &NodeRef) -> String {
+    input
+        .cast::<HtmlInputElement>()
+        .map(|i| i.value())
+        .unwrap_or_default()
+}
+
+fn clear(inputs: &[&NodeRef]) {
+    for input in inputs {
+        if let Some(input) = input.cast::<HtmlInputElement>() {
+            input.set_value("");
+        }
+    }
+}
+
+/// Password, email and account deletion sections of the profile page
+#[function_component(AccountSettings)]
+pub fn account_settings() -> Html {
+    let (store, dispatch) = use_store::<Store>();
+    let navigator = use_navigator().unwrap();
+
+    let current_ref = use_node_ref();
+    let password_ref = use_node_ref();
+    let password_confirm_ref = use_node_ref();
+    let email_password_ref = use_node_ref();
+    let email_ref = use_node_ref();
+    let delete_password_ref = use_node_ref();
+
+    let on_change_password = {
+        let dispatch = dispatch.clone();
+        let (current_ref, password_ref, password_confirm_ref) = (
+            current_ref.clone(),
+            password_ref.clone(),
+            password_confirm_ref.clone(),
+        );
+        Callback::from(move |_: MouseEvent| {
+            let password = value(&password_ref);
+            let check = validation::password(&password)
+                .map_err(|e| e.message.unwrap_or_default().to_string())
+                .and_then(|_| {
+                    if password == value(&password_confirm_ref) {
+                        Ok(())
+                    } else {
+                        Err("Passwords do not match".to_string())
+                    }
+                });
+            if let Err(e) = check {
+                set_show_alert(e, dispatch.clone());
+                return;
+            }
+            let json = serde_json::json!({
+                "current_password": value(&current_ref),
+                "password": password,
+                "password_confirm": value(&password_confirm_ref),
+            })
+            .to_string();
+            let dispatch = dispatch.clone();
+            let inputs = (
+                current_ref.clone(),
+                password_ref.clone(),
+                password_confirm_ref.clone(),
+            );
+            spawn_local(async move {
+                match change_password_api(&json).await {
+                    Ok(data) => {
+                        clear(&[&inputs.0, &inputs.1, &inputs.2]);
+                        set_show_alert(data.msg, dispatch);
+                    }
+                    Err(e) => set_show_alert(e, dispatch),
+                }
+            });
+        })
+    };
+
+    let on_change_email = {
+        let dispatch = dispatch.clone();
+        let user = store.auth_user.clone();
+        let (email_password_ref, email_ref) = (email_password_ref.clone(), email_ref.clone());
+        Callback::from(move |_: MouseEvent| {
+            let email = value(&email_ref);
+            if let Err(e) = validation::email(&email) {
+                set_show_alert(e.message.unwrap_or_default().to_string(), dispatch.clone());
+                return;
+            }
+            let json = serde_json::json!({
+                "current_password": value(&email_password_ref),
+                "email": email,
+            })
+            .to_string();
+            let dispatch = dispatch.clone();
+            let user = user.clone();
+            let inputs = (email_password_ref.clone(), email_ref.clone());
+            spawn_local(async move {
+                match change_email_api(&json).await {
+                    Ok(data) => {
+                        if let Some(mut user) = user {
+                            user.email = email;
+                            user.email_verified = false;
+                            set_auth_user(Some(user), dispatch.clone());
+                        }
+                        clear(&[&inputs.0, &inputs.1]);
+                        set_show_alert(data.msg, dispatch);
+                    }
+                    Err(e) => set_show_alert(e, dispatch),
+                }
+            });
+        })
+    };
+
+    let on_delete = {
+        let delete_password_ref = delete_password_ref.clone();
+        Callback::from(move |_: MouseEvent| {
+            let confirmed = web_sys::window()
+                .and_then(|w| {
+                    w.confirm_with_message(
+                        "Delete your account and listening history? This can't be undone.",
+                    )
+                    .ok()
+                })
+                .unwrap_or(false);
+            if !confirmed {
+                return;
+            }
+            let json = serde_json::json!({
+                "current_password": value(&delete_password_ref),
+            })
+            .to_string();
+            let dispatch = dispatch.clone();
+            let navigator = navigator.clone();
+            spawn_local(async move {
+                match delete_account_api(&json).await {
+                    Ok(data) => {
+                        set_auth_user(None, dispatch.clone());
+                        set_show_alert(data.msg, dispatch);
+                        navigator.push(&Route::Home);
+                    }
+                    Err(e) => set_show_alert(e, dispatch),
+                }
+            });
+        })
+    };
+
+    html! {
+        <div class="float-left w-full mt-4">
+            <p class="mb-4">{"Change password:"}</p>
+            <input class="mb-2 block" type="password" placeholder="Current password" ref={current_ref} />
+            <input class="mb-2 block" type="password" placeholder="New password" ref={password_ref} />
+            <input class="mb-2 block" type="password" placeholder="Confirm new password" ref={password_confirm_ref} />
+            <button class="mb-4" onclick={on_change_password}>{"Change password"}</button>
+
+            <p class="mb-4">{"Change email:"}</p>
+            <input class="mb-2 block" type="email" placeholder="New email" ref={email_ref} />
+            <input class="mb-2 block" type="password" placeholder="Current password" ref={email_password_ref} />
+            <p class="mb-2 text-sm">{"We'll send a verification link to the new address."}</p>
+            <button class="mb-4" onclick={on_change_email}>{"Change email"}</button>
+
+            <p class="mb-4">{"Delete account:"}</p>
+            <p class="mb-2 text-sm">
+                {"Your listening history is deleted with the account. Download it first: "}
+                <a class="underline mr-2" href="/api/v1/user_album_log/export?format=csv" download="">{"CSV"}</a>
+                <a class="underline" href="/api/v1/user_album_log/export?format=json" download="">{"JSON"}</a>
+            </p>
+            <input class="mb-2 block" type="password" placeholder="Current password" ref={delete_password_ref} />
+            <button class="mb-4 text-red-400" onclick={on_delete}>{"Delete my account"}</button>
+        </div>
+    }
+}
@@ -0,0 +1,116 @@
+use crate::api::user_api::{device_label_api, device_logout_api, devices_api};
+use crate::router::Route;
+use crate::store::{set_auth_user, set_show_alert, Store};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yew_hooks::use_async;
+use yew_router::prelude::*;
+use yewdux::prelude::*;
+
+/// Devices section of the profile page
+#[function_component(DeviceList)]
+pub fn device_list() -> Html {
+    let (_, dispatch) = use_store::<Store>();
+    let navigator = use_navigator().unwrap();
+
+    let devices = use_async(async move { devices_api().await });
+
+    {
+        let devices = devices.clone();
+        use_effect_with_deps(
+            move |_| {
+                devices.run();
+                || ()
+            },
+            (),
+        );
+    }
+
+    let on_logout = {
+        let devices = devices.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |(client_id, current): (String, bool)| {
+            let devices = devices.clone();
+            let dispatch = dispatch.clone();
+            let navigator = navigator.clone();
+            spawn_local(async move {
+                match device_logout_api(&client_id).await {
+                    Ok(data) => {
+                        set_show_alert(data.msg, dispatch.clone());
+                        if current {
+                            set_auth_user(None, dispatch);
+                            navigator.push(&Route::SignIn);
+                        } else {
+                            devices.run();
+                        }
+                    }
+                    Err(e) => set_show_alert(e, dispatch),
+                }
+            });
+        })
+    };
+
+    let on_label = {
+        let devices = devices.clone();
+        Callback::from(move |(client_id, label): (String, String)| {
+            let devices = devices.clone();
+            let dispatch = dispatch.clone();
+            spawn_local(async move {
+                let json = serde_json::json!({ "label": label }).to_string();
+                match device_label_api(&client_id, &json).await {
+                    Ok(_) => devices.run(),
+                    Err(e) => set_show_alert(e, dispatch),
+                }
+            });
+        })
+    };
+
+    html! {
+        <div class="float-left w-full mt-4">
+            <p class="mb-4">{"Devices:"}</p>
+            if let Some(data) = devices.data.clone() {
+                {
+                    data.into_iter().map(|d| {
+                        let label_ref = NodeRef::default();
+                        let rename = {
+                            let client_id = d.client_id.clone();
+                            let label_ref = label_ref.clone();
+                            let on_label = on_label.clone();
+                            Callback::from(move |_: MouseEvent| {
+                                let label = label_ref.cast::<HtmlInputElement>().unwrap().value();
+                                on_label.emit((client_id.clone(), label));
+                            })
+                        };
+                        let logout = {
+                            let client_id = d.client_id.clone();
+                            let current = d.current;
+                            let on_logout = on_logout.clone();
+                            Callback::from(move |_: MouseEvent| {
+                                on_logout.emit((client_id.clone(), current));
+                            })
+                        };
+                        let name = if d.label.is_empty() { d.user_agent.clone() } else { d.label.clone() };
+                        html! {
+                            <div class="mb-4 break-all">
+                                <p>
+                                    {name}
+                                    if d.current {
+                                        <span class="ml-2 text-cyan-400">{"(this device)"}</span>
+                                    }
+                                </p>
+                                <p class="text-sm">{format!("Last seen: {}", d.last_seen)}</p>
+                                <p class="text-sm">{format!("First seen: {}", d.first_seen)}</p>
+                                <input class="mt-2 mr-2" type="text" placeholder="Label" value={d.label.clone()} ref={label_ref} />
+                                <button class="mt-2 mr-2" onclick={rename}>{"Rename"}</button>
+                                <button class="mt-2" onclick={logout}>{"Sign out"}</button>
+                            </div>
+                        }
+                    }).collect::<Html>()
+                }
+            } else {
+                <p class="mb-4">{"Loading..."}</p>
+            }
+        </div>
+    }
+}
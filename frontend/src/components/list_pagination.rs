@@ -51,3 +51,35 @@ pub fn list_pagination(props: &Props) -> Html {
         </nav>
     }
 }
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct LoadMoreProps {
+    /// Whether there is a page after the ones shown
+    pub has_more: bool,
+    pub loading: bool,
+    pub callback: Callback<()>,
+}
+
+/// Pagination for cursor paged lists, which don't know their length
+#[function_component(LoadMore)]
+pub fn load_more(props: &LoadMoreProps) -> Html {
+    if !props.has_more {
+        return html! {};
+    }
+    let callback = props.callback.clone();
+    let onclick = Callback::from(move |ev: MouseEvent| {
+        ev.prevent_default();
+        callback.emit(())
+    });
+    html! {
+        <nav>
+            <ul class="pagination">
+                <li class="page-item" onclick={onclick}>
+                    <a class="page-link" href="">
+                        {if props.loading { "Loading..." } else { "Load more" }}
+                    </a>
+                </li>
+            </ul>
+        </nav>
+    }
+}
@@ -80,6 +80,9 @@ pub fn Menu() -> Html {
                     if let Some(user) = user {
                         <li><a href="/profile" onclick={onclick.clone()}>{ user.username }</a></li>
                         <li><a href="/history" onclick={onclick.clone()}>{ "History" }</a></li>
+                        if user.role == "admin" {
+                            <li><a href="/admin" onclick={onclick.clone()}>{ "Admin" }</a></li>
+                        }
                         <li><a onclick={logout}>{"Sign out"}</a></li>
                     } else {
                         <li><a href="/sign_in" onclick={onclick.clone()}>{"Sign in"}</a></li>
@@ -1,4 +1,6 @@
+pub mod account_settings;
 pub mod alert;
+pub mod device_list;
 pub mod form_input;
 pub mod list_pagination;
 pub mod loading_button;
@@ -0,0 +1,455 @@
+use crate::api::admin_api::{admin_audit_api, admin_genres_api, admin_post_api};
+use crate::api::user_api::{album_detail_api, providers_api};
+use crate::store::{set_page_loading, set_show_alert, Store};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement};
+use yew::prelude::*;
+use yew_hooks::use_async;
+use yewdux::prelude::*;
+
+fn input_value(node: &NodeRef) -> String {
+    node.cast::<HtmlInputElement>()
+        .map(|input| input.value())
+        .unwrap_or_default()
+}
+
+fn set_input_value(node: &NodeRef, value: &str) {
+    if let Some(input) = node.cast::<HtmlInputElement>() {
+        input.set_value(value);
+    }
+}
+
+/// Genres are edited as `Genre:pri, Other Genre:sec`
+fn parse_genres(text: &str) -> serde_json::Value {
+    let genres = text
+        .split(',')
+        .map(|g| g.trim())
+        .filter(|g| !g.is_empty())
+        .map(|g| match g.rsplit_once(':') {
+            Some((genre, genre_type)) => {
+                serde_json::json!({ "genre": genre.trim(), "genre_type": genre_type.trim() })
+            }
+            None => serde_json::json!({ "genre": g, "genre_type": "pri" }),
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({ "genres": genres })
+}
+
+/// Put one provider entry into the media_url JSON being edited. A new
+/// default takes over from the provider's previous one.
+fn add_media_link(
+    media_url: &NodeRef,
+    provider: &str,
+    id: &str,
+    entry: serde_json::Value,
+) -> Result<(), String> {
+    let Some(textarea) = media_url.cast::<HtmlTextAreaElement>() else {
+        return Ok(());
+    };
+    let text = textarea.value();
+    let mut value: serde_json::Value = if text.trim().is_empty() {
+        serde_json::json!({})
+    } else {
+        serde_json::from_str(&text).map_err(|e| format!("media_url: {e}"))?
+    };
+    let entries = value
+        .as_object_mut()
+        .ok_or("media_url must be an object")?
+        .entry(provider)
+        .or_insert_with(|| serde_json::json!({}))
+        .as_object_mut()
+        .ok_or_else(|| format!("media_url.{provider} must be an object"))?;
+    if entry["default"] == serde_json::json!(true) {
+        for other in entries.values_mut() {
+            if let Some(other) = other.as_object_mut() {
+                other.remove("default");
+            }
+        }
+    }
+    entries.insert(id.to_string(), entry);
+    textarea.set_value(&serde_json::to_string_pretty(&value).unwrap_or_default());
+    Ok(())
+}
+
+#[derive(Clone, PartialEq)]
+struct AlbumForm {
+    id: NodeRef,
+    name: NodeRef,
+    artist: NodeRef,
+    cover: NodeRef,
+    media_url: NodeRef,
+    descriptors: NodeRef,
+    released: NodeRef,
+    language: NodeRef,
+    rate: NodeRef,
+    genres: NodeRef,
+    merge_into: NodeRef,
+}
+
+impl AlbumForm {
+    fn album_json(&self) -> Result<String, String> {
+        let media_url = self
+            .media_url
+            .cast::<HtmlTextAreaElement>()
+            .map(|t| t.value())
+            .unwrap_or_default();
+        let media_url: serde_json::Value = if media_url.trim().is_empty() {
+            serde_json::json!({})
+        } else {
+            serde_json::from_str(&media_url).map_err(|e| format!("media_url: {e}"))?
+        };
+        Ok(serde_json::json!({
+            "name": input_value(&self.name),
+            "artist": input_value(&self.artist),
+            "cover": input_value(&self.cover),
+            "media_url": media_url,
+        })
+        .to_string())
+    }
+
+    fn detail_json(&self) -> String {
+        serde_json::json!({
+            "descriptors": input_value(&self.descriptors),
+            "released": input_value(&self.released),
+            "language": input_value(&self.language),
+            "rate": input_value(&self.rate),
+        })
+        .to_string()
+    }
+}
+
+fn run_admin_post(path: String, json: String, dispatch: Dispatch<Store>) {
+    spawn_local(async move {
+        set_page_loading(true, dispatch.clone());
+        let res = admin_post_api(&path, &json).await;
+        set_page_loading(false, dispatch.clone());
+        match res {
+            Ok(data) => {
+                let msg = match data.data.get("id") {
+                    Some(id) => format!("{} (id {id})", data.msg),
+                    None => data.msg,
+                };
+                set_show_alert(msg, dispatch);
+            }
+            Err(e) => set_show_alert(e, dispatch),
+        }
+    });
+}
+
+#[function_component(AdminPage)]
+pub fn admin_page() -> Html {
+    let (store, dispatch) = use_store::<Store>();
+    let is_admin = store
+        .auth_user
+        .as_ref()
+        .map(|u| u.role == "admin")
+        .unwrap_or(false);
+
+    let form = AlbumForm {
+        id: use_node_ref(),
+        name: use_node_ref(),
+        artist: use_node_ref(),
+        cover: use_node_ref(),
+        media_url: use_node_ref(),
+        descriptors: use_node_ref(),
+        released: use_node_ref(),
+        language: use_node_ref(),
+        rate: use_node_ref(),
+        genres: use_node_ref(),
+        merge_into: use_node_ref(),
+    };
+    let link_provider = use_state(String::new);
+    let link_id = use_node_ref();
+    let link_default = use_node_ref();
+    let genre_name = use_node_ref();
+    let genre_key = use_node_ref();
+    let genre_parent = use_node_ref();
+
+    let genres = use_async(async move { admin_genres_api().await });
+    let audit = use_async(async move { admin_audit_api(1, 40).await });
+    let providers = use_async(async move { providers_api().await });
+    {
+        let genres = genres.clone();
+        let audit = audit.clone();
+        let providers = providers.clone();
+        use_effect_with_deps(
+            move |is_admin| {
+                if *is_admin {
+                    genres.run();
+                    audit.run();
+                    providers.run();
+                }
+                || ()
+            },
+            is_admin,
+        );
+    }
+
+    let on_load = {
+        let form = form.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| {
+            let form = form.clone();
+            let dispatch = dispatch.clone();
+            spawn_local(async move {
+                let album_id = input_value(&form.id);
+                match album_detail_api(&album_id).await {
+                    Ok(detail) => {
+                        set_input_value(&form.name, &detail.name);
+                        set_input_value(&form.artist, &detail.artist);
+                        set_input_value(&form.cover, &detail.cover);
+                        set_input_value(&form.descriptors, &detail.descriptors);
+                        set_input_value(&form.released, &detail.released);
+                        set_input_value(&form.language, &detail.language);
+                        set_input_value(&form.rate, &detail.rate);
+                        let genres = detail
+                            .genres
+                            .iter()
+                            .map(|g| format!("{}:{}", g.genre, g.genre_type))
+                            .collect::<Vec<String>>()
+                            .join(", ");
+                        set_input_value(&form.genres, &genres);
+                        if let Some(t) = form.media_url.cast::<HtmlTextAreaElement>() {
+                            t.set_value(
+                                &serde_json::to_string_pretty(&detail.media_url).unwrap_or_default(),
+                            );
+                        }
+                    }
+                    Err(e) => set_show_alert(e, dispatch),
+                }
+            });
+        })
+    };
+
+    let on_save_album = {
+        let form = form.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| match form.album_json() {
+            Ok(json) => {
+                let path = match input_value(&form.id).trim() {
+                    "" => "/album".to_string(),
+                    id => format!("/album/{id}"),
+                };
+                run_admin_post(path, json, dispatch.clone());
+            }
+            Err(e) => set_show_alert(e, dispatch.clone()),
+        })
+    };
+
+    let on_select_provider = {
+        let link_provider = link_provider.clone();
+        Callback::from(move |e: Event| {
+            if let Some(select) = e.target_dyn_into::<HtmlSelectElement>() {
+                link_provider.set(select.value());
+            }
+        })
+    };
+
+    let link_editor = match providers.data.clone() {
+        Some(providers) => {
+            let key = (*link_provider).clone();
+            let selected = providers.iter().find(|p| p.key == key).cloned();
+            let field_refs = selected
+                .as_ref()
+                .map(|p| {
+                    p.fields
+                        .iter()
+                        .map(|f| (f.clone(), NodeRef::default()))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            let on_add_link = {
+                let media_url = form.media_url.clone();
+                let link_id = link_id.clone();
+                let link_default = link_default.clone();
+                let field_refs = field_refs.clone();
+                let dispatch = dispatch.clone();
+                Callback::from(move |_: MouseEvent| {
+                    let id = input_value(&link_id).trim().to_string();
+                    if key.is_empty() || id.is_empty() {
+                        set_show_alert("Pick a provider and enter an id".to_string(), dispatch.clone());
+                        return;
+                    }
+                    let mut entry = serde_json::Map::new();
+                    let is_default = link_default
+                        .cast::<HtmlInputElement>()
+                        .map(|c| c.checked())
+                        .unwrap_or(false);
+                    if is_default {
+                        entry.insert("default".to_string(), serde_json::json!(true));
+                    }
+                    for (field, node) in &field_refs {
+                        let value = input_value(node).trim().to_string();
+                        if !value.is_empty() {
+                            entry.insert(field.clone(), serde_json::json!(value));
+                        }
+                    }
+                    let entry = serde_json::Value::Object(entry);
+                    if let Err(e) = add_media_link(&media_url, &key, &id, entry) {
+                        set_show_alert(e, dispatch.clone());
+                    }
+                })
+            };
+            html! {
+                <div>
+                    <select class="mr-2 text-black" onchange={on_select_provider}>
+                        <option value="" selected={selected.is_none()}>{"Provider"}</option>
+                        {
+                            providers.iter().map(|p| html! {
+                                <option value={p.key.clone()} selected={Some(&p.key) == selected.as_ref().map(|s| &s.key)}>{&p.title}</option>
+                            }).collect::<Html>()
+                        }
+                    </select>
+                    <input class="mr-2" type="text" placeholder="id" ref={link_id.clone()} />
+                    {
+                        field_refs.into_iter().map(|(field, node)| html! {
+                            <input class="mr-2" type="text" placeholder={field} ref={node} />
+                        }).collect::<Html>()
+                    }
+                    <label class="mr-2">
+                        <input class="mr-1" type="checkbox" ref={link_default.clone()} />{"default"}
+                    </label>
+                    <button onclick={on_add_link}>{"Add link"}</button>
+                </div>
+            }
+        }
+        None => html! {},
+    };
+
+    let on_save_detail = {
+        let form = form.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| {
+            let path = format!("/album/{}/detail", input_value(&form.id).trim());
+            run_admin_post(path, form.detail_json(), dispatch.clone());
+        })
+    };
+
+    let on_save_genres = {
+        let form = form.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| {
+            let path = format!("/album/{}/genres", input_value(&form.id).trim());
+            let json = parse_genres(&input_value(&form.genres)).to_string();
+            run_admin_post(path, json, dispatch.clone());
+        })
+    };
+
+    let on_merge = {
+        let form = form.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| {
+            let into = input_value(&form.merge_into).trim().parse::<i32>();
+            match into {
+                Ok(into) => {
+                    let path = format!("/album/{}/merge", input_value(&form.id).trim());
+                    let json = serde_json::json!({ "into": into }).to_string();
+                    run_admin_post(path, json, dispatch.clone());
+                }
+                Err(_) => set_show_alert("Merge target must be an album id".to_string(), dispatch.clone()),
+            }
+        })
+    };
+
+    let on_create_genre = {
+        let genre_name = genre_name.clone();
+        let genre_key = genre_key.clone();
+        let genre_parent = genre_parent.clone();
+        let genres = genres.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| {
+            let parent_id = input_value(&genre_parent).trim().parse::<i32>().ok();
+            let json = serde_json::json!({
+                "name": input_value(&genre_name),
+                "key_name": input_value(&genre_key),
+                "parent_id": parent_id,
+            })
+            .to_string();
+            run_admin_post("/genre".to_string(), json, dispatch.clone());
+            genres.run();
+        })
+    };
+
+    if !is_admin {
+        return html! {
+            <div class="mx-auto p-8 text-left">
+                <p class="text-4xl font-semibold">{"Admin"}</p>
+                <p class="mt-4">{"Admins only."}</p>
+            </div>
+        };
+    }
+
+    html! {
+    <div class="mx-auto overflow-hidden p-8 space-y-5 text-left">
+        <p class="text-4xl font-semibold">{"Admin"}</p>
+        <div class="space-y-2">
+            <p class="text-2xl">{"Album"}</p>
+            <div>
+                <input class="mr-2" type="text" placeholder="Album id (empty for new)" ref={form.id.clone()} />
+                <button onclick={on_load}>{"Load"}</button>
+            </div>
+            <input class="block w-full" type="text" placeholder="Name" ref={form.name.clone()} />
+            <input class="block w-full" type="text" placeholder="Artist" ref={form.artist.clone()} />
+            <input class="block w-full" type="text" placeholder="Cover" ref={form.cover.clone()} />
+            <textarea class="block w-full h-40 text-black" placeholder="media_url JSON" ref={form.media_url.clone()}></textarea>
+            {link_editor}
+            <button onclick={on_save_album}>{"Save album"}</button>
+            <input class="block w-full" type="text" placeholder="Descriptors" ref={form.descriptors.clone()} />
+            <input class="block w-full" type="text" placeholder="Released" ref={form.released.clone()} />
+            <input class="block w-full" type="text" placeholder="Language" ref={form.language.clone()} />
+            <input class="block w-full" type="text" placeholder="Rate" ref={form.rate.clone()} />
+            <button onclick={on_save_detail}>{"Save detail"}</button>
+            <input class="block w-full" type="text" placeholder="Genre:pri, Genre:sec" ref={form.genres.clone()} />
+            <button onclick={on_save_genres}>{"Save genres"}</button>
+            <div>
+                <input class="mr-2" type="text" placeholder="Merge into album id" ref={form.merge_into.clone()} />
+                <button onclick={on_merge}>{"Merge"}</button>
+            </div>
+        </div>
+        <div class="space-y-2">
+            <p class="text-2xl">{"Genres"}</p>
+            <div>
+                <input class="mr-2" type="text" placeholder="Name" ref={genre_name} />
+                <input class="mr-2" type="text" placeholder="key_name" ref={genre_key} />
+                <input class="mr-2" type="text" placeholder="Parent id" ref={genre_parent} />
+                <button onclick={on_create_genre}>{"Add genre"}</button>
+            </div>
+            if let Some(data) = genres.data.clone() {
+                <ul>
+                {
+                    data.iter().map(|g| {
+                        let depth = g.path.matches('/').count();
+                        html! {
+                            <li style={format!("padding-left: {}rem", depth * 2)}>
+                                {format!("{} · {} [{}]", g.id, g.name, g.key_name)}
+                            </li>
+                        }
+                    }).collect::<Html>()
+                }
+                </ul>
+            }
+        </div>
+        <div class="space-y-2">
+            <p class="text-2xl">{"Audit log"}</p>
+            if let Some(data) = audit.data.clone() {
+                <table class="table-auto border-spacing-px border">
+                    <tbody>
+                    {
+                        data.iter().map(|a| {
+                            html! {
+                                <tr>
+                                    <td class="border px-3">{&a.create_time}</td>
+                                    <td class="border px-3">{a.user_id}</td>
+                                    <td class="border px-3">{&a.action}</td>
+                                    <td class="border px-3">{format!("{} {}", a.target_type, a.target_id)}</td>
+                                </tr>
+                            }
+                        }).collect::<Html>()
+                    }
+                    </tbody>
+                </table>
+            }
+        </div>
+    </div>
+    }
+}
@@ -1,5 +1,5 @@
 use crate::api::types::AlbumDetail;
-use crate::api::user_api::album_detail_api;
+use crate::api::user_api::{album_detail_api, cover_url};
 use crate::components::media_link::MediaLink;
 use crate::store::{set_page_loading, Store};
 use web_sys::HtmlElement;
@@ -71,10 +71,10 @@ pub fn album(props: &DetailProps) -> Html {
             <div class="md:absolute lg:inset-y-0 lg:left-0 lg:w-2/6 md:inset-x-0 md:top-0 md:w-full" id="container_left">
                 <div class="lg:m-4">
                     <i class="lazyload-img">
-                        <img class="w-full" src={detail.cover.clone()} onload={onload} hidden=true />
+                        <img class="w-full" src={cover_url(detail.id, 600)} onload={onload} hidden=true />
                     </i>
                     <div class="media-link object-center flex">
-                        <MediaLink media_data={detail.media_url.clone()}></MediaLink>
+                        <MediaLink links={detail.links.clone()}></MediaLink>
                     </div>
                 </div>
             </div>
@@ -1,7 +1,7 @@
 #[allow(unused_imports)]
 use crate::{
     api::types::AlbumLog,
-    api::user_api::artist_album_api,
+    api::user_api::{artist_album_api, cover_url},
     app::log,
     components::form_input::FormInput,
     components::list_pagination::ListPagination,
@@ -76,7 +76,7 @@ pub fn artist_page(props: &DetailProps) -> Html {
       if let Some(data) = chart_data.data.clone() {
           <div>
               <ListPagination
-                total_count={data.total}
+                total_count={data.total.unwrap_or_default()}
                 current_page={data.page}
                 callback={callback.clone()}
               />
@@ -97,7 +97,8 @@ pub fn artist_page(props: &DetailProps) -> Html {
                             html! {
                                 <tr>
                                   <td class="border w-16">
-                                      <img class="h-16 w-16" src={l.cover} />
+                                      <img class="h-16 w-16" src={cover_url(l.id, 64)}
+                                        style={l.color.as_ref().map(|c| format!("background-color: {c}"))} />
                                   </td>
                                   <td class="border px-3">
                                     <a class="break-all text-white hover:text-cyan-600" href={url}>{l.name}</a>
@@ -111,7 +112,7 @@ pub fn artist_page(props: &DetailProps) -> Html {
                   </tbody>
               </table>
               <ListPagination
-                total_count={data.total}
+                total_count={data.total.unwrap_or_default()}
                 current_page={data.page}
                 callback={callback}
               />
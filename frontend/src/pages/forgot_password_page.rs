@@ -0,0 +1,120 @@
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::api::user_api::forgot_password_api;
+use crate::components::{form_input::FormInput, loading_button::LoadingButton};
+use crate::router::Route;
+use crate::store::{set_page_loading, set_show_alert, Store};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationErrors};
+use wasm_bindgen_futures::spawn_local;
+use yew::prelude::*;
+use yew_router::prelude::*;
+use yewdux::prelude::*;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Validate)]
+struct ForgotPasswordSchema {
+    #[validate(custom = "common::validation::email")]
+    email: String,
+}
+
+#[function_component(ForgotPasswordPage)]
+pub fn forgot_password_page() -> Html {
+    let (store, dispatch) = use_store::<Store>();
+    let form = use_state(ForgotPasswordSchema::default);
+    let validation_errors = use_state(|| Rc::new(RefCell::new(ValidationErrors::new())));
+    let email_input_ref = NodeRef::default();
+    // the address the link went to, once sent
+    let sent = use_state(|| None::<String>);
+
+    let handle_email_input = {
+        let cloned_form = form.clone();
+        Callback::from(move |value: String| {
+            let mut data = cloned_form.deref().clone();
+            data.email = value;
+            cloned_form.set(data);
+        })
+    };
+
+    let validate_input_on_blur = {
+        let cloned_form = form.clone();
+        let cloned_validation_errors = validation_errors.clone();
+        Callback::from(move |(_, value): (String, String)| {
+            let data = ForgotPasswordSchema { email: value };
+            cloned_validation_errors.set(Rc::new(RefCell::new(
+                data.validate().err().unwrap_or_default(),
+            )));
+            cloned_form.set(data);
+        })
+    };
+
+    let on_submit = {
+        let cloned_form = form;
+        let cloned_validation_errors = validation_errors.clone();
+        let cloned_sent = sent.clone();
+        let store_dispatch = dispatch;
+        Callback::from(move |event: SubmitEvent| {
+            event.prevent_default();
+            let form = cloned_form.clone();
+            let validation_errors = cloned_validation_errors.clone();
+            let sent = cloned_sent.clone();
+            let dispatch = store_dispatch.clone();
+            spawn_local(async move {
+                if let Err(e) = form.validate() {
+                    validation_errors.set(Rc::new(RefCell::new(e)));
+                    return;
+                }
+                set_page_loading(true, dispatch.clone());
+                let form_json = serde_json::to_string(form.deref()).unwrap();
+                match forgot_password_api(&form_json).await {
+                    Ok(_) => {
+                        set_page_loading(false, dispatch);
+                        sent.set(Some(form.email.clone()));
+                    }
+                    Err(e) => {
+                        set_page_loading(false, dispatch.clone());
+                        set_show_alert(e, dispatch);
+                    }
+                }
+            });
+        })
+    };
+
+    html! {
+        <section class="bg-gray-600 min-h-screen grid place-items-center">
+          <div class="w-full">
+            <h1 class="text-4xl xl:text-6xl text-center font-[600] text-gray-800 mb-4">
+              {"Forgot Password"}
+            </h1>
+            if let Some(email) = (*sent).clone() {
+                <div class="max-w-md w-10/12 mx-auto overflow-hidden shadow-2xl bg-gray-700 bg-opacity-50 rounded-2xl p-8 space-y-5">
+                  <p class="text-gray-800">
+                    {format!("If {email} is the verified address of an account, we sent it a link to choose a new password. It expires soon and works once.")}
+                  </p>
+                  <Link<Route> to={Route::SignIn} classes="text-gray-800 font-medium hover:text-blue-400">{"Back to login"}</Link<Route>>
+                </div>
+            } else {
+                <h2 class="text-lg text-center mb-4 text-gray-700">
+                  {"We'll mail you a link to reset it"}
+                </h2>
+                <form
+                  onsubmit={on_submit}
+                  class="max-w-md w-10/12 mx-auto overflow-hidden shadow-2xl bg-gray-700 bg-opacity-50 rounded-2xl p-8 space-y-5"
+                >
+                  <FormInput label="Email" name="email" input_type="email" input_ref={email_input_ref} handle_onchange={handle_email_input} errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur} />
+                  <LoadingButton
+                    loading={store.page_loading}
+                    text_color={Some("text-gray-800".to_string())}
+                  >
+                    {"Send Reset Link"}
+                  </LoadingButton>
+                  <span class="block">
+                    <Link<Route> to={Route::SignIn} classes="text-gray-800 font-medium hover:text-blue-400">{"Back to login"}</Link<Route>>
+                  </span>
+                </form>
+            }
+          </div>
+        </section>
+    }
+}
@@ -1,79 +1,151 @@
 #[allow(unused_imports)]
 use crate::{
-    api::types::AlbumLog,
-    api::user_api::album_log_api,
+    api::types::{AlbumLog, HistoryImportReport},
+    api::user_api::{album_log_api, cover_url, history_import_api},
     app::log,
     components::form_input::FormInput,
-    components::list_pagination::ListPagination,
+    components::list_pagination::LoadMore,
     console_log,
     router::Route,
     store::{set_auth_user, set_page_loading, set_show_alert, Store},
 };
 // use serde::{Deserialize, Serialize};
+use gloo::file::{futures::read_as_text, Blob};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 use yew::prelude::*;
-use yew_hooks::use_async;
-// use yewdux::prelude::*;
+use yewdux::prelude::*;
 
 #[function_component(HistoryPage)]
 pub fn history_page() -> Html {
-    // let (store, dispatch) = use_store::<Store>();
+    let (_, dispatch) = use_store::<Store>();
     // let user = store.auth_user.clone();
-    let current_page = use_state(|| 1u32);
-
-    let album_logs = {
-        let current_page = current_page.clone();
-        use_async(async move {
-            match album_log_api(*current_page, 40).await {
-                Ok(data) => Ok(data),
-                Err(e) => Err(e),
-            }
-        })
-    };
+    let logs = use_state(Vec::<AlbumLog>::new);
+    let next_cursor = use_state(|| None::<String>);
+    let loading = use_state(|| true);
+    // cursor of the page to load next, and a counter to start over with
+    let after = use_state(|| (None::<String>, 0u32));
+    let import_file = use_node_ref();
+    let import_format = use_node_ref();
+    let import_report = use_state(|| None::<HistoryImportReport>);
 
     {
-        let current_page = current_page.clone();
+        let logs = logs.clone();
+        let next_cursor = next_cursor.clone();
+        let loading = loading.clone();
         use_effect_with_deps(
-            move |_| {
-                // Reset to first page
-                current_page.set(1);
+            move |(cursor, _)| {
+                let cursor = cursor.clone();
+                loading.set(true);
+                spawn_local(async move {
+                    match album_log_api(cursor.as_deref(), 40).await {
+                        Ok(data) => {
+                            // later pages add to the list, the first one replaces it
+                            let mut rows = if cursor.is_some() { (*logs).clone() } else { vec![] };
+                            rows.extend(data.res);
+                            logs.set(rows);
+                            next_cursor.set(data.next_cursor);
+                        }
+                        Err(_) => next_cursor.set(None),
+                    }
+                    loading.set(false);
+                });
                 || ()
             },
-            (),
+            (*after).clone(),
         );
     }
 
-    {
-        let album_logs = album_logs.clone();
-        use_effect_with_deps(
-            move |_| {
-                album_logs.run();
-                || ()
-            },
-            *current_page,
-        );
-    }
+    let on_import = {
+        let import_file = import_file.clone();
+        let import_format = import_format.clone();
+        let import_report = import_report.clone();
+        let after = after.clone();
+        Callback::from(move |_: MouseEvent| {
+            let file = import_file
+                .cast::<HtmlInputElement>()
+                .and_then(|input| input.files())
+                .and_then(|files| files.get(0));
+            let Some(file) = file else {
+                set_show_alert("Choose an export file first".to_string(), dispatch.clone());
+                return;
+            };
+            let format = import_format
+                .cast::<HtmlSelectElement>()
+                .map(|select| select.value())
+                .unwrap_or_default();
+            let import_report = import_report.clone();
+            let after = after.clone();
+            let dispatch = dispatch.clone();
+            spawn_local(async move {
+                set_page_loading(true, dispatch.clone());
+                let content = read_as_text(&Blob::from(file)).await;
+                let res = match content {
+                    Ok(content) => history_import_api(&format, &content).await,
+                    Err(_) => Err("Failed to read file".to_string()),
+                };
+                set_page_loading(false, dispatch.clone());
+                match res {
+                    Ok((msg, report)) => {
+                        set_show_alert(msg, dispatch);
+                        import_report.set(Some(report));
+                        after.set((None, after.1 + 1));
+                    }
+                    Err(e) => set_show_alert(e, dispatch),
+                }
+            });
+        })
+    };
 
-    let callback = {
-        let current_page = current_page;
-        use_callback(
-            move |page, _| {
-                current_page.set(page);
-            },
-            (),
-        )
+    let load_more = {
+        let next_cursor = next_cursor.clone();
+        Callback::from(move |_| {
+            after.set(((*next_cursor).clone(), after.1));
+        })
     };
 
     html! {
     <>
     <div class="mx-auto overflow-hidden p-8 space-y-5 text-left">
       <p class="text-4xl font-semibold">{"History"}</p>
-      if let Some(data) = album_logs.data.clone() {
+      <div class="space-x-4">
+          <span>{"Download:"}</span>
+          <a class="text-white hover:text-cyan-600" href="/api/v1/user_album_log/export?format=csv" download="">{"CSV"}</a>
+          <a class="text-white hover:text-cyan-600" href="/api/v1/user_album_log/export?format=json" download="">{"JSON"}</a>
+          <a class="text-white hover:text-cyan-600" href="/api/v1/user_album_log/export?format=markdown" download="">{"Markdown"}</a>
+      </div>
+      <div class="space-y-2">
+          <p>{"Import listening history from a Last.fm CSV or Spotify JSON export:"}</p>
+          <select class="mr-2 text-black" ref={import_format}>
+              <option value="">{"Detect"}</option>
+              <option value="lastfm">{"Last.fm"}</option>
+              <option value="spotify">{"Spotify"}</option>
+          </select>
+          <input class="mr-2" type="file" accept=".csv,.json" ref={import_file} />
+          <button onclick={on_import}>{"Import"}</button>
+          if let Some(report) = (*import_report).clone() {
+              <p>{format!(
+                  "Matched {} plays on {} albums, {} plays on {} albums not found.",
+                  report.matched_plays, report.matched_count, report.unmatched_plays, report.unmatched_count
+              )}</p>
+              <p>{format!(
+                  "Added {} plays, {} were already imported.",
+                  report.imported_plays, report.already_imported_plays
+              )}</p>
+              if report.failed_plays > 0 {
+                  <p>{format!("{} plays couldn't be saved, import the file again to retry.", report.failed_plays)}</p>
+              }
+              <ul class="text-sm">
+              {
+                  report.unmatched.iter().map(|p| html! {
+                      <li>{format!("{} - {} ({})", p.artist, p.title, p.plays)}</li>
+                  }).collect::<Html>()
+              }
+              </ul>
+          }
+      </div>
+      if !*loading || !logs.is_empty() {
           <div>
-              <ListPagination
-                total_count={data.total}
-                current_page={data.page}
-                callback={callback.clone()}
-              />
               <table class="table-auto border-spacing-px border">
                   <thead>
                     <tr>
@@ -85,13 +157,13 @@ pub fn history_page() -> Html {
                   </thead>
                   <tbody>
                       {
-                          data.res.iter().map(|l| {
+                          logs.iter().map(|l| {
                               let l = l.clone();
                               let url = format!("/album/{}", l.album_id);
                               html! {
                                   <tr>
                                     <td class="border w-16">
-                                        <img class="h-16 w-16" src={l.cover} />
+                                        <img class="h-16 w-16" src={cover_url(&l.album_id, 64)} />
                                     </td>
                                     <td class="border px-3">
                                         <a class="break-all text-white hover:text-cyan-600" href={url}>{l.album_name}</a>
@@ -104,10 +176,10 @@ pub fn history_page() -> Html {
                       }
                   </tbody>
               </table>
-              <ListPagination
-                total_count={data.total}
-                current_page={data.page}
-                callback={callback}
+              <LoadMore
+                has_more={next_cursor.is_some()}
+                loading={*loading}
+                callback={load_more}
               />
           </div>
       }else {
@@ -1,14 +1,16 @@
 use yew::prelude::*;
-// use yewdux::prelude::*;
+use yewdux::prelude::*;
 use gloo::storage::LocalStorage;
 use gloo_storage::Storage;
-use web_sys::HtmlElement;
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, HtmlElement, ImageData};
 use yew_hooks::use_async;
 // use gloo_timers::callback::Timeout;
-use crate::api::user_api::today_album_api;
+use crate::api::user_api::{cover_url, today_album_api};
 use uuid::Uuid;
 // use crate::api::types::Album;
-// use crate::store::{Store, set_page_loading};
+use crate::store::Store;
+// use crate::store::set_page_loading;
 #[allow(unused_imports)]
 use crate::{app::log, console_log};
 
@@ -16,22 +18,64 @@ use crate::{app::log, console_log};
 pub struct Props {
     pub id: i32,
     pub cover: String,
+    /// Opened instead of the album page when set
+    #[prop_or_default]
+    pub link: Option<String>,
+    #[prop_or_default]
+    pub color: Option<String>,
+    #[prop_or_default]
+    pub blurhash: Option<String>,
+}
+
+/// Side of the canvas a blurhash is decoded into; css stretches it.
+const BLURHASH_SIZE: u32 = 32;
+
+fn paint_blurhash(canvas: &HtmlCanvasElement, hash: &str) -> Option<()> {
+    let pixels = blurhash::decode(hash, BLURHASH_SIZE, BLURHASH_SIZE, 1.0).ok()?;
+    let data =
+        ImageData::new_with_u8_clamped_array_and_sh(Clamped(&pixels), BLURHASH_SIZE, BLURHASH_SIZE)
+            .ok()?;
+    let ctx = canvas
+        .get_context("2d")
+        .ok()??
+        .dyn_into::<CanvasRenderingContext2d>()
+        .ok()?;
+    ctx.put_image_data(&data, 0.0, 0.0).ok()
 }
 
 #[function_component]
 fn AlbumCover(props: &Props) -> Html {
     let id = props.id;
-    let cover = props.cover.clone();
-
     let img_src = {
-        if cover.is_empty() {
+        if props.cover.is_empty() {
             "/static/default.png".to_string()
         } else {
-            format!("{cover}-cover")
+            cover_url(id, 300)
         }
     };
 
-    let detail_url = format!("/album/{id}");
+    let canvas = use_node_ref();
+    {
+        let canvas = canvas.clone();
+        use_effect_with_deps(
+            move |hash| {
+                if let (Some(el), Some(hash)) = (canvas.cast::<HtmlCanvasElement>(), hash) {
+                    paint_blurhash(&el, hash);
+                }
+                || ()
+            },
+            props.blurhash.clone(),
+        );
+    }
+    let placeholder = props
+        .color
+        .as_ref()
+        .map(|c| format!("background: {c}"));
+
+    let detail_url = match &props.link {
+        Some(link) => link.clone(),
+        None => format!("/album/{id}"),
+    };
     let onerror = Callback::from(move |_e: Event| {
         // console_log!("{:#?}", e);
         // img_src = "https://randomyourmusic.fun/static/default.png".to_string();
@@ -46,7 +90,10 @@ fn AlbumCover(props: &Props) -> Html {
     html! {
         <div class="album">
             <a href={detail_url} target="_blank">
-                <i class="lazyload-img">
+                <i class="lazyload-img" style={placeholder}>
+                    if props.blurhash.is_some() {
+                        <canvas ref={canvas} width={BLURHASH_SIZE.to_string()} height={BLURHASH_SIZE.to_string()} />
+                    }
                     <img src={img_src} onerror={onerror} onload={onload} hidden=true />
                     // <img loading="lazy" src={img_src} onerror={onerror} />
                 </i>
@@ -57,7 +104,12 @@ fn AlbumCover(props: &Props) -> Html {
 
 #[function_component(HomePage)]
 pub fn home() -> Html {
-    // let (_, dispatch) = use_store::<Store>();
+    let (store, _) = use_store::<Store>();
+    let open_direct = store
+        .auth_user
+        .as_ref()
+        .map(|u| u.open_direct)
+        .unwrap_or(false);
     let page = use_state(|| 1);
     // let album_data: yew::UseStateHandle<Vec<Album>> = use_state(||vec![]);
     // let album_data = vec![];
@@ -166,7 +218,9 @@ pub fn home() -> Html {
         if let Some(data) = items.data.clone() {
             {
                 data.iter().map(move |album| {
-                    html!{ <AlbumCover id={album.id} cover={album.cover.clone()}/> }
+                    let link = album.link.clone().filter(|_| open_direct);
+                    html!{ <AlbumCover id={album.id} cover={album.cover.clone()} link={link}
+                        color={album.color.clone()} blurhash={album.blurhash.clone()}/> }
                 }).collect::<Html>()
             }
         }
@@ -161,9 +161,9 @@ pub fn sign_in() -> Html {
                 <FormInput label="Password" name="password" input_type="password" input_ref={password_input_ref} handle_onchange={handle_password_input} errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur.clone()} />
 
                 <div class="text-right">
-                  <a href="#" class="text-gray-800 font-medium hover:text-blue-400">
+                  <Link<Route> to={Route::ForgotPassword} classes="text-gray-800 font-medium hover:text-blue-400">
                     {"Forgot Password?"}
-                  </a>
+                  </Link<Route>>
                 </div>
                 <LoadingButton
                   loading={store.page_loading}
@@ -1,9 +1,13 @@
 pub mod about_page;
+pub mod admin_page;
 pub mod album_page;
+pub mod forgot_password_page;
 pub mod genre_page;
 pub mod history_page;
 pub mod home_page;
 pub mod login_page;
 pub mod profile_page;
 pub mod register_page;
+pub mod reset_password_page;
+pub mod verify_email_page;
 pub mod artist_page;
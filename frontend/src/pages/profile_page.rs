@@ -4,8 +4,10 @@ use std::rc::Rc;
 
 #[allow(unused_imports)]
 use crate::{
-    api::user_api::{genres_api, user_config_api, user_info_api},
+    api::user_api::{genres_api, providers_api, resend_verification_api, user_config_api, user_info_api},
     app::log,
+    components::account_settings::AccountSettings,
+    components::device_list::DeviceList,
     components::form_input::FormInput,
     console_log,
     router::Route,
@@ -17,14 +19,18 @@ use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
+use yew_hooks::use_async;
 use yew_router::prelude::*;
 use yewdux::prelude::*;
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, Validate)]
 struct ProfileSchema {
+    #[validate(custom = "common::validation::genres")]
     genres: String,
-    #[validate(length(min = 1, max = 4, message = "Fresh time require 1 - 3600 min"))]
+    #[validate(custom = "common::validation::fresh_time")]
     fresh_time: String,
+    providers: Vec<String>,
+    open_direct: bool,
 }
 
 fn get_input_callback(
@@ -47,7 +53,11 @@ pub fn profile_page() -> Html {
     let user = store.auth_user.clone();
     let navigator = use_navigator().unwrap();
     let fresh_time_input_ref = NodeRef::default();
+    let open_direct_ref = use_node_ref();
     let genres = use_state(std::vec::Vec::new);
+    let providers = use_async(async move { providers_api().await });
+    // preferred provider keys, best first
+    let preferred = use_state(Vec::<String>::new);
     let validation_errors = use_state(|| Rc::new(RefCell::new(ValidationErrors::new())));
     let form = use_state(ProfileSchema::default);
     let handle_fresh_time_input = get_input_callback("fresh_time", form.clone());
@@ -90,6 +100,7 @@ pub fn profile_page() -> Html {
 
     {
         let genres = genres.clone();
+        let providers = providers.clone();
         let dispatch = dispatch.clone();
         use_effect_with_deps(
             move |_| {
@@ -113,6 +124,7 @@ pub fn profile_page() -> Html {
                     if let Ok(data) = genres_api().await {
                         genres.set(data);
                     }
+                    providers.run();
                     set_page_loading(false, dispatch);
                 });
             },
@@ -120,8 +132,66 @@ pub fn profile_page() -> Html {
         );
     }
 
+    {
+        let preferred = preferred.clone();
+        let saved = user
+            .as_ref()
+            .map(|u| u.preferred_providers.clone())
+            .unwrap_or_default();
+        use_effect_with_deps(
+            move |saved| {
+                preferred.set(
+                    saved
+                        .split(',')
+                        .filter(|k| !k.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                );
+                || ()
+            },
+            saved,
+        );
+    }
+
+    // (from, to) moves a provider in the ranking, to == None removes it
+    let on_rank = {
+        let preferred = preferred.clone();
+        Callback::from(move |(from, to): (usize, Option<usize>)| {
+            let mut list = (*preferred).clone();
+            let key = list.remove(from);
+            if let Some(to) = to {
+                list.insert(to.min(list.len()), key);
+            }
+            preferred.set(list);
+        })
+    };
+
+    let on_prefer = {
+        let preferred = preferred.clone();
+        Callback::from(move |key: String| {
+            let mut list = (*preferred).clone();
+            list.push(key);
+            preferred.set(list);
+        })
+    };
+
+    let on_resend = {
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| {
+            let dispatch = dispatch.clone();
+            spawn_local(async move {
+                match resend_verification_api().await {
+                    Ok(data) => set_show_alert(data.msg, dispatch),
+                    Err(e) => set_show_alert(e, dispatch),
+                }
+            });
+        })
+    };
+
     let on_submit = {
         let fresh_time_input_ref = fresh_time_input_ref.clone();
+        let open_direct_ref = open_direct_ref.clone();
+        let preferred = preferred.clone();
         let store_dispatch = dispatch;
         let user = user.clone();
         Callback::from(move |_: MouseEvent| {
@@ -140,15 +210,24 @@ pub fn profile_page() -> Html {
             }
             let genre_str = user_genre.join(",");
             let fresh_time_input_ref = fresh_time_input_ref.clone();
-            let fresh_time_input = fresh_time_input_ref
+            let fresh_time_value = fresh_time_input_ref
                 .cast::<HtmlInputElement>()
                 .unwrap()
-                .value()
-                .parse::<i32>()
-                .unwrap();
+                .value();
+            if let Err(e) = common::validation::fresh_time(&fresh_time_value) {
+                set_show_alert(e.message.unwrap_or_default().to_string(), store_dispatch.clone());
+                return;
+            }
+            let fresh_time_input = fresh_time_value.trim().parse::<i32>().unwrap_or_default();
+            let open_direct = open_direct_ref
+                .cast::<HtmlInputElement>()
+                .map(|c| c.checked())
+                .unwrap_or(false);
             let form = ProfileSchema {
                 genres: genre_str.clone(),
                 fresh_time: fresh_time_input.to_string(),
+                providers: (*preferred).clone(),
+                open_direct,
             };
             let dispatch = store_dispatch.clone();
             let user = user.clone();
@@ -163,6 +242,8 @@ pub fn profile_page() -> Html {
                         // update user store
                         user.as_mut().unwrap().genre_data = Some(genre_str);
                         user.as_mut().unwrap().fresh_time = fresh_time_input;
+                        user.as_mut().unwrap().preferred_providers = form.providers.join(",");
+                        user.as_mut().unwrap().open_direct = form.open_direct;
                         set_auth_user(user, dispatch.clone());
                         set_page_loading(false, dispatch.clone());
                         set_show_alert(data.msg, dispatch);
@@ -186,6 +267,12 @@ pub fn profile_page() -> Html {
                 <div class="mt-8">
                     <p class="mb-4">{format!("Name: {}", user.username)}</p>
                     <p class="mb-4">{format!("Email: {}", user.email)}</p>
+                    if !user.email_verified {
+                        <p class="mb-4">
+                            {"Your email address isn't verified yet, and a password reset can't be sent to it until it is. Check your inbox for the link. "}
+                            <button class="underline" onclick={on_resend}>{"Send it again"}</button>
+                        </p>
+                    }
                     // <p class="mb-4">{format!("Role: {}", user.role)}</p>
                 </div>
                 <div>
@@ -202,10 +289,49 @@ pub fn profile_page() -> Html {
                     }
                     }).collect::<Html>()
                 }
+                <div class="float-left w-full mt-4">
+                    <p class="mb-4">{"Preferred providers:"}</p>
+                    if let Some(all) = providers.data.clone() {
+                        {
+                            preferred.iter().enumerate().map(|(n, key)| {
+                                let title = all
+                                    .iter()
+                                    .find(|p| &p.key == key)
+                                    .map(|p| p.title.clone())
+                                    .unwrap_or_else(|| key.clone());
+                                let up = { let on_rank = on_rank.clone(); Callback::from(move |_: MouseEvent| on_rank.emit((n, Some(n.saturating_sub(1))))) };
+                                let down = { let on_rank = on_rank.clone(); Callback::from(move |_: MouseEvent| on_rank.emit((n, Some(n + 1)))) };
+                                let remove = { let on_rank = on_rank.clone(); Callback::from(move |_: MouseEvent| on_rank.emit((n, None))) };
+                                html! {
+                                    <div class="m-1">
+                                        <span class="mr-2">{format!("{}. {}", n + 1, title)}</span>
+                                        <button class="mr-1" onclick={up}>{"↑"}</button>
+                                        <button class="mr-1" onclick={down}>{"↓"}</button>
+                                        <button onclick={remove}>{"✕"}</button>
+                                    </div>
+                                }
+                            }).collect::<Html>()
+                        }
+                        <div class="m-1">
+                        {
+                            all.iter().filter(|p| !preferred.contains(&p.key)).map(|p| {
+                                let add = { let on_prefer = on_prefer.clone(); let key = p.key.clone(); Callback::from(move |_: MouseEvent| on_prefer.emit(key.clone())) };
+                                html! { <button class="mr-2 mb-2" onclick={add}>{format!("+ {}", p.title)}</button> }
+                            }).collect::<Html>()
+                        }
+                        </div>
+                    }
+                    <label class="m-1 block">
+                        <input class="mr-2" type="checkbox" checked={user.open_direct} ref={open_direct_ref} />
+                        {"Open albums on the home page directly in my provider"}
+                    </label>
+                </div>
                 <div class="float-left w-full">
                     <FormInput label="Fresh Time: [1-3600] min" name="fresh_time" input_type="" input_ref={fresh_time_input_ref} handle_onchange={handle_fresh_time_input} errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur.clone()} />
                 </div>
                 <button class="mt-4" onclick={on_submit}>{"Update"}</button>
+                <DeviceList />
+                <AccountSettings />
                 // <LoadingButton
                     // loading={store.page_loading}
                     // text_color={Some("text-gray-800".to_string())}
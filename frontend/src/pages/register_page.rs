@@ -16,17 +16,11 @@ use yewdux::prelude::*;
 
 #[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
 struct RegisterUserSchema {
-    #[validate(length(min = 1, message = "Username is required"))]
+    #[validate(custom = "common::validation::username")]
     username: String,
-    #[validate(
-        length(min = 1, message = "Email is required"),
-        email(message = "Email is invalid")
-    )]
+    #[validate(custom = "common::validation::email")]
     email: String,
-    #[validate(
-        length(min = 1, message = "Password is required"),
-        length(min = 6, message = "Password must be at least 6 characters")
-    )]
+    #[validate(custom = "common::validation::password")]
     password: String,
     #[validate(
         length(min = 1, message = "Please confirm your password"),
@@ -70,8 +64,10 @@ pub fn register_page() -> Html {
         Callback::from(move |(name, value): (String, String)| {
             let mut data = cloned_form.deref().clone();
             match name.as_str() {
+                "username" => data.username = value,
                 "email" => data.email = value,
                 "password" => data.password = value,
+                "password_confirm" => data.password_confirm = value,
                 _ => (),
             }
             cloned_form.set(data);
@@ -185,7 +181,7 @@ pub fn register_page() -> Html {
             onsubmit={on_submit}
             class="max-w-md w-10/12 mx-auto overflow-hidden shadow-2xl bg-gray-700 bg-opacity-50 rounded-2xl p-8 space-y-5"
           >
-            <FormInput label="Userame" name="name" input_ref={username_input_ref} handle_onchange={handle_username_input}  errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur.clone()} />
+            <FormInput label="Username" name="username" input_ref={username_input_ref} handle_onchange={handle_username_input}  errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur.clone()} />
             <FormInput label="Email" name="email" input_type="email" input_ref={email_input_ref} handle_onchange={handle_email_input}  errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur.clone()} />
             <FormInput label="Password" name="password" input_type="password" input_ref={password_input_ref} handle_onchange={handle_password_input}  errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur.clone()} />
             <FormInput
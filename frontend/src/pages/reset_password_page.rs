@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::api::user_api::reset_password_api;
+use crate::components::{form_input::FormInput, loading_button::LoadingButton};
+use crate::router::Route;
+use crate::store::{set_page_loading, set_show_alert, Store};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationErrors};
+use wasm_bindgen_futures::spawn_local;
+use yew::prelude::*;
+use yew_router::prelude::*;
+use yewdux::prelude::*;
+
+/// `?token=` of the links in verification and reset mails.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct TokenQuery {
+    #[serde(default)]
+    pub token: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Validate)]
+struct ResetPasswordSchema {
+    token: String,
+    #[validate(custom = "common::validation::password")]
+    password: String,
+    #[validate(
+        length(min = 1, message = "Please confirm your password"),
+        must_match(other = "password", message = "Passwords do not match")
+    )]
+    password_confirm: String,
+}
+
+fn get_input_callback(
+    name: &'static str,
+    cloned_form: UseStateHandle<ResetPasswordSchema>,
+) -> Callback<String> {
+    Callback::from(move |value| {
+        let mut data = cloned_form.deref().clone();
+        match name {
+            "password" => data.password = value,
+            "password_confirm" => data.password_confirm = value,
+            _ => (),
+        }
+        cloned_form.set(data);
+    })
+}
+
+#[function_component(ResetPasswordPage)]
+pub fn reset_password_page() -> Html {
+    let (store, dispatch) = use_store::<Store>();
+    let navigator = use_navigator().unwrap();
+    let token = use_location()
+        .and_then(|l| l.query::<TokenQuery>().ok())
+        .unwrap_or_default()
+        .token;
+    let form = use_state(|| ResetPasswordSchema {
+        token: token.clone(),
+        ..Default::default()
+    });
+    let validation_errors = use_state(|| Rc::new(RefCell::new(ValidationErrors::new())));
+    let password_input_ref = NodeRef::default();
+    let password_confirm_input_ref = NodeRef::default();
+
+    let handle_password_input = get_input_callback("password", form.clone());
+    let handle_password_confirm_input = get_input_callback("password_confirm", form.clone());
+
+    let validate_input_on_blur = {
+        let cloned_form = form.clone();
+        let cloned_validation_errors = validation_errors.clone();
+        Callback::from(move |(name, value): (String, String)| {
+            let mut data = cloned_form.deref().clone();
+            match name.as_str() {
+                "password" => data.password = value,
+                "password_confirm" => data.password_confirm = value,
+                _ => (),
+            }
+            cloned_form.set(data);
+
+            match cloned_form.validate() {
+                Ok(_) => {
+                    cloned_validation_errors
+                        .borrow_mut()
+                        .errors_mut()
+                        .remove(name.as_str());
+                }
+                Err(errors) => {
+                    cloned_validation_errors
+                        .borrow_mut()
+                        .errors_mut()
+                        .retain(|key, _| key != &name);
+                    for (field_name, error) in errors.errors() {
+                        if field_name == &name {
+                            cloned_validation_errors
+                                .borrow_mut()
+                                .errors_mut()
+                                .insert(*field_name, error.clone());
+                        }
+                    }
+                }
+            }
+        })
+    };
+
+    let on_submit = {
+        let cloned_form = form;
+        let cloned_validation_errors = validation_errors.clone();
+        let cloned_navigator = navigator;
+        let store_dispatch = dispatch;
+        Callback::from(move |event: SubmitEvent| {
+            event.prevent_default();
+            let form = cloned_form.clone();
+            let validation_errors = cloned_validation_errors.clone();
+            let navigator = cloned_navigator.clone();
+            let dispatch = store_dispatch.clone();
+            spawn_local(async move {
+                if let Err(e) = form.validate() {
+                    validation_errors.set(Rc::new(RefCell::new(e)));
+                    return;
+                }
+                set_page_loading(true, dispatch.clone());
+                let form_json = serde_json::to_string(form.deref()).unwrap();
+                match reset_password_api(&form_json).await {
+                    Ok(data) => {
+                        set_page_loading(false, dispatch.clone());
+                        set_show_alert(data.msg, dispatch);
+                        navigator.push(&Route::SignIn);
+                    }
+                    Err(e) => {
+                        set_page_loading(false, dispatch.clone());
+                        set_show_alert(e, dispatch);
+                    }
+                }
+            });
+        })
+    };
+
+    html! {
+        <section class="bg-gray-600 min-h-screen grid place-items-center">
+          <div class="w-full">
+            <h1 class="text-4xl xl:text-6xl text-center font-[600] text-gray-800 mb-4">
+              {"Reset Password"}
+            </h1>
+            if token.is_empty() {
+                <div class="max-w-md w-10/12 mx-auto overflow-hidden shadow-2xl bg-gray-700 bg-opacity-50 rounded-2xl p-8 space-y-5">
+                  <p class="text-gray-800">{"This link is incomplete. Open the one from the mail again, or ask for a new one."}</p>
+                  <Link<Route> to={Route::ForgotPassword} classes="text-gray-800 font-medium hover:text-blue-400">{"Send a new link"}</Link<Route>>
+                </div>
+            } else {
+                <h2 class="text-lg text-center mb-4 text-gray-700">
+                  {"Choose a new password"}
+                </h2>
+                <form
+                  onsubmit={on_submit}
+                  class="max-w-md w-10/12 mx-auto overflow-hidden shadow-2xl bg-gray-700 bg-opacity-50 rounded-2xl p-8 space-y-5"
+                >
+                  <FormInput label="New Password" name="password" input_type="password" input_ref={password_input_ref} handle_onchange={handle_password_input} errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur.clone()} />
+                  <FormInput label="Confirm Password" name="password_confirm" input_type="password" input_ref={password_confirm_input_ref} handle_onchange={handle_password_confirm_input} errors={&*validation_errors} handle_on_input_blur={validate_input_on_blur} />
+                  <LoadingButton
+                    loading={store.page_loading}
+                    text_color={Some("text-gray-800".to_string())}
+                  >
+                    {"Change Password"}
+                  </LoadingButton>
+                </form>
+            }
+          </div>
+        </section>
+    }
+}
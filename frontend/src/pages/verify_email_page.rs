@@ -0,0 +1,72 @@
+use crate::api::user_api::verify_email_api;
+use crate::pages::reset_password_page::TokenQuery;
+use crate::router::Route;
+use crate::store::{set_auth_user, Store};
+use yew::prelude::*;
+use yew_router::prelude::*;
+use yewdux::prelude::*;
+
+#[function_component(VerifyEmailPage)]
+pub fn verify_email_page() -> Html {
+    let (store, dispatch) = use_store::<Store>();
+    let token = use_location()
+        .and_then(|l| l.query::<TokenQuery>().ok())
+        .unwrap_or_default()
+        .token;
+    // None while the request is in flight
+    let result = use_state(|| None::<Result<String, String>>);
+
+    {
+        let result = result.clone();
+        let user = store.auth_user.clone();
+        use_effect_with_deps(
+            move |token: &String| {
+                let token = token.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if token.is_empty() {
+                        result.set(Some(Err("This link is incomplete.".to_string())));
+                        return;
+                    }
+                    let data = serde_json::json!({ "token": token }).to_string();
+                    match verify_email_api(&data).await {
+                        Ok(data) => {
+                            if let Some(mut user) = user {
+                                user.email_verified = true;
+                                set_auth_user(Some(user), dispatch);
+                            }
+                            result.set(Some(Ok(data.msg)));
+                        }
+                        Err(e) => result.set(Some(Err(e))),
+                    }
+                });
+            },
+            token,
+        );
+    }
+
+    html! {
+        <section class="bg-gray-600 min-h-screen grid place-items-center">
+          <div class="max-w-md w-10/12 mx-auto overflow-hidden shadow-2xl bg-gray-700 bg-opacity-50 rounded-2xl p-8 space-y-5">
+            <h1 class="text-4xl text-center font-[600] text-gray-800">{"Email Verification"}</h1>
+            {
+                match &*result {
+                    None => html! { <p class="text-gray-800">{"Checking your link..."}</p> },
+                    Some(Ok(_)) => html! {
+                        <>
+                          <p class="text-gray-800">{"Thanks, your email address is verified."}</p>
+                          <Link<Route> to={Route::Home} classes="text-gray-800 font-medium hover:text-blue-400">{"Go to your albums"}</Link<Route>>
+                        </>
+                    },
+                    Some(Err(e)) => html! {
+                        <>
+                          <p class="text-gray-800">{e}</p>
+                          <p class="text-gray-800">{"You can ask for a new link on your profile page."}</p>
+                          <Link<Route> to={Route::Profile} classes="text-gray-800 font-medium hover:text-blue-400">{"Profile"}</Link<Route>>
+                        </>
+                    },
+                }
+            }
+          </div>
+        </section>
+    }
+}
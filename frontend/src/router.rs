@@ -2,9 +2,10 @@ use yew::prelude::*;
 use yew_router::prelude::*;
 
 use crate::pages::{
-    about_page::AboutPage, album_page::AlbumPage, genre_page::GenrePage, history_page::HistoryPage,
+    about_page::AboutPage, admin_page::AdminPage, album_page::AlbumPage, genre_page::GenrePage, history_page::HistoryPage,
     home_page::HomePage, login_page::SignInPage, profile_page::ProfilePage,
-    register_page::RegisterPage, artist_page::ArtistPage,
+    register_page::RegisterPage, artist_page::ArtistPage, forgot_password_page::ForgotPasswordPage,
+    reset_password_page::ResetPasswordPage, verify_email_page::VerifyEmailPage,
 };
 
 #[derive(Clone, Routable, PartialEq)]
@@ -17,6 +18,12 @@ pub enum Route {
     Register,
     #[at("/sign_in")]
     SignIn,
+    #[at("/forgot_password")]
+    ForgotPassword,
+    #[at("/reset_password")]
+    ResetPassword,
+    #[at("/verify_email")]
+    VerifyEmail,
     #[at("/about")]
     About,
     #[at("/profile")]
@@ -27,6 +34,8 @@ pub enum Route {
     Artist { artist: String },
     #[at("/genre/*genre")]
     Genre { genre: String },
+    #[at("/admin")]
+    Admin,
     #[not_found]
     #[at("/404")]
     NotFound,
@@ -46,6 +55,15 @@ pub fn switch(routes: Route) -> Html {
         Route::SignIn => html! {
             <SignInPage />
         },
+        Route::ForgotPassword => html! {
+            <ForgotPasswordPage />
+        },
+        Route::ResetPassword => html! {
+            <ResetPasswordPage />
+        },
+        Route::VerifyEmail => html! {
+            <VerifyEmailPage />
+        },
         Route::About => html! {
             <AboutPage />
         },
@@ -61,6 +79,9 @@ pub fn switch(routes: Route) -> Html {
         Route::Genre { genre } => html! {
             <GenrePage genre={genre} />
         },
+        Route::Admin => html! {
+            <AdminPage />
+        },
         Route::NotFound => html! {
             <h1>{ "404" }</h1>
         },